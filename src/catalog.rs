@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+
+use chrono::prelude::*;
+use rusqlite::{self, Connection, Row};
+use rusqlite::types::ToSql;
+
+use storage::UploadedObject;
+
+/// Every archive is a row of `archives`, indexed on `(exchange, symbol, start_ts)` to answer range queries
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS archives (
+        location TEXT PRIMARY KEY,
+        key TEXT NOT NULL,
+        exchange TEXT NOT NULL,
+        symbol TEXT NOT NULL,
+        start_ts REAL NOT NULL,
+        end_ts REAL NOT NULL,
+        row_count INTEGER NOT NULL,
+        size INTEGER NOT NULL,
+        md5 TEXT NOT NULL,
+        etag TEXT NOT NULL,
+        codec TEXT,
+        uploaded TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS archives_symbol_start ON archives (exchange, symbol, start_ts);
+";
+
+/// Columns of `archives`, in the order [`CatalogEntry::from_row`] reads them
+const COLUMNS: &str = "location, key, exchange, symbol, start_ts, end_ts, row_count, size, md5, etag, codec, uploaded";
+
+/// Archive recorded in the [`Catalog`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct CatalogEntry {
+    /// Where the archive is stored, e.g. `s3://bucket/key`
+    pub location: String,
+    /// Key the archive is stored under. The same archive has the same key in every storage backend
+    pub key: String,
+    /// Exchange the data was collected from
+    pub exchange: String,
+    /// Pair symbol, as named by the exchange
+    pub symbol: String,
+    /// Timestamp of the earliest delta, in seconds
+    pub start: f64,
+    /// Timestamp of the latest delta, in seconds
+    pub end: f64,
+    /// Amount of deltas
+    pub rows: u64,
+    /// Size of the archive in bytes
+    pub size: u64,
+    /// MD5 of the archive, in hex
+    pub md5: String,
+    /// ETag of the stored object
+    pub etag: String,
+    /// Codec the archive was compressed with. Missing for files that aren't archives (e.g. CSV files)
+    pub codec: Option<String>,
+    /// When the archive was uploaded, in RFC 3339
+    pub uploaded: String,
+}
+
+impl CatalogEntry {
+    /// Describes an uploaded object from the metadata stored with it (see [`uploader::ArchiveInfo::metadata`]).
+    /// Fails if the object has no such metadata.
+    pub fn from_object(object: &UploadedObject) -> Result<CatalogEntry, Error> {
+        let field = |name: &str| object.metadata.get(name)
+            .ok_or(Error::new(ErrorKind::InvalidData, format!("{} has no \"{}\" metadata", object.location, name)));
+
+        Ok(CatalogEntry {
+            location: object.location.clone(),
+            key: object.key.clone(),
+            exchange: field("exchange")?.clone(),
+            symbol: field("symbol")?.clone(),
+            start: parse_timestamp(field("start")?)?,
+            end: parse_timestamp(field("end")?)?,
+            rows: field("rows")?.parse().map_err(|e| Error::new(ErrorKind::InvalidData, e))?,
+            size: object.size,
+            md5: object.md5.clone(),
+            etag: object.etag.clone(),
+            codec: object.metadata.get("codec").cloned(),
+            uploaded: Utc::now().to_rfc3339(),
+        })
+    }
+
+    /// Reads a row selected with [`COLUMNS`]
+    fn from_row(row: &Row) -> CatalogEntry {
+        CatalogEntry {
+            location: row.get(0),
+            key: row.get(1),
+            exchange: row.get(2),
+            symbol: row.get(3),
+            start: row.get(4),
+            end: row.get(5),
+            rows: row.get::<_, i64>(6) as u64,
+            size: row.get::<_, i64>(7) as u64,
+            md5: row.get(8),
+            etag: row.get(9),
+            codec: row.get(10),
+            uploaded: row.get(11),
+        }
+    }
+}
+
+/// Manifest of every archive we've uploaded, kept in a SQLite database. Lets us find the archives holding
+/// a given symbol and time range without listing the storage backends.
+pub struct Catalog {
+    /// SQLite database connection
+    connection: Connection,
+}
+
+impl Catalog {
+    /// Opens (or creates) the catalog at `path`
+    pub fn open(path: &str) -> Result<Catalog, Error> {
+        let connection = Connection::open(path).map_err(to_io_error)?;
+        connection.execute_batch(SCHEMA).map_err(to_io_error)?;
+
+        Ok(Catalog {
+            connection,
+        })
+    }
+
+    /// Adds `entry` to the catalog, replacing any previous entry stored at the same location
+    pub fn record(&self, entry: &CatalogEntry) -> Result<(), Error> {
+        self.connection.execute(
+            &format!("INSERT OR REPLACE INTO archives ({}) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)", COLUMNS),
+            &[&entry.location as &ToSql, &entry.key, &entry.exchange, &entry.symbol, &entry.start, &entry.end,
+                &(entry.rows as i64), &(entry.size as i64), &entry.md5, &entry.etag, &entry.codec, &entry.uploaded])
+            .map_err(to_io_error)?;
+
+        Ok(())
+    }
+
+    /// Forgets the archive stored at `location`, once it's been deleted
+    pub fn remove(&self, location: &str) -> Result<(), Error> {
+        self.connection.execute("DELETE FROM archives WHERE location = ?", &[&location.to_owned() as &ToSql])
+            .map_err(to_io_error)?;
+
+        Ok(())
+    }
+
+    /// Entry of the archive stored at `location`, if it's in the catalog
+    pub fn get(&self, location: &str) -> Result<Option<CatalogEntry>, Error> {
+        let mut stmt = self.connection.prepare(&format!("SELECT {} FROM archives WHERE location = ?", COLUMNS))
+            .map_err(to_io_error)?;
+
+        let location = location.to_owned();
+        let mut entries = stmt.query_map(&[&location as &ToSql], CatalogEntry::from_row)
+            .map_err(to_io_error)?;
+
+        entries.next().map_or(Ok(None), |entry| entry.map(Some).map_err(to_io_error))
+    }
+
+    /// Archives stored at a location starting with `prefix`, such as every archive of a storage backend
+    /// (see [`storage::Storage::location`])
+    pub fn stored_under(&self, prefix: &str) -> Result<Vec<CatalogEntry>, Error> {
+        let mut stmt = self.connection.prepare(&format!("SELECT {} FROM archives WHERE substr(location, 1, ?) = ?", COLUMNS))
+            .map_err(to_io_error)?;
+
+        let length = prefix.chars().count() as i64;
+        let prefix = prefix.to_owned();
+
+        let entries = stmt.query_map(&[&length as &ToSql, &prefix], CatalogEntry::from_row)
+            .map_err(to_io_error)?
+            .collect::<Result<Vec<CatalogEntry>, rusqlite::Error>>()
+            .map_err(to_io_error)?;
+
+        Ok(entries)
+    }
+
+    /// Archives of `symbol` on `exchange` holding any data between `start` and `end` (timestamps in seconds,
+    /// both inclusive), ordered by their earliest delta. Archives stored in several backends are listed once per backend.
+    pub fn find(&self, exchange: &str, symbol: &str, start: f64, end: f64) -> Result<Vec<CatalogEntry>, Error> {
+        let mut stmt = self.connection.prepare(&format!(
+            "SELECT {} FROM archives WHERE exchange = ? AND symbol = ? AND start_ts <= ? AND end_ts >= ? ORDER BY start_ts",
+            COLUMNS))
+            .map_err(to_io_error)?;
+
+        let exchange = exchange.to_owned();
+        let symbol = symbol.to_owned();
+
+        let entries = stmt.query_map(&[&exchange as &ToSql, &symbol, &end, &start], CatalogEntry::from_row)
+            .map_err(to_io_error)?
+            .collect::<Result<Vec<CatalogEntry>, rusqlite::Error>>()
+            .map_err(to_io_error)?;
+
+        Ok(entries)
+    }
+
+    /// Amount of archives of every exchange/symbol pair in the catalog
+    pub fn symbols(&self) -> Result<HashMap<(String, String), u64>, Error> {
+        let mut stmt = self.connection.prepare("SELECT exchange, symbol, COUNT(*) FROM archives GROUP BY exchange, symbol")
+            .map_err(to_io_error)?;
+
+        let symbols = stmt.query_map(&[], |row| ((row.get(0), row.get(1)), row.get::<_, i64>(2) as u64))
+            .map_err(to_io_error)?
+            .collect::<Result<HashMap<(String, String), u64>, rusqlite::Error>>()
+            .map_err(to_io_error)?;
+
+        Ok(symbols)
+    }
+}
+
+/// Parses an RFC 3339 date into a timestamp in seconds
+pub fn parse_timestamp(date: &str) -> Result<f64, Error> {
+    let date = DateTime::parse_from_rfc3339(date)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("\"{}\" is not an RFC 3339 date: {}", date, e)))?;
+
+    Ok(date.timestamp() as f64 + date.timestamp_subsec_nanos() as f64 * 1e-9)
+}
+
+/// SQLite errors are converted to IO errors, like everywhere else in the crate
+fn to_io_error(e: rusqlite::Error) -> Error {
+    Error::new(ErrorKind::Other, e.to_string())
+}
@@ -0,0 +1,349 @@
+use std::env;
+use std::f64;
+use std::fs::{remove_file, File};
+use std::io::{self, BufWriter, Error, ErrorKind, Write};
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use chrono::prelude::*;
+use signal_hook;
+use signal_hook::iterator::Signals;
+
+use catalog::{self, Catalog, CatalogEntry};
+use config::Config;
+use exchange::{AssetExchange, bitmex, gdax_l2};
+use listener;
+use orderbook::dtf;
+use orderbook::tectonic::TectonicPool;
+use sink;
+use sink::file::FileFormat;
+use transport::POLL_TIMEOUT;
+use uploader::{self, Archive};
+
+/// Pool used by the sinks and the subcommands that talk to TectonicDB. Connections are only opened once something needs one,
+/// so subcommands that don't touch TectonicDB work without a server.
+fn tectonic_pool(config: &Config) -> TectonicPool {
+    TectonicPool::lazy(Some(config.tectonic.host.clone()), Some(config.tectonic.port))
+}
+
+/// Returns a flag that is cleared once we receive SIGTERM or SIGINT. Receiving a second signal
+/// exits right away, for when shutting down gracefully takes longer than we're willing to wait.
+pub fn shutdown_flag() -> Result<Arc<AtomicBool>, Error> {
+    let running = Arc::new(AtomicBool::new(true));
+    let signals = Signals::new(&[signal_hook::SIGTERM, signal_hook::SIGINT])?;
+    let signal_running = running.clone();
+
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            if !signal_running.swap(false, Ordering::SeqCst) {
+                println!("Received signal {} again. Exiting without flushing", signal);
+                process::exit(1);
+            }
+
+            println!("Received signal {}. Shutting down...", signal);
+        }
+    });
+
+    Ok(running)
+}
+
+/// Sinks the listener writes to: the configured ones, followed by the arbitrage monitor if it's enabled.
+/// Replays leave the monitor out, as it's only meaningful on live books.
+fn listener_sinks(config: &Config) -> Result<Vec<Box<sink::Sink>>, Error> {
+    let mut sinks = config.sinks(&tectonic_pool(config))?;
+
+    if let Some(monitor) = config.monitor()? {
+        sinks.push(Box::new(monitor));
+    }
+
+    Ok(sinks)
+}
+
+/// Starts a thread for every configured exchange. Each one publishes its deltas on the configured transport
+/// until `running` is cleared.
+pub fn spawn_collectors(config: &Config, running: &Arc<AtomicBool>) -> Result<Vec<JoinHandle<()>>, Error> {
+    let transport = config.transport()?;
+    let wire_format = config.wire_format()?;
+
+    let mut collectors = vec![];
+
+    for exchange in &config.exchanges {
+        match exchange.name.as_str() {
+            "bitmex" => {
+                let mut settings = *bitmex::WSExchange::default_settings().unwrap();
+                settings.metadata.asset_pair = Some(exchange.symbols.clone());
+                if let Some(channels) = &exchange.channels {
+                    settings.dual_channels = channels.clone();
+                }
+                settings.transport = transport.clone();
+                settings.wire_format = wire_format;
+                settings.running = running.clone();
+
+                collectors.push(thread::spawn(move ||
+                    bitmex::WSExchange::run(Some(&settings))));
+            },
+            "gdax" => {
+                let mut settings = *gdax_l2::WSExchange::default_settings().unwrap();
+                settings.metadata.asset_pair = Some(exchange.symbols.clone());
+                if let Some(channels) = &exchange.channels {
+                    settings.single_channels = channels.clone();
+                }
+                settings.transport = transport.clone();
+                settings.wire_format = wire_format;
+                settings.running = running.clone();
+
+                collectors.push(thread::spawn(move ||
+                    gdax_l2::WSExchange::run(Some(&settings))));
+            },
+            // Unknown exchanges are rejected when the configuration is validated
+            _ => unreachable!(),
+        }
+    }
+
+    Ok(collectors)
+}
+
+/// Runs the collectors and the listener in a single process. On SIGTERM, the collectors are stopped,
+/// and once they're all done publishing, the listener drains what's left before rotating and closing every sink.
+pub fn run(config: &Config) -> Result<(), Error> {
+    let running = shutdown_flag()?;
+
+    let transport = config.transport()?;
+    let sinks = listener_sinks(config)?;
+    let period = Duration::from_secs(config.upload.period);
+
+    // Collectors close their websockets on their own once `running` is cleared. A second signal
+    // exits right away if a quiet websocket keeps one of them from noticing.
+    let collectors = spawn_collectors(config, &running)?;
+
+    // The listener gets a flag of its own, so that it only starts draining once the collectors are gone
+    let listening = Arc::new(AtomicBool::new(true));
+    let listener_running = listening.clone();
+    let collectors_running = running.clone();
+    let listener = thread::spawn(move || {
+        let listened = listener::listen_and_insert(&transport, sinks, period, listener_running);
+
+        // Nothing is left to publish to if the listener failed, so stop the collectors too
+        collectors_running.store(false, Ordering::SeqCst);
+        listened
+    });
+
+    for collector in collectors {
+        let _ = collector.join();
+    }
+
+    // A collector may have given up before we were asked to stop
+    while running.load(Ordering::SeqCst) {
+        thread::sleep(POLL_TIMEOUT);
+    }
+
+    listening.store(false, Ordering::SeqCst);
+
+    listener.join()
+        .unwrap_or_else(|_| Err(Error::new(ErrorKind::Other, "Listener panicked")))
+}
+
+/// Runs only the collectors until SIGTERM. A listener has to be running elsewhere on the same transport.
+pub fn collect(config: &Config) -> Result<(), Error> {
+    let running = shutdown_flag()?;
+
+    for collector in spawn_collectors(config, &running)? {
+        let _ = collector.join();
+    }
+
+    Ok(())
+}
+
+/// Runs only the listener until SIGTERM, writing whatever the collectors publish to the configured sinks
+pub fn ingest(config: &Config) -> Result<(), Error> {
+    let running = shutdown_flag()?;
+
+    let transport = config.transport()?;
+    let sinks = listener_sinks(config)?;
+
+    listener::listen_and_insert(&transport, sinks, Duration::from_secs(config.upload.period), running)
+}
+
+/// Compresses every database in the DTF directory `path` into its own archive, and uploads them to
+/// every storage backend regardless of whether uploads are enabled. Set `upload` to `false` to only create the archives.
+///
+/// If `path` is an archive in `upload.archive_dir` instead, it's uploaded as is under the key matching its location,
+/// with the metadata recorded next to it when it was written. This resumes uploads that failed halfway through.
+pub fn upload(config: &Config, path: &str, upload: bool) -> Result<(), Error> {
+    let archives = if Path::new(path).is_file() {
+        vec![Archive::load(path, &config.upload.archive_dir)?]
+    } else {
+        uploader::archive_databases(path, &config.upload.archive_dir, &config.codec()?, config.upload.encryption_key()?.as_ref())?
+    };
+
+    for archive in archives {
+        println!("Archived {} as {}", archive.path, archive.key);
+
+        if upload {
+            uploader::upload_archive(&archive.path, &archive.key, &archive.metadata, &config.upload)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Replays an archive into every configured sink
+pub fn replay(config: &Config, archive: &str) -> Result<(), Error> {
+    let pool = tectonic_pool(config);
+    let key = config.upload.encryption_key()?;
+
+    for mut sink in config.sinks(&pool)? {
+        sink::replay_archive(&archive.into(), config.upload.zstd_dictionary.as_ref(), key.as_ref(), sink.as_mut())?;
+        sink.close()?;
+    }
+
+    Ok(())
+}
+
+/// Lists the archives in the catalog holding `symbol` on `exchange` between `from` and `to` (RFC 3339 dates,
+/// defaulting to the beginning and end of time). Lists how many archives every symbol has if `symbol` is `None`.
+pub fn list_catalog(config: &Config, exchange: Option<&str>, symbol: Option<&str>, from: Option<&str>, to: Option<&str>) -> Result<(), Error> {
+    let catalog = Catalog::open(&config.upload.catalog)?;
+
+    let (exchange, symbol) = match (exchange, symbol) {
+        (Some(exchange), Some(symbol)) => (exchange, symbol),
+        _ => {
+            let mut symbols: Vec<_> = catalog.symbols()?.into_iter().collect();
+            symbols.sort();
+
+            for ((exchange, symbol), archives) in symbols {
+                println!("{}\t{}\t{} archives", exchange, symbol, archives);
+            }
+
+            return Ok(());
+        }
+    };
+
+    for entry in find_archives(&catalog, exchange, symbol, from, to)? {
+        println!("{}\t{}\t{}\t{} rows\t{} bytes\tmd5 {}",
+            entry.location,
+            Utc.timestamp(entry.start as i64, 0).to_rfc3339(),
+            Utc.timestamp(entry.end as i64, 0).to_rfc3339(),
+            entry.rows,
+            entry.size,
+            entry.md5);
+    }
+
+    Ok(())
+}
+
+/// Archives in `catalog` holding `symbol` on `exchange` between the RFC 3339 dates `from` and `to`.
+/// Archives stored in several backends are only listed once, preferring local copies as they don't need downloading.
+pub fn find_archives(catalog: &Catalog, exchange: &str, symbol: &str, from: Option<&str>, to: Option<&str>) -> Result<Vec<CatalogEntry>, Error> {
+    let start = from.map(catalog::parse_timestamp).unwrap_or(Ok(0.0))?;
+    let end = to.map(catalog::parse_timestamp).unwrap_or(Ok(f64::MAX))?;
+
+    let mut archives: Vec<CatalogEntry> = vec![];
+
+    for entry in catalog.find(exchange, symbol, start, end)? {
+        match archives.iter().position(|archive| archive.key == entry.key) {
+            Some(i) => if entry.location.starts_with("file://") {
+                archives[i] = entry;
+            },
+            None => archives.push(entry),
+        }
+    }
+
+    Ok(archives)
+}
+
+/// Fetches every archive in `locations` (`s3://bucket/key` URLs, `file://` URLs, or local paths) and restores its DTF files into the
+/// TectonicDB data folder `into`, defaulting to `tectonic.db_path`. With `stream`, the deltas are written to stdout
+/// in that format instead, and nothing is restored.
+///
+/// Downloads are checked against the MD5 recorded in the catalog, decrypted with the configured key if they're
+/// encrypted, and deleted once restored. Archives are processed one at a time, stopping at the first one that fails.
+pub fn restore(config: &Config, locations: &[String], into: Option<&str>, stream: Option<FileFormat>) -> Result<(), Error> {
+    let catalog = Catalog::open(&config.upload.catalog)?;
+    let key = config.upload.encryption_key()?;
+    let download_dir = env::temp_dir().join("rusty_road_restore").to_string_lossy().into_owned();
+    let db_path = into.unwrap_or(&config.tectonic.db_path);
+
+    let stdout = io::stdout();
+    let mut output = BufWriter::new(stdout.lock());
+
+    if let Some(header) = stream.and_then(|format| format.header()) {
+        output.write_all(header.as_bytes())?;
+    }
+
+    for location in locations {
+        // Progress goes to stderr, as stdout may be carrying the deltas
+        eprintln!("Fetching {}", location);
+        let (archive, md5) = uploader::fetch_archive(location, &download_dir, &config.upload)?;
+
+        let result = match (md5.as_ref(), catalog.get(location)?) {
+            (Some(md5), Some(ref entry)) if md5 != &entry.md5 =>
+                Err(Error::new(ErrorKind::InvalidData,
+                    format!("{} was corrupted in transit (MD5 {}, expected {})", location, md5, entry.md5))),
+            _ => match stream {
+                Some(format) => sink::read_archive(&archive, config.upload.zstd_dictionary.as_ref(), key.as_ref(), |exchange, batch| {
+                    for delta in batch {
+                        output.write_all(format.format_line(exchange, delta)?.as_bytes())?;
+                    }
+
+                    Ok(())
+                }),
+                None => uploader::restore_archive(&archive, db_path, config.upload.zstd_dictionary.as_ref(), key.as_ref())
+                    .map(|restored| {
+                        for dtf_file in restored {
+                            eprintln!("Restored {}", dtf_file.display());
+                        }
+                    }),
+            },
+        };
+
+        // Local archives are left alone
+        if md5.is_some() {
+            let _ = remove_file(&archive);
+        }
+
+        result?;
+    }
+
+    output.flush()
+}
+
+/// Trains a zstd dictionary of at most `max_size` bytes on the DTF files in `dir`, and writes it to `output`.
+/// Point `upload.zstd_dictionary` at it to compress archives with it.
+pub fn train_dictionary(dir: &str, output: &str, max_size: usize) -> Result<(), Error> {
+    uploader::train_dictionary(dir, output, max_size)?;
+
+    println!("Wrote dictionary to {}", output);
+
+    Ok(())
+}
+
+/// Writes every delta stored in the TectonicDB database `db_name` to `output`.
+/// The server is asked to flush first, so that the DTF file on disk is up to date.
+pub fn dump(config: &Config, db_name: &str, output: &str, format: FileFormat) -> Result<(), Error> {
+    if let Err(e) = tectonic_pool(config).get().and_then(|mut connection| connection.flush_all()) {
+        println!("Log Error: Failed to flush TectonicDB, dumping what's on disk: {}", e);
+    }
+
+    let dtf_file = Path::new(&config.tectonic.db_path).join(format!("{}.dtf", db_name));
+    let (exchange, _, deltas) = dtf::read_deltas(&dtf_file.to_string_lossy())?;
+
+    let mut writer = BufWriter::new(File::create(output)?);
+
+    if let Some(header) = format.header() {
+        writer.write_all(header.as_bytes())?;
+    }
+    for delta in &deltas {
+        writer.write_all(format.format_line(&exchange, delta)?.as_bytes())?;
+    }
+
+    writer.flush()?;
+
+    println!("Wrote {} deltas to {}", deltas.len(), output);
+
+    Ok(())
+}
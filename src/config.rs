@@ -0,0 +1,760 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{canonicalize, create_dir_all, File};
+use std::io::{Error, ErrorKind, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+use chrono::Duration;
+use redis;
+use rusoto_core::Region;
+use toml;
+use url::Url;
+
+use encryption::EncryptionKey;
+use exchange::{Asset, Exchange};
+use monitor::ArbitrageMonitor;
+use orderbook::tectonic::TectonicPool;
+use sink::{self, Sink};
+use sink::file::{FileFormat, Rotation};
+use storage::{Backend, Storage};
+use storage::local::LocalStorage;
+use storage::s3::S3Storage;
+use transport::{self, Publisher, Transport};
+use transport::wire::WireFormat;
+use transport::zeromq::ZmqPublisher;
+use uploader::Codec;
+
+/// Path the configuration is loaded from when the environment variable `CONFIG` isn't set
+pub const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+/// Everything needed to wire up the collectors, the transport, and the sinks.
+///
+/// Every section is optional. Missing sections fall back to the same defaults we used before
+/// the configuration file existed, so an empty file collects BitMEX and GDAX into TectonicDB.
+/// Environment variables (see the crate documentation) override the values read from the file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Redis server used by the Redis transports
+    pub redis: RedisConfig,
+    /// Message bus between the exchanges and the listener
+    pub transport: TransportConfig,
+    /// TectonicDB server
+    pub tectonic: TectonicConfig,
+    /// Archive rotation and upload policy
+    pub upload: UploadConfig,
+    /// Exchanges we collect from
+    pub exchanges: Vec<ExchangeConfig>,
+    /// Destinations the listener writes deltas to
+    pub sinks: Vec<SinkConfig>,
+    /// Live cross-exchange arbitrage and basis monitor
+    pub monitor: MonitorConfig,
+}
+
+/// Redis connection settings
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedisConfig {
+    /// Redis URL, including the database number
+    pub url: String,
+    /// Password sent with `AUTH` on connect. Overridden by `REDIS_AUTH`
+    pub password: Option<String>,
+}
+
+/// Transport settings. Only the settings of the selected `kind` are used
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransportConfig {
+    /// "redis", "redis-streams", or "zeromq". Overridden by `TRANSPORT`
+    pub kind: String,
+    /// "json" or "binary", the encoding publishers send deltas with. Listeners detect the encoding of every
+    /// payload they receive, so they don't need this. Overridden by `WIRE_FORMAT`
+    pub wire_format: String,
+    /// Endpoint the ZeroMQ listener binds to. Overridden by `ZMQ_ENDPOINT`
+    pub zmq_endpoint: String,
+    /// Approximate maximum length of every Redis stream. Overridden by `REDIS_STREAM_MAXLEN`
+    pub stream_max_len: usize,
+    /// Redis Streams consumer group. Overridden by `REDIS_GROUP`
+    pub group: String,
+    /// Redis Streams consumer name. Overridden by `REDIS_CONSUMER`
+    pub consumer: String,
+}
+
+/// TectonicDB server settings
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TectonicConfig {
+    /// Server host
+    pub host: String,
+    /// Server port
+    pub port: u16,
+    /// Folder the server writes its DTF files to. Overridden by `DTF_DB_PATH`
+    pub db_path: String,
+}
+
+/// Archive rotation and upload policy
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UploadConfig {
+    /// Upload archives to S3 once they're rotated. Overridden by `S3_UPLOAD`
+    pub enabled: bool,
+    /// S3 bucket. Overridden by `S3_BUCKET`
+    pub bucket: String,
+    /// S3 storage class. Overridden by `S3_STORAGE_CLASS`
+    pub storage_class: String,
+    /// AWS region, or the region name passed to `endpoint`. Overridden by `S3_REGION`
+    pub region: String,
+    /// S3-compatible server to upload to instead of AWS, such as MinIO or Ceph (e.g. `http://localhost:9000`).
+    /// Overridden by `S3_ENDPOINT`. How buckets are addressed on it isn't configurable: the S3 client decides, going by
+    /// the region
+    pub endpoint: Option<String>,
+    /// Seconds between sink rotations. Overridden by `UPLOAD_PERIOD`
+    pub period: u64,
+    /// Folder archives are written to before they're uploaded, laid out like their keys in the bucket. Each archive
+    /// has its metadata in a `.json` file next to it, and archives that failed to upload are retried on the next rotation
+    pub archive_dir: String,
+    /// SQLite database every uploaded archive is recorded in (see [`catalog::Catalog`])
+    pub catalog: String,
+    /// Archive codec, "xz" or "zstd"
+    pub codec: String,
+    /// Compression level. Defaults to 9 for both codecs
+    pub level: Option<i32>,
+    /// Amount of threads xz compresses with
+    pub compression_threads: u32,
+    /// zstd dictionary, as trained by `rusty_road train-dictionary`
+    pub zstd_dictionary: Option<String>,
+    /// File holding the base64 encoded 256-bit key archives are encrypted with. Archives aren't encrypted if missing.
+    /// `ARCHIVE_ENCRYPTION_KEY` takes precedence, and holds the key itself
+    pub encryption_key: Option<String>,
+    /// Where archives are stored once uploaded. Defaults to the S3 bucket above, keeping everything
+    pub backends: Vec<BackendConfig>,
+}
+
+/// Storage backend archives are uploaded to, along with its retention policy
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BackendConfig {
+    /// "s3" or "local". S3 backends use the bucket, storage class, and region of the upload section
+    pub kind: String,
+    /// Folder archives are copied to. Only used by "local", which also works with NFS mounts. Can't be `upload.archive_dir`,
+    /// or be inside or contain it
+    pub path: Option<String>,
+    /// Days archives are kept once stored. Kept forever if missing. Only archives recorded in the catalog are deleted
+    pub retention_days: Option<u32>,
+}
+
+/// Exchange we collect from
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExchangeConfig {
+    /// Exchange name, as found in [`exchange::get_supported_exchanges`]
+    pub name: String,
+    /// Pairs to collect, written as `["BTC", "USD"]`
+    pub symbols: Vec<[Asset; 2]>,
+    /// Websocket channels to subscribe to. Defaults to the exchange's own defaults
+    pub channels: Option<Vec<String>>,
+}
+
+/// Destination the listener writes deltas to
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SinkConfig {
+    /// "tectonic", "parquet", "csv", "ndjson", or "sqlite"
+    pub kind: String,
+    /// Folder (or database file, for "sqlite") the sink writes to. Unused by "tectonic"
+    pub path: Option<String>,
+    /// "hourly", "daily", or "size". Only used by "csv" and "ndjson". Defaults to "hourly"
+    pub rotation: Option<String>,
+    /// Size in bytes files are rotated at, when `rotation` is "size"
+    pub max_size: Option<u64>,
+}
+
+/// Cross-exchange arbitrage and basis monitor, run by the listener alongside the sinks
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MonitorConfig {
+    /// Whether the listener runs the monitor
+    pub enabled: bool,
+    /// Transport channel the spreads and basis are published on. Can't be named after an exchange
+    pub channel: String,
+    /// Edge left after fees, in basis points, from which opportunities are logged
+    pub threshold_bps: f32,
+    /// Exchanges whose symbols are perpetual swaps, which we compute the basis of against spot exchanges
+    pub perpetuals: Vec<String>,
+    /// Taker fees in basis points, by exchange. Exchanges left out are taken to charge nothing
+    pub fees: HashMap<String, f32>,
+    /// Price increment of the books kept for every exchange. Books take `8 / tick_size` MB of memory each,
+    /// so tick sizes smaller than the exchange's own should be avoided. Defaults to 0.01
+    pub tick_sizes: HashMap<String, f32>,
+    /// Endpoint the monitor binds a PUB socket to, for consumers of its events to connect to. Required with the
+    /// zeromq transport, as the listener's endpoint only takes messages in (e.g. `tcp://127.0.0.1:5557`)
+    pub zmq_endpoint: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            redis: RedisConfig::default(),
+            transport: TransportConfig::default(),
+            tectonic: TectonicConfig::default(),
+            upload: UploadConfig::default(),
+            exchanges: vec![
+                ExchangeConfig {
+                    name: "bitmex".into(),
+                    symbols: vec![[Asset::BTC, Asset::USD]],
+                    channels: None,
+                },
+                ExchangeConfig {
+                    name: "gdax".into(),
+                    symbols: vec![
+                        [Asset::BTC, Asset::USD],
+                        [Asset::ETH, Asset::USD],
+                        [Asset::LTC, Asset::USD],
+                        [Asset::BTC, Asset::USDC],
+                    ],
+                    channels: None,
+                },
+            ],
+            sinks: vec![
+                SinkConfig {
+                    kind: "tectonic".into(),
+                    path: None,
+                    rotation: None,
+                    max_size: None,
+                },
+            ],
+            monitor: MonitorConfig::default(),
+        }
+    }
+}
+
+impl Default for MonitorConfig {
+    fn default() -> MonitorConfig {
+        let mut fees = HashMap::new();
+        fees.insert("bitmex".into(), 7.5);
+        fees.insert("gdax".into(), 30.0);
+
+        let mut tick_sizes = HashMap::new();
+        tick_sizes.insert("bitmex".into(), 0.5);
+        tick_sizes.insert("gdax".into(), 0.01);
+
+        MonitorConfig {
+            enabled: false,
+            channel: "arbitrage".into(),
+            threshold_bps: 10.0,
+            perpetuals: vec!["bitmex".into()],
+            zmq_endpoint: None,
+            fees,
+            tick_sizes,
+        }
+    }
+}
+
+impl Default for RedisConfig {
+    fn default() -> RedisConfig {
+        RedisConfig {
+            url: "redis://127.0.0.1:6379/0".into(),
+            password: None,
+        }
+    }
+}
+
+impl Default for TransportConfig {
+    fn default() -> TransportConfig {
+        TransportConfig {
+            kind: "redis".into(),
+            wire_format: "json".into(),
+            zmq_endpoint: transport::DEFAULT_ZMQ_ENDPOINT.into(),
+            stream_max_len: transport::DEFAULT_STREAM_MAX_LEN,
+            group: "rusty_road".into(),
+            consumer: "listener".into(),
+        }
+    }
+}
+
+impl Default for TectonicConfig {
+    fn default() -> TectonicConfig {
+        TectonicConfig {
+            host: "127.0.0.1".into(),
+            port: 9001,
+            db_path: env::var("HOME").unwrap_or(".".into()) + "/tectonicdb/target/release/db",
+        }
+    }
+}
+
+impl Default for UploadConfig {
+    fn default() -> UploadConfig {
+        UploadConfig {
+            enabled: false,
+            bucket: "cuteq".into(),
+            storage_class: "STANDARD_IA".into(),
+            region: "us-east-1".into(),
+            endpoint: None,
+            period: 86400,
+            archive_dir: "archives".into(),
+            catalog: "catalog.sqlite".into(),
+            codec: "xz".into(),
+            level: None,
+            compression_threads: 1,
+            zstd_dictionary: None,
+            encryption_key: None,
+            backends: vec![
+                BackendConfig {
+                    kind: "s3".into(),
+                    path: None,
+                    retention_days: None,
+                },
+            ],
+        }
+    }
+}
+
+impl UploadConfig {
+    /// Region uploads are sent to. A custom endpoint becomes a [`Region::Custom`] named after `region`,
+    /// otherwise `region` has to be one of the AWS regions. The S3 client addresses buckets however it does for that region.
+    pub fn region(&self) -> Result<Region, Error> {
+        match &self.endpoint {
+            Some(endpoint) => {
+                Url::parse(endpoint)
+                    .map_err(|e| invalid(format!("upload.endpoint \"{}\" is invalid: {}", endpoint, e)))?;
+
+                Ok(Region::Custom {
+                    name: self.region.clone(),
+                    endpoint: endpoint.clone(),
+                })
+            },
+            None => Region::from_str(&self.region)
+                .map_err(|e| invalid(format!("upload.region \"{}\" is invalid: {}", self.region, e))),
+        }
+    }
+
+    /// Key archives are encrypted and decrypted with, read from `ARCHIVE_ENCRYPTION_KEY` or the `encryption_key` file.
+    /// `None` if neither is set, in which case archives are stored in the clear.
+    pub fn encryption_key(&self) -> Result<Option<EncryptionKey>, Error> {
+        if let Ok(encoded) = env::var("ARCHIVE_ENCRYPTION_KEY") {
+            return EncryptionKey::from_base64(&encoded)
+                .map(Some)
+                .map_err(|e| invalid(format!("ARCHIVE_ENCRYPTION_KEY is invalid: {}", e)));
+        }
+
+        match &self.encryption_key {
+            Some(path) => EncryptionKey::from_file(path)
+                .map(Some)
+                .map_err(|e| invalid(format!("upload.encryption_key {} is invalid: {}", path, e))),
+            None => Ok(None),
+        }
+    }
+
+    /// Opens every configured storage backend, creating the folders of local ones
+    pub fn backends(&self) -> Result<Vec<Backend>, Error> {
+        let mut backends = Vec::with_capacity(self.backends.len());
+
+        for (i, config) in self.backends.iter().enumerate() {
+            let storage: Box<Storage> = match config.kind.as_str() {
+                "s3" => Box::new(S3Storage::new(&self.bucket, &self.storage_class, self.region()?)),
+                _ => Box::new(LocalStorage::new(&backend_path(i, config)?)?),
+            };
+
+            backends.push(Backend {
+                storage,
+                retention: config.retention_days.map(|days| Duration::days(days as i64)),
+            });
+        }
+
+        Ok(backends)
+    }
+}
+
+/// Shorthand for the errors returned while loading the configuration
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Parses the environment variable `name` as a number, if it's set
+fn env_number<T: FromStr>(name: &str) -> Result<Option<T>, Error> {
+    match env::var(name) {
+        Ok(var) => var.parse::<T>()
+            .map(Some)
+            .map_err(|_| invalid(format!("{} must be a whole number, got \"{}\"", name, var))),
+        Err(_) => Ok(None),
+    }
+}
+
+impl Config {
+    /// Loads the configuration from `path`, or the file named by the environment variable `CONFIG` if `path` is `None`.
+    /// If neither is set, [`DEFAULT_CONFIG_PATH`] is used if it exists, and the defaults otherwise.
+    pub fn load(path: Option<&str>) -> Result<Config, Error> {
+        match path.map(String::from).or(env::var("CONFIG").ok()) {
+            Some(path) => Config::from_file(&path),
+            None => if Path::new(DEFAULT_CONFIG_PATH).exists() {
+                Config::from_file(DEFAULT_CONFIG_PATH)
+            } else {
+                let mut config = Config::default();
+                config.apply_env_overrides()?;
+                config.validate()?;
+
+                Ok(config)
+            },
+        }
+    }
+
+    /// Reads and validates the configuration file at `path`, applying environment variable overrides
+    pub fn from_file(path: &str) -> Result<Config, Error> {
+        let mut contents = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|e| Error::new(e.kind(), format!("Failed to read {}: {}", path, e)))?;
+
+        let mut config = toml::from_str::<Config>(&contents)
+            .map_err(|e| invalid(format!("Failed to parse {}: {}", path, e)))?;
+
+        config.apply_env_overrides()?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Parses and validates a configuration from a TOML string. Environment variables are ignored.
+    pub fn from_toml(contents: &str) -> Result<Config, Error> {
+        let config = toml::from_str::<Config>(contents)
+            .map_err(|e| invalid(e.to_string()))?;
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Overrides the values read from the file with the environment variables that are set
+    pub fn apply_env_overrides(&mut self) -> Result<(), Error> {
+        if let Ok(password) = env::var("REDIS_AUTH") {
+            self.redis.password = Some(password);
+        }
+
+        if let Ok(kind) = env::var("TRANSPORT") {
+            self.transport.kind = kind;
+        }
+        if let Ok(wire_format) = env::var("WIRE_FORMAT") {
+            self.transport.wire_format = wire_format;
+        }
+        if let Ok(endpoint) = env::var("ZMQ_ENDPOINT") {
+            self.transport.zmq_endpoint = endpoint;
+        }
+        if let Some(max_len) = env_number("REDIS_STREAM_MAXLEN")? {
+            self.transport.stream_max_len = max_len;
+        }
+        if let Ok(group) = env::var("REDIS_GROUP") {
+            self.transport.group = group;
+        }
+        if let Ok(consumer) = env::var("REDIS_CONSUMER") {
+            self.transport.consumer = consumer;
+        }
+
+        if let Ok(db_path) = env::var("DTF_DB_PATH") {
+            self.tectonic.db_path = db_path;
+        }
+
+        if let Ok(enabled) = env::var("S3_UPLOAD") {
+            self.upload.enabled = enabled == "true";
+        }
+        if let Ok(bucket) = env::var("S3_BUCKET") {
+            self.upload.bucket = bucket;
+        }
+        if let Ok(storage_class) = env::var("S3_STORAGE_CLASS") {
+            self.upload.storage_class = storage_class;
+        }
+        if let Ok(region) = env::var("S3_REGION") {
+            self.upload.region = region;
+        }
+        if let Ok(endpoint) = env::var("S3_ENDPOINT") {
+            self.upload.endpoint = Some(endpoint);
+        }
+        if let Some(period) = env_number("UPLOAD_PERIOD")? {
+            self.upload.period = period;
+        }
+
+        Ok(())
+    }
+
+    /// Checks the configuration for mistakes we would otherwise only find out about after startup
+    pub fn validate(&self) -> Result<(), Error> {
+        redis::Client::open(self.redis.url.as_str())
+            .map_err(|e| invalid(format!("redis.url \"{}\" is invalid: {}", self.redis.url, e)))?;
+
+        match self.transport.kind.as_str() {
+            "redis" | "redis-streams" | "zeromq" => (),
+            kind => return Err(invalid(format!(
+                "transport.kind \"{}\" is unknown. Expected \"redis\", \"redis-streams\", or \"zeromq\"", kind))),
+        }
+        self.wire_format()?;
+
+        if self.upload.period == 0 {
+            return Err(invalid("upload.period must be at least one second".into()));
+        }
+        if self.upload.compression_threads == 0 {
+            return Err(invalid("upload.compression_threads must be at least 1".into()));
+        }
+        self.codec()?;
+        self.upload.region()?;
+        self.upload.encryption_key()?;
+
+        if self.upload.backends.is_empty() {
+            return Err(invalid("No upload.backends configured".into()));
+        }
+
+        for (i, backend) in self.upload.backends.iter().enumerate() {
+            match backend.kind.as_str() {
+                "s3" => (),
+                "local" => {
+                    // Storing an archive would overwrite it, and the upload would then delete the only copy
+                    if dirs_overlap(&backend_path(i, backend)?, &self.upload.archive_dir)? {
+                        return Err(invalid(format!(
+                            "upload.backends[{}]: path can't be upload.archive_dir, or be inside or contain it", i)));
+                    }
+                },
+                kind => return Err(invalid(format!(
+                    "upload.backends[{}]: kind \"{}\" is unknown. Expected \"s3\" or \"local\"", i, kind))),
+            }
+
+            if backend.retention_days == Some(0) {
+                return Err(invalid(format!("upload.backends[{}]: retention_days must be at least 1", i)));
+            }
+        }
+
+        if self.exchanges.is_empty() {
+            return Err(invalid("No exchanges configured".into()));
+        }
+
+        for (i, exchange) in self.exchanges.iter().enumerate() {
+            let exch = exchange_by_name(&exchange.name)
+                .ok_or(invalid(format!(
+                    "exchanges[{}]: exchange \"{}\" is unknown. Expected \"bitmex\" or \"gdax\"", i, exchange.name)))?;
+
+            if exchange.symbols.is_empty() {
+                return Err(invalid(format!("exchanges[{}]: {} has no symbols", i, exchange.name)));
+            }
+
+            for pair in &exchange.symbols {
+                for asset in pair {
+                    if exch.normalize_asset(asset).is_none() {
+                        return Err(invalid(format!(
+                            "exchanges[{}]: {:?} isn't traded on {}", i, asset, exchange.name)));
+                    }
+                }
+            }
+
+            if let Some(channels) = &exchange.channels {
+                if channels.is_empty() {
+                    return Err(invalid(format!("exchanges[{}]: channels can't be empty", i)));
+                }
+            }
+        }
+
+        if self.sinks.is_empty() {
+            return Err(invalid("No sinks configured".into()));
+        }
+
+        for (i, sink) in self.sinks.iter().enumerate() {
+            match sink.kind.as_str() {
+                "tectonic" => (),
+                "parquet" | "sqlite" => { sink_path(i, sink)?; },
+                "csv" | "ndjson" => {
+                    sink_path(i, sink)?;
+                    sink_rotation(i, sink)?;
+                },
+                kind => return Err(invalid(format!(
+                    "sinks[{}]: kind \"{}\" is unknown. Expected \"tectonic\", \"parquet\", \"csv\", \"ndjson\", or \"sqlite\"", i, kind))),
+            }
+        }
+
+        if exchange_by_name(&self.monitor.channel).is_some() || self.monitor.channel.is_empty() {
+            return Err(invalid(format!(
+                "monitor.channel \"{}\" can't be empty or named after an exchange", self.monitor.channel)));
+        }
+
+        for name in self.monitor.perpetuals.iter()
+            .chain(self.monitor.fees.keys())
+            .chain(self.monitor.tick_sizes.keys()) {
+
+            if exchange_by_name(name).is_none() {
+                return Err(invalid(format!("monitor: exchange \"{}\" is unknown", name)));
+            }
+        }
+
+        if let Some((name, fee)) = self.monitor.fees.iter().find(|(_, fee)| **fee < 0.0) {
+            return Err(invalid(format!("monitor.fees: {} can't charge a negative fee ({})", name, fee)));
+        }
+        if let Some((name, tick_size)) = self.monitor.tick_sizes.iter().find(|(_, tick_size)| **tick_size <= 0.0) {
+            return Err(invalid(format!("monitor.tick_sizes: {} must be greater than zero, got {}", name, tick_size)));
+        }
+        if self.monitor.enabled && self.transport.kind == "zeromq" {
+            match &self.monitor.zmq_endpoint {
+                None => return Err(invalid("monitor.zmq_endpoint is required with the zeromq transport".into())),
+                Some(endpoint) if *endpoint == self.transport.zmq_endpoint => return Err(invalid(format!(
+                    "monitor.zmq_endpoint can't be transport.zmq_endpoint ({}), which the listener binds to", endpoint))),
+                Some(_) => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Encoding the exchanges publish deltas with
+    pub fn wire_format(&self) -> Result<WireFormat, Error> {
+        match self.transport.wire_format.as_str() {
+            "json" => Ok(WireFormat::Json),
+            "binary" => Ok(WireFormat::Binary),
+            format => Err(invalid(format!(
+                "transport.wire_format \"{}\" is unknown. Expected \"json\" or \"binary\"", format))),
+        }
+    }
+
+    /// Compression used for the archives
+    pub fn codec(&self) -> Result<Codec, Error> {
+        match self.upload.codec.as_str() {
+            "xz" => match self.upload.level.unwrap_or(9) {
+                level @ 0..=9 => {
+                    if self.upload.zstd_dictionary.is_some() {
+                        return Err(invalid("upload.zstd_dictionary requires upload.codec = \"zstd\"".into()));
+                    }
+
+                    Ok(Codec::Xz {
+                        level: level as u32,
+                        threads: self.upload.compression_threads,
+                    })
+                },
+                level => Err(invalid(format!("upload.level {} is out of range. xz levels go from 0 to 9", level))),
+            },
+            "zstd" => match self.upload.level.unwrap_or(9) {
+                level @ 1..=22 => {
+                    if let Some(dictionary) = &self.upload.zstd_dictionary {
+                        if !Path::new(dictionary).is_file() {
+                            return Err(invalid(format!("upload.zstd_dictionary {} doesn't exist", dictionary)));
+                        }
+                    }
+
+                    Ok(Codec::Zstd {
+                        level,
+                        dictionary: self.upload.zstd_dictionary.clone(),
+                    })
+                },
+                level => Err(invalid(format!("upload.level {} is out of range. zstd levels go from 1 to 22", level))),
+            },
+            codec => Err(invalid(format!(
+                "upload.codec \"{}\" is unknown. Expected \"xz\" or \"zstd\"", codec))),
+        }
+    }
+
+    /// Builds the configured transport
+    pub fn transport(&self) -> Result<Transport, Error> {
+        let client = redis::Client::open(self.redis.url.as_str())
+            .map_err(|e| invalid(format!("redis.url \"{}\" is invalid: {}", self.redis.url, e)))?;
+        let password = self.redis.password.clone();
+
+        Ok(match self.transport.kind.as_str() {
+            "redis-streams" => Transport::RedisStreams {
+                client,
+                password,
+                max_len: self.transport.stream_max_len,
+                group: self.transport.group.clone(),
+                consumer: self.transport.consumer.clone(),
+            },
+            "zeromq" => Transport::ZeroMQ {
+                endpoint: self.transport.zmq_endpoint.clone(),
+            },
+            _ => Transport::Redis {
+                client,
+                password,
+            },
+        })
+    }
+
+    /// Starts the arbitrage monitor if it's enabled, publishing on the configured transport. With ZeroMQ, the monitor
+    /// publishes on its own endpoint (`monitor.zmq_endpoint`) instead of the one the listener is bound to.
+    pub fn monitor(&self) -> Result<Option<ArbitrageMonitor>, Error> {
+        if !self.monitor.enabled {
+            return Ok(None);
+        }
+
+        let publisher: Box<Publisher> = match (self.transport.kind.as_str(), &self.monitor.zmq_endpoint) {
+            ("zeromq", Some(endpoint)) => Box::new(ZmqPublisher::bind(endpoint)?),
+            ("zeromq", None) => return Err(invalid("monitor.zmq_endpoint is required with the zeromq transport".into())),
+            _ => self.transport()?.publisher()?,
+        };
+
+        Ok(Some(ArbitrageMonitor::new(&self.monitor, &self.exchanges, publisher)))
+    }
+
+    /// Opens every configured sink. TectonicDB sinks check their connection out of `pool`
+    pub fn sinks(&self, pool: &TectonicPool) -> Result<Vec<Box<Sink>>, Error> {
+        let mut sinks: Vec<Box<Sink>> = Vec::with_capacity(self.sinks.len());
+
+        for (i, config) in self.sinks.iter().enumerate() {
+            sinks.push(match config.kind.as_str() {
+                "tectonic" => Box::new(sink::tectonic::TectonicSink::new(
+                    pool,
+                    &self.tectonic.db_path,
+                    self.upload.clone(),
+                    self.codec()?,
+                    self.upload.encryption_key()?)?),
+                "parquet" => Box::new(sink::parquet::ParquetSink::new(&sink_path(i, config)?)),
+                "sqlite" => Box::new(sink::sqlite::SqliteSink::new(&sink_path(i, config)?)?),
+                kind => Box::new(sink::file::FileSink::new(
+                    &sink_path(i, config)?,
+                    if kind == "csv" { FileFormat::Csv } else { FileFormat::Ndjson },
+                    sink_rotation(i, config)?,
+                    self.upload.clone())),
+            });
+        }
+
+        Ok(sinks)
+    }
+}
+
+/// Looks up a collector by the name used in the configuration file
+pub fn exchange_by_name(name: &str) -> Option<Exchange> {
+    match name {
+        "bitmex" => Some(Exchange::BitMEX),
+        "gdax" => Some(Exchange::GDAX),
+        _ => None,
+    }
+}
+
+/// Path of a sink that requires one
+fn sink_path(i: usize, sink: &SinkConfig) -> Result<String, Error> {
+    sink.path.clone()
+        .ok_or(invalid(format!("sinks[{}]: {} sinks require a path", i, sink.kind)))
+}
+
+/// Folder of a local storage backend
+fn backend_path(i: usize, backend: &BackendConfig) -> Result<String, Error> {
+    backend.path.clone()
+        .ok_or(invalid(format!("upload.backends[{}]: {} backends require a path", i, backend.kind)))
+}
+
+/// Returns `true` if the folders `a` and `b` are the same, or one is inside the other. Both are created if
+/// missing, so that they can be resolved through `.`, `..`, and symlinks.
+fn dirs_overlap(a: &str, b: &str) -> Result<bool, Error> {
+    let resolve = |dir: &str| create_dir_all(dir)
+        .and_then(|_| canonicalize(dir))
+        .map_err(|e| invalid(format!("Failed to resolve the folder {}: {}", dir, e)));
+
+    let a = resolve(a)?;
+    let b = resolve(b)?;
+
+    Ok(a.starts_with(&b) || b.starts_with(&a))
+}
+
+/// Rotation policy of a file sink
+fn sink_rotation(i: usize, sink: &SinkConfig) -> Result<Rotation, Error> {
+    match sink.rotation.as_ref().map(|r| r.as_str()).unwrap_or("hourly") {
+        "hourly" => Ok(Rotation::Hourly),
+        "daily" => Ok(Rotation::Daily),
+        "size" => match sink.max_size {
+            Some(size) if size > 0 => Ok(Rotation::Size(size)),
+            _ => Err(invalid(format!("sinks[{}]: size rotation requires a max_size greater than zero", i))),
+        },
+        rotation => Err(invalid(format!(
+            "sinks[{}]: rotation \"{}\" is unknown. Expected \"hourly\", \"daily\", or \"size\"", i, rotation))),
+    }
+}
@@ -0,0 +1,256 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Write};
+
+use base64;
+use byteorder::{BigEndian, ByteOrder};
+use ring::aead::{self, OpeningKey, SealingKey, AES_256_GCM};
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// First bytes of every encrypted archive
+pub const ENCRYPTED_MAGIC: [u8; 8] = *b"RRAEAD01";
+/// Amount of plaintext sealed at once. Archives are encrypted in chunks so that they never have to fit in memory
+pub const CHUNK_SIZE: usize = 64 * 1024;
+/// Size of the AES-GCM tag following every sealed chunk
+const TAG_LEN: usize = 16;
+/// Size of the random part of the nonce, stored after the magic. The remaining 4 bytes count chunks
+const NONCE_PREFIX_LEN: usize = 8;
+/// Size of an AES-256 key
+const KEY_LEN: usize = 32;
+
+/// 256-bit key archives are encrypted with. Never printed, so that it doesn't end up in logs.
+#[derive(Clone)]
+pub struct EncryptionKey {
+    /// Raw key
+    key: [u8; KEY_LEN],
+}
+
+impl EncryptionKey {
+    /// Decodes a base64 encoded key, such as the output of `head -c 32 /dev/urandom | base64`
+    pub fn from_base64(encoded: &str) -> Result<EncryptionKey, Error> {
+        let decoded = base64::decode(encoded.trim())
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("Encryption key isn't valid base64: {}", e)))?;
+
+        if decoded.len() != KEY_LEN {
+            return Err(Error::new(ErrorKind::InvalidData,
+                format!("Encryption key must be {} bytes long, got {}", KEY_LEN, decoded.len())));
+        }
+
+        let mut key = [0u8; KEY_LEN];
+        key.copy_from_slice(&decoded);
+
+        Ok(EncryptionKey {
+            key,
+        })
+    }
+
+    /// Reads a base64 encoded key from the file at `path`
+    pub fn from_file(path: &str) -> Result<EncryptionKey, Error> {
+        let mut encoded = String::new();
+        File::open(path)?.read_to_string(&mut encoded)?;
+
+        EncryptionKey::from_base64(&encoded)
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "EncryptionKey(..)")
+    }
+}
+
+/// Encrypts everything written to it with AES-256-GCM before passing it on to `writer`.
+///
+/// The output starts with [`ENCRYPTED_MAGIC`] and a random nonce prefix, followed by the plaintext sealed in
+/// [`CHUNK_SIZE`] chunks. Every chunk is authenticated on its own, with a nonce made of the prefix and the chunk's
+/// number, and the last chunk is marked as such, so that reordered or truncated archives fail to decrypt.
+/// Has to be finished for the output to be complete.
+pub struct EncryptingWriter<W: Write> {
+    /// Destination of the sealed chunks
+    writer: W,
+    /// Key chunks are sealed with
+    key: SealingKey,
+    /// Random part of every nonce
+    nonce_prefix: [u8; NONCE_PREFIX_LEN],
+    /// Number of the next chunk
+    chunk: u32,
+    /// Plaintext of the chunk being filled, with room for its tag
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> EncryptingWriter<W> {
+    /// Writes the header to `writer`, and encrypts everything written afterwards with `key`
+    pub fn new(mut writer: W, key: &EncryptionKey) -> Result<EncryptingWriter<W>, Error> {
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+        SystemRandom::new().fill(&mut nonce_prefix)
+            .map_err(|_| Error::new(ErrorKind::Other, "Failed to generate a nonce"))?;
+
+        writer.write_all(&ENCRYPTED_MAGIC)?;
+        writer.write_all(&nonce_prefix)?;
+
+        Ok(EncryptingWriter {
+            writer,
+            key: SealingKey::new(&AES_256_GCM, &key.key)
+                .map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid encryption key"))?,
+            nonce_prefix,
+            chunk: 0,
+            buffer: Vec::with_capacity(CHUNK_SIZE + TAG_LEN),
+        })
+    }
+
+    /// Seals the buffered plaintext and writes it out
+    fn seal_chunk(&mut self, last: bool) -> Result<(), Error> {
+        let plaintext_len = self.buffer.len();
+        self.buffer.resize(plaintext_len + TAG_LEN, 0);
+
+        let nonce = chunk_nonce(&self.nonce_prefix, self.chunk);
+        let sealed_len = aead::seal_in_place(&self.key, &nonce, &[last as u8], &mut self.buffer, TAG_LEN)
+            .map_err(|_| Error::new(ErrorKind::Other, "Failed to encrypt archive"))?;
+
+        self.writer.write_all(&self.buffer[..sealed_len])?;
+        self.buffer.clear();
+
+        self.chunk = self.chunk.checked_add(1)
+            .ok_or(Error::new(ErrorKind::InvalidInput, "Archive is too large to encrypt"))?;
+
+        Ok(())
+    }
+
+    /// Seals the last chunk, returning the inner writer
+    pub fn finish(mut self) -> Result<W, Error> {
+        // Readers take full chunks to be followed by more, so a full last chunk is followed by an empty one
+        if self.buffer.len() == CHUNK_SIZE {
+            self.seal_chunk(false)?;
+        }
+        self.seal_chunk(true)?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for EncryptingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        // Full chunks are only sealed once more data comes in, as the last chunk has to be marked as such
+        if self.buffer.len() == CHUNK_SIZE {
+            self.seal_chunk(false)?;
+        }
+
+        let written = buf.len().min(CHUNK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..written]);
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()
+    }
+}
+
+/// Decrypts the output of an [`EncryptingWriter`] as it's read
+pub struct DecryptingReader<R: Read> {
+    /// Source of the sealed chunks
+    reader: R,
+    /// Key chunks are opened with
+    key: OpeningKey,
+    /// Random part of every nonce, read from the header
+    nonce_prefix: [u8; NONCE_PREFIX_LEN],
+    /// Number of the next chunk
+    chunk: u32,
+    /// Sealed chunk, opened in place
+    buffer: Vec<u8>,
+    /// Plaintext left in `buffer`, as a range
+    plaintext: (usize, usize),
+    /// Whether the last chunk was opened
+    finished: bool,
+}
+
+impl<R: Read> DecryptingReader<R> {
+    /// Reads the header from `reader`, and decrypts everything read afterwards with `key`
+    pub fn new(mut reader: R, key: &EncryptionKey) -> Result<DecryptingReader<R>, Error> {
+        let mut magic = [0u8; 8];
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+        reader.read_exact(&mut magic)?;
+        reader.read_exact(&mut nonce_prefix)?;
+
+        if magic != ENCRYPTED_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "Not an encrypted archive"));
+        }
+
+        Ok(DecryptingReader {
+            reader,
+            key: OpeningKey::new(&AES_256_GCM, &key.key)
+                .map_err(|_| Error::new(ErrorKind::InvalidInput, "Invalid encryption key"))?,
+            nonce_prefix,
+            chunk: 0,
+            buffer: vec![0u8; CHUNK_SIZE + TAG_LEN],
+            plaintext: (0, 0),
+            finished: false,
+        })
+    }
+
+    /// Reads and opens the next chunk. Only a chunk shorter than [`CHUNK_SIZE`] can be the last one, and nothing
+    /// may follow it
+    fn open_chunk(&mut self) -> Result<(), Error> {
+        self.buffer.resize(CHUNK_SIZE + TAG_LEN, 0);
+
+        let mut sealed_len = 0;
+        while sealed_len < self.buffer.len() {
+            match self.reader.read(&mut self.buffer[sealed_len..])? {
+                0 => break,
+                read => sealed_len += read,
+            }
+        }
+
+        if sealed_len < TAG_LEN {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "Encrypted archive is truncated"));
+        }
+
+        let last = sealed_len < CHUNK_SIZE + TAG_LEN;
+        let nonce = chunk_nonce(&self.nonce_prefix, self.chunk);
+        let plaintext_len = aead::open_in_place(&self.key, &nonce, &[last as u8], 0, &mut self.buffer[..sealed_len])
+            .map_err(|_| Error::new(ErrorKind::InvalidData,
+                "Failed to decrypt archive. Either the key is wrong, or the archive was tampered with or truncated"))?
+            .len();
+
+        // Whatever follows the last chunk wasn't written by us
+        if last && self.reader.read(&mut [0u8; 1])? > 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "Encrypted archive has data after its last chunk"));
+        }
+
+        self.plaintext = (0, plaintext_len);
+        self.finished = last;
+        self.chunk = self.chunk.checked_add(1)
+            .ok_or(Error::new(ErrorKind::InvalidData, "Encrypted archive has too many chunks"))?;
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecryptingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        while self.plaintext.0 == self.plaintext.1 {
+            if self.finished {
+                return Ok(0);
+            }
+
+            self.open_chunk()?;
+        }
+
+        let (start, end) = self.plaintext;
+        let read = buf.len().min(end - start);
+        buf[..read].copy_from_slice(&self.buffer[start..start + read]);
+        self.plaintext.0 += read;
+
+        Ok(read)
+    }
+}
+
+/// Nonce of chunk number `chunk`: the random prefix followed by the chunk number, big endian
+fn chunk_nonce(prefix: &[u8; NONCE_PREFIX_LEN], chunk: u32) -> [u8; NONCE_PREFIX_LEN + 4] {
+    let mut nonce = [0u8; NONCE_PREFIX_LEN + 4];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    BigEndian::write_u32(&mut nonce[NONCE_PREFIX_LEN..], chunk);
+
+    nonce
+}
@@ -1,10 +1,12 @@
 use std::collections::HashMap;
+use std::io;
 use std::thread;
 use std::ops::Deref;
 use std::sync::{Arc, Mutex, mpsc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use chrono::prelude::*;
-use redis::{self, Commands};
+use redis;
 use reqwest;
 use serde_json;
 use ws;
@@ -13,6 +15,8 @@ use ws::{Error, Handler, Handshake, Message, Sender};
 
 use exchange::{self, Asset, AssetExchange, Exchange};
 use orderbook;
+use transport::{Publisher, Transport};
+use transport::wire::{self, WireFormat};
 
 const EXPIRE: Token = Token(1);
 
@@ -39,13 +43,12 @@ pub struct WSExchange {
     /// Allows us to calculate the price of a given asset in combination with [`asset_indexes`]
     pub asset_tick_size: HashMap<String, f32>,
 
-    /// TectonicDB connection
-    pub tectonic: orderbook::tectonic::TectonicConnection,
-
-    /// Redis client (before connection)
-    pub r: redis::Client,
-    /// Redis password: If this is present, we will send an AUTH message to the server on connect
-    pub r_password: Option<String>,
+    /// Message bus we publish deltas on
+    pub transport: Transport,
+    /// Encoding of the deltas we publish
+    pub wire_format: WireFormat,
+    /// Cleared to stop collecting. The websocket is closed on the next message instead of reconnecting
+    pub running: Arc<AtomicBool>,
 
     /// Thread channel. We will use this to communicate with a secondary connection
     /// opened after a 15 minute count to ensure a stable connection. This channel is
@@ -78,10 +81,12 @@ pub struct WSExchangeSender {
     /// Allows us to calculate the price of a given asset in combination with [`asset_indexes`]
     asset_tick_size: Arc<RwLock<HashMap<String, f32>>>,
 
-    /// TectonicDB connection
-    tectonic: orderbook::tectonic::TectonicConnection,
-    /// Redis client (used to send deltas as PUBSUB)
-    r: Arc<Mutex<redis::Connection>>,
+    /// Publisher (used to send deltas to the listener)
+    publisher: Arc<Mutex<Box<Publisher>>>,
+    /// Encoding of the deltas we publish
+    wire_format: WireFormat,
+    /// Cleared to stop collecting
+    running: Arc<AtomicBool>,
 
     /// Websocket sender
     out: Sender,
@@ -158,9 +163,12 @@ impl AssetExchange for WSExchange {
             asset_indexes: HashMap::new(),
             asset_tick_size: HashMap::new(),
 
-            tectonic: orderbook::tectonic::TectonicConnection::new(None, None).expect("Unable to connect to TectonicDB"),
-            r: redis::Client::open("redis://localhost").unwrap(),
-            r_password: None,
+            transport: Transport::Redis {
+                client: redis::Client::open("redis://localhost").unwrap(),
+                password: None,
+            },
+            wire_format: WireFormat::Json,
+            running: Arc::new(AtomicBool::new(true)),
 
             channel: None,
         };
@@ -168,21 +176,8 @@ impl AssetExchange for WSExchange {
         Ok(Box::new(settings))
     }
 
-    fn init_redis(&mut self) -> Result<redis::Connection, redis::RedisError> {
-        let redis_connection = self.r.clone()
-            .get_connection()
-            .unwrap();
-
-        // Send an auth message if we have a password
-        match &self.r_password {
-            Some(password) => {
-                redis::cmd("AUTH").arg(password)
-                    .execute(&redis_connection);
-            },
-            None => (),
-        };
-
-        Ok(redis_connection)
+    fn init_publisher(&mut self) -> Result<Box<Publisher>, io::Error> {
+        self.transport.publisher()
     }
 
     fn run(settings: Option<&Self>) {
@@ -201,8 +196,9 @@ impl AssetExchange for WSExchange {
             asset_indexes: Arc::new(RwLock::new(settings.asset_indexes.clone())),
             asset_tick_size: Arc::new(RwLock::new(settings.asset_tick_size.clone())),
 
-            tectonic: settings.tectonic.clone(),
-            r: Arc::new(Mutex::new(settings.init_redis().expect("Failed to connect to Redis server."))),
+            publisher: Arc::new(Mutex::new(settings.init_publisher().expect("Failed to create publisher."))),
+            wire_format: settings.wire_format,
+            running: settings.running.clone(),
 
             out,
         }).unwrap();
@@ -256,17 +252,6 @@ impl Handler for WSExchangeSender {
                 .write()
                 .unwrap()
                 .insert(asset.symbol.clone(), asset.tick_size);
-
-            if !self.tectonic.exists(format!("bitmex_{}", asset.symbol.clone()))? && 
-                asset.symbol.clone() == exchange::get_asset_pair(
-                    &[exchange::Asset::BTC, exchange::Asset::USD], 
-                    exchange::Exchange::BitMEX)
-                {
-
-                // Create tectonic database if it doesn't exist yet. This avoids many issues
-                // relating to inserting to a non-existant database.
-                let _ = self.tectonic.create(format!("bitmex_{}", asset.symbol.clone()));
-            }
         }
 
         // Send our constructed message to the server
@@ -274,7 +259,13 @@ impl Handler for WSExchangeSender {
     }
 
     fn on_message(&mut self, msg: Message) -> Result<(), Error> {
-        let redis_ref = self.r.clone();
+        // We've been asked to stop. Close the socket instead of publishing anything else
+        if !self.running.load(Ordering::SeqCst) {
+            return self.out.close(ws::CloseCode::Away);
+        }
+
+        let publisher_ref = self.publisher.clone();
+        let wire_format = self.wire_format;
         let asset_tick_ref = self.asset_tick_size.clone();
         let asset_index_ref = self.asset_indexes.clone();
 
@@ -336,11 +327,11 @@ impl Handler for WSExchangeSender {
                     }
 
                     // Lock the connection until we are able to aquire it
-                    let _ = redis_ref.as_ref()
+                    let _ = publisher_ref.as_ref()
                         .lock()
                         .unwrap()
-                        .publish::<&str, &str, u8>("bitmex", &serde_json::to_string(&deltas).unwrap())
-                        .expect("Failed to publish message to redis PUBSUB");
+                        .publish("bitmex", &wire::encode(&deltas, wire_format).unwrap())
+                        .expect("Failed to publish message");
                 },
 
                 Err(e) => {
@@ -354,6 +345,11 @@ impl Handler for WSExchangeSender {
     }
 
     fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        if !self.running.load(Ordering::SeqCst) {
+            println!("BitMEX Socket closed.");
+            return;
+        }
+
         // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
         // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
         println!("BitMEX Socket is closing. Opening a new connection...");
@@ -369,14 +365,19 @@ impl Handler for WSExchangeSender {
             asset_indexes: self.asset_indexes.clone(),
             asset_tick_size: self.asset_tick_size.clone(),
 
-            tectonic: self.tectonic.clone(),
-            r: self.r.clone(),
+            publisher: self.publisher.clone(),
+            wire_format: self.wire_format,
+            running: self.running.clone(),
 
             out,
         }).unwrap();
     }
 
     fn on_timeout(&mut self, _: Token) -> Result<(), ws::Error> {
+        if !self.running.load(Ordering::SeqCst) {
+            return self.out.close(ws::CloseCode::Away);
+        }
+
         // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
         // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
         println!("BitMEX Socket timed out (5s of inactivity). Opening a new connection...");
@@ -392,8 +393,9 @@ impl Handler for WSExchangeSender {
             asset_indexes: self.asset_indexes.clone(),
             asset_tick_size: self.asset_tick_size.clone(),
 
-            tectonic: self.tectonic.clone(),
-            r: self.r.clone(),
+            publisher: self.publisher.clone(),
+            wire_format: self.wire_format,
+            running: self.running.clone(),
 
             out,
         }).unwrap();
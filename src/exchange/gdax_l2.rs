@@ -1,9 +1,11 @@
+use std::io;
 use std::thread;
 use std::ops::Deref;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use chrono::prelude::*;
-use redis::{self, Commands};
+use redis;
 use serde_json;
 use ws;
 use ws::util::Token;
@@ -11,6 +13,8 @@ use ws::{Error, Handler, Handshake, Message, Sender};
 
 use exchange::{self, Asset, AssetExchange, Exchange};
 use orderbook;
+use transport::{Publisher, Transport};
+use transport::wire::{self, WireFormat};
 
 const EXPIRE: Token = Token(1);
 
@@ -30,13 +34,12 @@ pub struct WSExchange {
     /// Channel name with no argument we want to subscribe to
     pub single_channels: Vec<String>,
 
-    /// TectonicDB connection
-    pub tectonic: orderbook::tectonic::TectonicConnection,
-
-    /// Redis client (before connection)
-    pub r: redis::Client,
-    /// Redis password: If this is present, we will send an AUTH message to the server on connect
-    pub r_password: Option<String>,
+    /// Message bus we publish deltas on
+    pub transport: Transport,
+    /// Encoding of the deltas we publish
+    pub wire_format: WireFormat,
+    /// Cleared to stop collecting. The websocket is closed on the next message instead of reconnecting
+    pub running: Arc<AtomicBool>,
 }
 
 /// Create two identical structs and transfer the data over when we start the websocket.
@@ -53,10 +56,12 @@ pub struct WSExchangeSender {
     /// Channel name with no argument we want to subscribe to
     single_channels: Vec<String>,
 
-    /// TectonicDB connection
-    tectonic: orderbook::tectonic::TectonicConnection,
-    /// Redis client (used to send deltas as PUBSUB)
-    r: Arc<Mutex<redis::Connection>>,
+    /// Publisher (used to send deltas to the listener)
+    publisher: Arc<Mutex<Box<Publisher>>>,
+    /// Encoding of the deltas we publish
+    wire_format: WireFormat,
+    /// Cleared to stop collecting
+    running: Arc<AtomicBool>,
 
     /// Websocket sender
     out: Sender,
@@ -98,27 +103,17 @@ impl AssetExchange for WSExchange {
                 "level2".into(), 
                 "matches".into()],
 
-            tectonic: orderbook::tectonic::TectonicConnection::new(None, None).expect("Unable to connect to TectonicDB"),
-            r: redis::Client::open("redis://localhost").unwrap(),
-            r_password: None,
+            transport: Transport::Redis {
+                client: redis::Client::open("redis://localhost").unwrap(),
+                password: None,
+            },
+            wire_format: WireFormat::Json,
+            running: Arc::new(AtomicBool::new(true)),
         }))
     }
 
-    fn init_redis(&mut self) -> Result<redis::Connection, redis::RedisError> {
-        let redis_connection = self.r.clone()
-            .get_connection()
-            .unwrap();
-
-        // Send an auth message if we have a password
-        match &self.r_password {
-            Some(password) => {
-                redis::cmd("AUTH").arg(password)
-                    .execute(&redis_connection);
-            },
-            None => (),
-        };
-
-        Ok(redis_connection)
+    fn init_publisher(&mut self) -> Result<Box<Publisher>, io::Error> {
+        self.transport.publisher()
     }
 
     fn run(settings: Option<&Self>) {
@@ -133,8 +128,9 @@ impl AssetExchange for WSExchange {
 
             single_channels: settings.single_channels.clone(),
             
-            tectonic: settings.tectonic.clone(),
-            r: Arc::new(Mutex::new(settings.init_redis().expect("Failed to connect to Redis server."))),
+            publisher: Arc::new(Mutex::new(settings.init_publisher().expect("Failed to create publisher."))),
+            wire_format: settings.wire_format,
+            running: settings.running.clone(),
 
             out,
         }).unwrap();
@@ -198,14 +194,6 @@ impl Handler for WSExchangeSender {
         // Issue: currently, this reruns every five seconds. Comment out while we fix.
         //self.out.timeout(5_000, EXPIRE).unwrap();
 
-        for pair in self.metadata.asset_pair.as_ref().expect("No asset pairs passed to GDAX structure") {
-            let db_name = format!("{}_{}", self.metadata.exchange.deref(), exchange::get_asset_pair(pair, Exchange::GDAX));
-
-            if !self.tectonic.exists(db_name.clone())? {
-                let _ = self.tectonic.create(db_name);
-            }
-        }
-
         let mut msg = SubscribeMessage {
             type_: "subscribe".into(),
             product_ids: vec![],
@@ -224,16 +212,16 @@ impl Handler for WSExchangeSender {
 
         println!("Sending message {}", serde_json::to_string(&msg).unwrap());
         self.out.send(serde_json::to_string(&msg).unwrap())
-        /*if !self.tectonic.exists(format!("bitmex_{}", asset.symbol.clone()))? {
-         *        // Create tectonic database if it doesn't exist yet. This avoids many issues
-         *        // relating to inserting to a non-existant database.
-         *        let _ = self.tectonic.create(format!("bitmex_{}", asset.symbol.clone()));
-         *}
-         */
     }
 
     fn on_message(&mut self, msg: Message) -> Result<(), Error> {
-        let redis_ref = self.r.clone();
+        // We've been asked to stop. Close the socket instead of publishing anything else
+        if !self.running.load(Ordering::SeqCst) {
+            return self.out.close(ws::CloseCode::Away);
+        }
+
+        let publisher_ref = self.publisher.clone();
+        let wire_format = self.wire_format;
         let exchange = self.metadata.exchange.clone();
 
         thread::spawn(move || {
@@ -269,19 +257,19 @@ impl Handler for WSExchangeSender {
                         }
 
                         // Lock the connection until we are able to aquire it
-                        let _ = redis_ref.as_ref()
+                        let _ = publisher_ref.as_ref()
                             .lock()
                             .unwrap()
-                            .publish::<&str, &str, u8>(exchange.deref(), &serde_json::to_string(&deltas).unwrap())
-                            .expect("Failed to publish message to redis PUBSUB");
+                            .publish(exchange.deref(), &wire::encode(&deltas, wire_format).unwrap())
+                            .expect("Failed to publish message");
 
                     } else if message.type_ == "match" || message.type_ == "last_match" {
-                        let _ = redis_ref.as_ref()
+                        let _ = publisher_ref.as_ref()
                             .lock()
                             .unwrap()
-                            .publish::<&str, &str, u8>(
+                            .publish(
                                 exchange.deref(), 
-                                &serde_json::to_string(&[orderbook::Delta{
+                                &wire::encode(&[orderbook::Delta{
                                     symbol: message.product_id,
                                     price: message.price.unwrap().parse::<f32>().unwrap(),
                                     size: message.size.unwrap().parse::<f32>().unwrap(),
@@ -295,9 +283,9 @@ impl Handler for WSExchangeSender {
                                     ts: Utc.datetime_from_str(&message.time, "%Y-%m-%dT%H:%M:%S.%6fZ")
                                         .expect("Failed to parse DateTime from string")
                                         .timestamp_millis() as f64 * 0.001f64 
-                                }])
+                                }], wire_format)
                                 .unwrap())
-                            .expect("Failed to publish GDAX 'match' to Redis");
+                            .expect("Failed to publish GDAX 'match'");
                     } else {
                         // Message is snapshot. Save to disk and upload to s3 or google cloud 
 
@@ -311,6 +299,11 @@ impl Handler for WSExchangeSender {
     }
 
     fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        if !self.running.load(Ordering::SeqCst) {
+            println!("GDAX Socket closed.");
+            return;
+        }
+
         // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
         // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
         println!("GDAX Socket is closing. Opening a new connection...");
@@ -322,14 +315,19 @@ impl Handler for WSExchangeSender {
 
             single_channels: self.single_channels.clone(),
 
-            tectonic: self.tectonic.clone(),
-            r: self.r.clone(),
+            publisher: self.publisher.clone(),
+            wire_format: self.wire_format,
+            running: self.running.clone(),
 
             out,
         }).unwrap();
     }
 
     fn on_timeout(&mut self, _: Token) -> Result<(), ws::Error> {
+        if !self.running.load(Ordering::SeqCst) {
+            return self.out.close(ws::CloseCode::Away);
+        }
+
         // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
         // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
         println!("GDAX Socket timed out (5s of inactivity). Opening a new connection...");
@@ -341,8 +339,9 @@ impl Handler for WSExchangeSender {
 
             single_channels: self.single_channels.clone(),
 
-            tectonic: self.tectonic.clone(),
-            r: self.r.clone(),
+            publisher: self.publisher.clone(),
+            wire_format: self.wire_format,
+            running: self.running.clone(),
 
             out,
         }).unwrap();
@@ -5,7 +5,9 @@ pub mod bitmex;
 /// GDAX managed by level 2 orderbook
 pub mod gdax_l2;
 
-use redis;
+use std::io;
+
+use transport::Publisher;
 
 /// Returns the list of supported exchanges as a vector of strings
 pub fn get_supported_exchanges() -> Vec<String> {
@@ -109,8 +111,8 @@ impl Exchange {
 pub trait AssetExchange {
     /// Require that each asset exchange we define have defaults
     fn default_settings() -> Result<Box<Self>, String>;
-    /// Initializes the redis connection
-    fn init_redis(&mut self) -> Result<redis::Connection, redis::RedisError>;
+    /// Creates the publisher we send deltas to the listener with
+    fn init_publisher(&mut self) -> Result<Box<Publisher>, io::Error>;
     /// Start and run the websocket data collection
     fn run(settings: Option<&Self>);
 }
@@ -118,7 +120,7 @@ pub trait AssetExchange {
 /// Assets that are currently supported. We plan on standardizing all token names across multiple exchanges,
 /// so having an enum of supported assets is quite... the asset ᕕ( ᐛ )ᕗ. We've included fiat as well in here,
 /// as they are considered a valid market on many websites
-#[derive(AsStaticStr, Clone)]
+#[derive(AsStaticStr, Clone, Debug, Deserialize, PartialEq, Eq, Hash)]
 pub enum Asset {
     /// Bitcoin
     BTC = 0,
@@ -1,79 +1,165 @@
-use std::env;
-use std::thread;
-use std::time::Duration;
-
-use chrono::prelude::*;
-use redis;
-use serde_json;
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 use exchange;
 use orderbook;
-use orderbook::tectonic;
-use uploader;
+use sink::Sink;
+use transport::{wire, Transport, Written};
+
+/// Most batches queued for a [`SinkWorker`]. Once a worker falls that far behind, ingestion waits on it
+const MAX_QUEUED_BATCHES: usize = 10_000;
+
+/// Listens on `transport` for [`Delta`] ticks and writes them to every sink in `sinks`.
+/// This function is called and ran in its own thread.
+///
+/// Every sink runs on a [`SinkWorker`] of its own, which also rotates it (i.e. flushes, compresses, and uploads)
+/// once every `rotation_period`. Batches are queued for the workers, so that a sink busy rotating doesn't hold up
+/// ingestion or the other sinks, unless it falls more than [`MAX_QUEUED_BATCHES`] behind.
+///
+/// Clearing `running` shuts the listener down: in-flight messages are drained, and every sink is
+/// rotated one last time and closed. Returns the first error encountered while doing so.
+pub fn listen_and_insert(transport: &Transport, sinks: Vec<Box<Sink>>, rotation_period: Duration,
+                         running: Arc<AtomicBool>) -> Result<(), Error> {
+    let workers: Vec<SinkWorker> = sinks.into_iter()
+        .map(|sink| SinkWorker::spawn(sink, rotation_period))
+        .collect();
+
+    let listened = transport.listen(&exchange::get_supported_exchanges(), &running, |channel, payload| {
+        // Deserialize and load into delta struct for insertion into the sinks
+        let deltas = wire::decode(payload);
+
+        if deltas.is_err() {
+            println!("Log Error: {}", deltas.err().unwrap());
+            // Delivering it again won't make it any more decodable
+            return Written::done();
+        }
 
-/// Initializes redis connection. Takes care of authentication if a password is present
-pub fn redis_init(r: &redis::Client, r_password: Option<&String>) -> redis::Connection {
-    let redis_conn = r.get_connection().unwrap();
+        write_to_sinks(&workers, channel, deltas.unwrap())
+    });
 
-    match r_password {
-        Some(password) => redis::cmd("AUTH").arg(password)
-            .execute(&redis_conn),
-        None => ()
-    };
+    // The sinks are closed even if the transport failed, so that nothing received so far is lost
+    let closed = close_sinks(workers);
 
-    redis_conn
+    listened.and(closed)
 }
-/// Listens on redis for [`Delta`] ticks and writes them to TectonicDB.
-/// This function is called and ran in its own thread.
-pub fn redis_listen_and_insert(r: &redis::Client, r_password: Option<String>,
-                         t: &mut tectonic::TectonicConnection) {
 
-    let mut redis_conn = self::redis_init(r, r_password.as_ref());
-    let mut subscription = redis_conn.as_pubsub();
-    let mut ticks = 0;
+/// Batch of deltas queued for a [`SinkWorker`], along with the exchange it was published on, and where to report
+/// whether it was written
+type Batch = (String, Arc<Vec<orderbook::Delta>>, Sender<bool>);
+
+/// Sink running on its own thread. The worker writes the batches queued for it, and rotates the sink
+/// once every rotation period.
+pub struct SinkWorker {
+    /// Queues batches for the worker. Dropping it stops the worker
+    sender: SyncSender<Batch>,
+    /// Worker thread. Returns the result of rotating and closing the sink
+    handle: JoinHandle<Result<(), Error>>,
+}
+
+impl SinkWorker {
+    /// Moves `sink` to a thread of its own, rotating it once every `rotation_period`
+    pub fn spawn(sink: Box<Sink>, rotation_period: Duration) -> SinkWorker {
+        let (sender, receiver) = mpsc::sync_channel(MAX_QUEUED_BATCHES);
+
+        SinkWorker {
+            sender,
+            handle: thread::spawn(move || run_sink(sink, &receiver, rotation_period)),
+        }
+    }
+
+    /// Queues a batch of deltas published on `exchange`'s channel. Once written, the worker reports whether
+    /// it succeeded on `done`. Blocks while the worker has [`MAX_QUEUED_BATCHES`] queued. Fails if the worker is gone
+    pub fn write_batch(&self, exchange: &str, deltas: Arc<Vec<orderbook::Delta>>, done: Sender<bool>) -> Result<(), Error> {
+        let stopped = || Error::new(ErrorKind::BrokenPipe, "Sink worker has stopped");
+
+        match self.sender.try_send((exchange.into(), deltas, done)) {
+            Ok(_) => Ok(()),
+            Err(TrySendError::Full(batch)) => {
+                println!("Log Error: Sink worker has {} batches queued. Waiting on it", MAX_QUEUED_BATCHES);
+                self.sender.send(batch).map_err(|_| stopped())
+            },
+            Err(TrySendError::Disconnected(_)) => Err(stopped()),
+        }
+    }
+
+    /// Waits for everything queued so far to be written, then rotates and closes the sink
+    pub fn close(self) -> Result<(), Error> {
+        drop(self.sender);
 
-    for exch in exchange::get_supported_exchanges() {
-        subscription.subscribe(exch).expect("Failed to subscribe to channel");
+        self.handle.join()
+            .unwrap_or_else(|_| Err(Error::new(ErrorKind::Other, "Sink worker panicked")))
     }
+}
+
+/// Writes the batches received on `batches` to `sink`, rotating it once every `period`. Failures are
+/// logged, and rotations retried on the next period. Once every sender is gone, the sink is rotated
+/// one last time and closed.
+fn run_sink(mut sink: Box<Sink>, batches: &Receiver<Batch>, period: Duration) -> Result<(), Error> {
+    let mut last_rotation = Instant::now();
 
     loop {
-        // Sleep while ticks are accumulated. This will ensure that the database
-        // can be written to every `n` periods. This parameter can be configured
-        // by the environment variable `UPLOAD_PERIOD`, set in seconds.
-        thread::sleep(Duration::from_secs(match env::var("UPLOAD_PERIOD") {
-            Ok(var) => var.parse::<u64>().unwrap(),
-            Err(_) => 86400u64,
-        }));
+        let until_rotation = period.checked_sub(last_rotation.elapsed()).unwrap_or_default();
 
-        // Begin by reading from redis
-        let message = subscription.get_message().unwrap();
-        let payload: String = message.get_payload().unwrap();
+        match batches.recv_timeout(until_rotation) {
+            Ok((exchange, deltas, done)) => {
+                let written = sink.write_batch(&exchange, &deltas);
 
-        // Deserialize and load into delta struct for insertion to tectonicdb
-        let deltas = serde_json::from_str::<Vec<orderbook::Delta>>(&payload);
+                if let Err(ref e) = written {
+                    println!("Log Error: {}", e);
+                }
 
-        if deltas.is_err() {
-            println!("Log Error: {}", deltas.err().unwrap());
-            continue;
+                // Nobody might be waiting on the report
+                let _ = done.send(written.is_ok());
+            },
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => break,
         }
 
-        for delta in &deltas.unwrap() {
-            let _ = t
-                .insert_into(format!("{}_{}", message.get_channel_name(), delta.symbol), delta)
-                .unwrap();
+        if last_rotation.elapsed() >= period {
+            last_rotation = Instant::now();
+
+            if let Err(e) = sink.rotate() {
+                println!("Log Error: Failed to rotate sink: {}", e);
+            }
         }
+    }
 
-        // TODO: Write files to AWS before flushing new files to disk
-        print!("Flushing TectonicDB data to disk... ");
-        let _ = t.flush_all().unwrap();
-        let t = Utc::now().to_rfc3339() + ".tar.xz".into();
+    sink.rotate().and_then(|_| sink.close())
+}
 
-        uploader::compress_database_and_delete(&t, None).unwrap();
+/// Fans a batch of deltas out to every sink. A failing sink doesn't stop the others from being written to.
+/// The returned [`Written`] reports once every sink has written the batch.
+pub fn write_to_sinks(workers: &[SinkWorker], exchange: &str, deltas: Vec<orderbook::Delta>) -> Written {
+    let deltas = Arc::new(deltas);
+    let (done, written) = Written::new(workers.len());
 
-        if env::var("S3_UPLOAD").unwrap_or("false".into()) == String::from("true") {
-            uploader::s3_upload(&t, None, None, None).unwrap();
+    for worker in workers {
+        if let Err(e) = worker.write_batch(exchange, deltas.clone(), done.clone()) {
+            println!("Log Error: {}", e);
         }
+    }
+
+    written
+}
 
-        println!("Success");
+/// Rotates and closes every sink, so that nothing written so far is left behind. Every sink is
+/// closed even if an earlier one fails, and the first error is returned.
+pub fn close_sinks(workers: Vec<SinkWorker>) -> Result<(), Error> {
+    let mut result = Ok(());
+
+    for worker in workers {
+        if let Err(e) = worker.close() {
+            println!("Log Error: Failed to close sink: {}", e);
+
+            if result.is_ok() {
+                result = Err(e);
+            }
+        }
     }
+
+    result
 }
@@ -9,109 +9,302 @@
 //! plug it in to various exchanges. CCXT may be another option for this as well, as it already has built-in support for lots of crypto exchanges
 //!
 //! This project makes use of [TectonicDB](https://github.com/rickyhan/tectonicdb) to store orderbook data
-//! in a database efficiently. We also make use of LZMA2 to compress that data further to allow for more data storage.
+//! in a database efficiently. We also make use of LZMA2 (or Zstandard) to compress that data further to allow for more data storage.
+//!
+//! # Configuration
+//! Exchanges, symbols, channels, the transport, sinks, TectonicDB, and the upload policy are read from
+//! the TOML file named by `CONFIG` (defaults to `config.toml`). See `config.example.toml` for every setting.
+//! The configuration is validated at startup, and the environment variables below override the file.
+//!
+//! # Usage
+//! `rusty_road [run]` starts the collectors and the listener in one process. The roles can also be split
+//! across machines with `rusty_road collect` and `rusty_road ingest`. `upload`, `catalog`, `restore`, `replay`, and `dump` are
+//! one-shot maintenance commands. Run `rusty_road help` for the details.
+//!
+//! On SIGTERM (or SIGINT), the collectors are stopped, in-flight messages are drained, and every sink is
+//! rotated one last time before exiting. The exit status is non-zero if any of that failed.
+//! A second signal exits right away.
 //!
 //! # Environment Variables
+//! `CONFIG`: Path to the configuration file. Defaults to `config.toml`, or the built-in defaults if that file is missing
 //! `AWS_ACCESS_KEY_ID`: AWS Access Key
 //! `AWS_SECRET_ACCESS_KEY`: AWS Access Key Secret.
-//! `S3_UPLOAD`: Determines whether we upload to s3. "true" and "false" are valid values. Defaults to "true"
+//! `S3_UPLOAD`: Determines whether we upload to s3. "true" and "false" are valid values. Defaults to "false"
 //! `S3_BUCKET`: Amazon S3 Bucket to upload to. Defaults to "cuteq"
 //! `S3_STORAGE_CLASS`: Amazon S3 Storage class type. Defaults to "STANDARD_IA"
+//! `S3_REGION`: AWS region to upload to. Defaults to "us-east-1"
+//! `S3_ENDPOINT`: S3-compatible server (e.g. MinIO) to upload to instead of AWS
+//! `ARCHIVE_ENCRYPTION_KEY`: Base64 encoded 256-bit key archives are encrypted with. Takes precedence over `upload.encryption_key`
 //! `UPLOAD_PERIOD`: Sets the amount of time in seconds we should wait before dumping the
 //!     tectonicdb database and uploading it. Defaults to 86400 seconds (one day)
 //! `REDIS_AUTH`: Redis password.
 //! `DTF_DB_PATH`: TectonicDB Database where files are written to. Defaults to `$HOME/tectonicdb/target/release/db`
+//! `TRANSPORT`: Message bus used between the exchanges and the listener. "redis", "redis-streams", and "zeromq" are valid values. Defaults to "redis"
+//! `REDIS_STREAM_MAXLEN`: Approximate maximum amount of entries kept in every Redis stream. Defaults to 1000000
+//! `REDIS_GROUP`: Redis Streams consumer group the listener reads through. Defaults to "rusty_road"
+//! `REDIS_CONSUMER`: Name of the listener inside the consumer group. Keep it the same across restarts. Defaults to "listener"
+//! `ZMQ_ENDPOINT`: ZeroMQ endpoint the listener binds to. Defaults to `ipc:///tmp/rusty_road.ipc`
+//! `WIRE_FORMAT`: Encoding the exchanges publish deltas with. "json" and "binary" are valid values. Defaults to "json".
+//!     The listener accepts both formats regardless of this setting.
 
 #![deny(missing_docs)]
 #![feature(custom_attribute)]
 #![feature(vec_remove_item)]
 #![feature(nll)]
+#![feature(test)]
 
+extern crate base64;
+extern crate byteorder;
 extern crate chrono;
+#[macro_use]
+extern crate clap;
 extern crate futures;
+extern crate md5;
 extern crate ndarray;
+extern crate parquet;
 extern crate rayon;
 extern crate redis;
 extern crate reqwest;
+extern crate ring;
+extern crate rusqlite;
 extern crate rusoto_core;
 extern crate rusoto_s3;
 extern crate serde_json;
+extern crate signal_hook;
 extern crate strum;
 extern crate tar;
+#[cfg(test)]
+extern crate test;
+extern crate toml;
 extern crate url;
 extern crate ws;
 extern crate xz2;
+extern crate zmq;
+extern crate zstd;
 
 #[macro_use]
 extern crate serde_derive;
 #[macro_use]
 extern crate strum_macros;
 
+/// Manifest of the archives we've uploaded
+pub mod catalog;
+/// Subcommands of the `rusty_road` binary
+pub mod cli;
+/// Configuration file loading and validation
+pub mod config;
+/// Client-side encryption of archives
+pub mod encryption;
 /// Exchanges and exchange-related methods and modules
 pub mod exchange;
 /// Methods to listen on redis/ZeroMQ sockets.
 pub mod listener;
+/// Live cross-exchange arbitrage and basis monitor
+pub mod monitor;
 /// Handles uploading DTF compressed archives to the cloud
 pub mod uploader;
 /// Orderbook analytics and state management data structures
 pub mod orderbook;
+/// Destinations the listener persists deltas to
+pub mod sink;
+/// Storage backends archives are uploaded to
+pub mod storage;
 /// Unit tests for various parts of this project
 pub mod tests;
+/// Message buses used to move deltas from the exchanges to the listener
+pub mod transport;
 
-use std::env;
-use std::thread;
-
-use exchange::{Asset, AssetExchange, bitmex, gdax_l2};
-use orderbook::tectonic;
-
-fn main() {
-    // Redis client is setup here so that we can provide it a host, password, and database
-    let r = redis::Client::open("redis://127.0.0.1:6379/0").unwrap();
-    // TODO: Consider moving this to the `redis_init` function?
-    let r_password = match env::var_os("REDIS_AUTH") {
-        Some(password) => Some(password.into_string().unwrap()),
-        None => None
-    };
-
-    // Begin connection setup to exchange websockets
-    // =====================================================
+use std::process;
 
-    let mut bitmex_settings = *bitmex::WSExchange::default_settings().unwrap();
-    bitmex_settings.metadata.asset_pair = Some(vec![
-        [Asset::BTC, Asset::USD],]);
-    bitmex_settings.r = r.clone();
-    bitmex_settings.r_password = r_password.as_ref().cloned();
+use clap::{App, AppSettings, Arg, SubCommand};
 
-    let mut gdax_settings = *gdax_l2::WSExchange::default_settings().unwrap();
-    gdax_settings.metadata.asset_pair = Some(vec![
-        [Asset::BTC, Asset::USD],
-        [Asset::ETH, Asset::USD],
-        [Asset::LTC, Asset::USD],
-        [Asset::BTC, Asset::USDC],
-    ]);
-    gdax_settings.r = r.clone();
-    gdax_settings.r_password = r_password.as_ref().cloned();
+use catalog::Catalog;
+use config::Config;
+use sink::file::FileFormat;
 
-    // =====================================================
+fn main() {
+    let matches = App::new("rusty_road")
+        .version(crate_version!())
+        .about("Cryptocurrency orderbook data collection")
+        .setting(AppSettings::VersionlessSubcommands)
+        .arg(Arg::with_name("config")
+            .short("c")
+            .long("config")
+            .value_name("FILE")
+            .takes_value(true)
+            .global(true)
+            .help("Configuration file. Overrides the CONFIG environment variable"))
+        .subcommand(SubCommand::with_name("run")
+            .about("Runs the collectors and the listener in one process (default)"))
+        .subcommand(SubCommand::with_name("collect")
+            .about("Runs only the collectors, publishing deltas on the configured transport"))
+        .subcommand(SubCommand::with_name("ingest")
+            .about("Runs only the listener, writing deltas from the transport to the configured sinks"))
+        .subcommand(SubCommand::with_name("upload")
+            .about("Compresses every database in a DTF directory into its own archive and uploads them to every storage backend")
+            .arg(Arg::with_name("PATH")
+                .required(true)
+                .help("Directory holding the DTF files, which are deleted once archived, \
+                       or an archive left behind by a failed upload"))
+            .arg(Arg::with_name("no-upload")
+                .long("no-upload")
+                .help("Only create the archives")))
+        .subcommand(SubCommand::with_name("catalog")
+            .about("Lists the uploaded archives holding a symbol, or every symbol in the catalog")
+            .arg(Arg::with_name("EXCHANGE")
+                .requires("SYMBOL")
+                .help("Exchange, e.g. gdax"))
+            .arg(Arg::with_name("SYMBOL")
+                .help("Symbol, e.g. BTC-USD"))
+            .arg(Arg::with_name("from")
+                .long("from")
+                .value_name("DATE")
+                .takes_value(true)
+                .help("Only list archives with data after this RFC 3339 date"))
+            .arg(Arg::with_name("to")
+                .long("to")
+                .value_name("DATE")
+                .takes_value(true)
+                .help("Only list archives with data before this RFC 3339 date")))
+        .subcommand(SubCommand::with_name("restore")
+            .about("Fetches archives from S3 or disk and restores them into TectonicDB, or prints their deltas")
+            .arg(Arg::with_name("LOCATION")
+                .multiple(true)
+                .required_unless("symbol")
+                .conflicts_with("symbol")
+                .help("s3://bucket/key URL, file:// URL, or path of an archive"))
+            .arg(Arg::with_name("exchange")
+                .long("exchange")
+                .takes_value(true)
+                .requires("symbol")
+                .help("Restore every archive of --symbol on this exchange found in the catalog"))
+            .arg(Arg::with_name("symbol")
+                .long("symbol")
+                .takes_value(true)
+                .requires("exchange")
+                .help("Symbol to look up in the catalog, e.g. BTC-USD"))
+            .arg(Arg::with_name("from")
+                .long("from")
+                .value_name("DATE")
+                .takes_value(true)
+                .requires("symbol")
+                .help("Only restore archives with data after this RFC 3339 date"))
+            .arg(Arg::with_name("to")
+                .long("to")
+                .value_name("DATE")
+                .takes_value(true)
+                .requires("symbol")
+                .help("Only restore archives with data before this RFC 3339 date"))
+            .arg(Arg::with_name("into")
+                .long("into")
+                .value_name("DIR")
+                .takes_value(true)
+                .help("TectonicDB data folder to restore into. Defaults to tectonic.db_path"))
+            .arg(Arg::with_name("stream")
+                .long("stream")
+                .value_name("FORMAT")
+                .takes_value(true)
+                .possible_values(&["csv", "ndjson"])
+                .help("Print the deltas to stdout in this format instead of restoring the archives")))
+        .subcommand(SubCommand::with_name("replay")
+            .about("Replays an archive into the configured sinks")
+            .arg(Arg::with_name("ARCHIVE")
+                .required(true)
+                .help("Archive created by the TectonicDB sink or the upload command")))
+        .subcommand(SubCommand::with_name("train-dictionary")
+            .about("Trains a zstd dictionary for archives on a directory of DTF files")
+            .arg(Arg::with_name("DIR")
+                .required(true)
+                .help("Directory holding the DTF files to train on"))
+            .arg(Arg::with_name("OUTPUT")
+                .required(true)
+                .help("File to write the dictionary to"))
+            .arg(Arg::with_name("max-size")
+                .long("max-size")
+                .value_name("BYTES")
+                .takes_value(true)
+                .default_value("112640")
+                .help("Maximum size of the dictionary")))
+        .subcommand(SubCommand::with_name("dump")
+            .about("Dumps a TectonicDB database to a file")
+            .arg(Arg::with_name("DB")
+                .required(true)
+                .help("Database name, e.g. gdax_BTC-USD"))
+            .arg(Arg::with_name("OUTPUT")
+                .required(true)
+                .help("File to write to"))
+            .arg(Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["csv", "ndjson"])
+                .default_value("csv")
+                .help("Output format")))
+        .get_matches();
 
-    let mut exchanges = vec![];
+    let config_path = matches.subcommand()
+        .1
+        .and_then(|sub_matches| sub_matches.value_of("config"))
+        .or(matches.value_of("config"));
 
-    // Push exchange instance threads to vector
-    exchanges.push(thread::spawn(move ||
-        bitmex::WSExchange::run(Some(&bitmex_settings))));
+    // Every setting comes from the configuration file, with environment variables taking precedence
+    let config = match Config::load(config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            process::exit(1);
+        }
+    };
 
-    exchanges.push(thread::spawn(move ||
-        gdax_l2::WSExchange::run(Some(&gdax_settings))));
+    let result = match matches.subcommand() {
+        ("collect", _) => cli::collect(&config),
+        ("ingest", _) => cli::ingest(&config),
+        ("upload", Some(args)) => cli::upload(
+            &config,
+            args.value_of("PATH").unwrap(),
+            !args.is_present("no-upload")),
+        ("catalog", Some(args)) => cli::list_catalog(
+            &config,
+            args.value_of("EXCHANGE"),
+            args.value_of("SYMBOL"),
+            args.value_of("from"),
+            args.value_of("to")),
+        ("restore", Some(args)) => {
+            let locations = match args.value_of("symbol") {
+                Some(symbol) => Catalog::open(&config.upload.catalog)
+                    .and_then(|catalog| cli::find_archives(
+                        &catalog,
+                        args.value_of("exchange").unwrap(),
+                        symbol,
+                        args.value_of("from"),
+                        args.value_of("to")))
+                    .map(|entries| entries.into_iter().map(|entry| entry.location).collect()),
+                None => Ok(args.values_of("LOCATION").unwrap().map(String::from).collect()),
+            };
 
-    // Start a listener to insert ticks into tectonicdb
-    exchanges.push(thread::spawn(move ||
-        listener::redis_listen_and_insert(
-            &r,
-            r_password,
-            &mut tectonic::TectonicConnection::new(None, None)
-                .expect("Failed to connect to TectonicDB"))));
+            locations.and_then(|locations: Vec<String>| cli::restore(
+                &config,
+                &locations,
+                args.value_of("into"),
+                args.value_of("stream").map(|format| if format == "ndjson" { FileFormat::Ndjson } else { FileFormat::Csv })))
+        },
+        ("replay", Some(args)) => cli::replay(&config, args.value_of("ARCHIVE").unwrap()),
+        ("train-dictionary", Some(args)) => match value_t!(args, "max-size", usize) {
+            Ok(max_size) => cli::train_dictionary(
+                args.value_of("DIR").unwrap(),
+                args.value_of("OUTPUT").unwrap(),
+                max_size),
+            Err(e) => e.exit(),
+        },
+        ("dump", Some(args)) => cli::dump(
+            &config,
+            args.value_of("DB").unwrap(),
+            args.value_of("OUTPUT").unwrap(),
+            if args.value_of("format") == Some("ndjson") { FileFormat::Ndjson } else { FileFormat::Csv }),
+        _ => cli::run(&config),
+    };
 
-    for exchange in exchanges {
-        let _ = exchange.join();
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        process::exit(1);
     }
 }
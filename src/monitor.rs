@@ -0,0 +1,283 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{Error, ErrorKind};
+
+use serde_json;
+
+use config::{self, ExchangeConfig, MonitorConfig};
+use orderbook::{Book, Delta};
+use orderbook::consolidated::{ConsolidatedBook, Instrument};
+use sink::Sink;
+use transport::Publisher;
+
+/// Price increment of the books of exchanges missing from `monitor.tick_sizes`
+pub const DEFAULT_TICK_SIZE: f32 = 0.01;
+
+/// What a [`SpreadEvent`] measures
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpreadKind {
+    /// Spread between two venues of the same kind, such as two spot exchanges
+    Spread,
+    /// Premium of a perpetual swap over spot
+    Basis,
+}
+
+/// Point of a spread or basis series, as published on the monitor's channel
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SpreadEvent {
+    /// Timestamp of the latest delta taken into account, in seconds
+    pub ts: f64,
+    /// Instrument both venues trade (e.g. "BTC-USD")
+    pub instrument: String,
+    /// Spot-spot spread or perpetual-spot basis
+    pub kind: SpreadKind,
+    /// Venue the spread is measured on. The perpetual for a basis
+    pub venue: String,
+    /// Venue the spread is measured against. The spot exchange for a basis
+    pub against: String,
+    /// Mid price of `venue` minus the mid price of `against`
+    pub spread: f32,
+    /// `spread`, in basis points of the mid price of `against`
+    pub spread_bps: f32,
+    /// Venue to buy on (at the ask) to capture the spread
+    pub buy: String,
+    /// Best ask on `buy`
+    pub buy_price: f32,
+    /// Venue to sell on (at the bid) to capture the spread
+    pub sell: String,
+    /// Best bid on `sell`
+    pub sell_price: f32,
+    /// What's left, in basis points, once both sides cross the spread and pay taker fees.
+    /// Positive when there's something to capture
+    pub edge_bps: f32,
+}
+
+/// Follows the books of every instrument collected on more than one exchange, and publishes their spreads and
+/// basis every time one of the books changes. Opportunities with an executable edge above the threshold are
+/// logged once when they open, and once when they close.
+///
+/// Runs as a sink, so that it sees the deltas as the listener receives them.
+pub struct ArbitrageMonitor {
+    /// Book of every instrument traded on more than one exchange
+    books: Vec<ConsolidatedBook>,
+    /// Publishes the events on the transport
+    publisher: Box<Publisher>,
+    /// Channel the events are published on
+    channel: String,
+    /// Edge in basis points opportunities are logged from
+    threshold_bps: f32,
+    /// Exchanges trading perpetual swaps
+    perpetuals: Vec<String>,
+    /// Taker fees in basis points, by exchange
+    fees: HashMap<String, f32>,
+    /// Opportunities above the threshold, as (instrument, buy venue, sell venue)
+    open: HashSet<(String, String, String)>,
+    /// Books (as indexes into `books`) whose venues have all been seeded. Nothing is published for the others
+    seeded: HashSet<usize>,
+}
+
+impl ArbitrageMonitor {
+    /// Creates a monitor following the symbols of `exchanges`, publishing its events with `publisher`
+    pub fn new(config: &MonitorConfig, exchanges: &[ExchangeConfig], publisher: Box<Publisher>) -> ArbitrageMonitor {
+        let mut venues: Vec<(Instrument, Vec<(String, String)>)> = vec![];
+
+        for exchange in exchanges {
+            let exch = match config::exchange_by_name(&exchange.name) {
+                Some(exch) => exch,
+                None => continue,
+            };
+
+            for pair in &exchange.symbols {
+                let instrument = Instrument {
+                    asset: pair[0].clone(),
+                    market: pair[1].clone(),
+                };
+                let symbol = match instrument.symbol(&exch) {
+                    Some(symbol) => symbol,
+                    None => continue,
+                };
+
+                match venues.iter().position(|(i, _)| *i == instrument) {
+                    Some(idx) => venues[idx].1.push((exchange.name.clone(), symbol)),
+                    None => venues.push((instrument, vec![(exchange.name.clone(), symbol)])),
+                }
+            }
+        }
+
+        let books = venues.into_iter()
+            .filter(|(_, venues)| venues.len() > 1)
+            .map(|(instrument, venues)| {
+                let mut book = ConsolidatedBook::new(instrument.clone());
+
+                for (name, symbol) in venues {
+                    book.add_venue(&name, &symbol, Book {
+                        market: Some(instrument.market.clone()),
+                        asset: Some(instrument.asset.clone()),
+                        tick_size: *config.tick_sizes.get(&name).unwrap_or(&DEFAULT_TICK_SIZE),
+                        ..Default::default()
+                    });
+                }
+
+                book
+            })
+            .collect();
+
+        ArbitrageMonitor {
+            books,
+            publisher,
+            channel: config.channel.clone(),
+            threshold_bps: config.threshold_bps,
+            perpetuals: config.perpetuals.clone(),
+            fees: config.fees.clone(),
+            open: HashSet::new(),
+            seeded: HashSet::new(),
+        }
+    }
+
+    /// Consolidated books being followed
+    pub fn books(&self) -> &[ConsolidatedBook] {
+        &self.books
+    }
+
+    /// Spreads and basis between every pair of venues of `book` quoting both sides
+    pub fn spreads(&self, book: &ConsolidatedBook, ts: f64) -> Vec<SpreadEvent> {
+        let venues: Vec<(&str, &Book)> = book.venues().into_iter()
+            .filter_map(|name| book.venue(name).map(|b| (name, b)))
+            .filter(|(_, b)| !b.bid_price_points.is_empty() && !b.ask_price_points.is_empty())
+            .collect();
+
+        let mut events = vec![];
+
+        for (i, &(first, first_book)) in venues.iter().enumerate() {
+            for &(second, second_book) in &venues[i + 1..] {
+                let first_perpetual = self.perpetuals.iter().any(|p| p == first);
+                let second_perpetual = self.perpetuals.iter().any(|p| p == second);
+
+                // A basis is always measured on the perpetual, against spot
+                let (kind, venue, against) = match (first_perpetual, second_perpetual) {
+                    (false, true) => (SpreadKind::Basis, (second, second_book), (first, first_book)),
+                    (true, false) => (SpreadKind::Basis, (first, first_book), (second, second_book)),
+                    _ => (SpreadKind::Spread, (second, second_book), (first, first_book)),
+                };
+
+                events.push(self.spread(book, kind, venue, against, ts));
+            }
+        }
+
+        events
+    }
+
+    /// Spread of `venue` against `against`, with the edge of the most profitable direction
+    fn spread(&self, book: &ConsolidatedBook, kind: SpreadKind, venue: (&str, &Book), against: (&str, &Book),
+              ts: f64) -> SpreadEvent {
+        let spread = venue.1.mid_price() - against.1.mid_price();
+
+        // Buy wherever it's cheaper, and sell on the other venue
+        let (buy, sell) = if spread >= 0.0 { (against, venue) } else { (venue, against) };
+        let buy_price = buy.1.mid_price() + buy.1.bid_ask_spread() / 2.0;
+        let sell_price = sell.1.mid_price() - sell.1.bid_ask_spread() / 2.0;
+        let fees = self.fee(buy.0) + self.fee(sell.0);
+
+        SpreadEvent {
+            ts,
+            instrument: book.instrument.to_string(),
+            kind,
+            venue: venue.0.into(),
+            against: against.0.into(),
+            spread,
+            spread_bps: spread / against.1.mid_price() * 10_000.0,
+            buy: buy.0.into(),
+            buy_price,
+            sell: sell.0.into(),
+            sell_price,
+            edge_bps: (sell_price / buy_price - 1.0) * 10_000.0 - fees,
+        }
+    }
+
+    /// Taker fee of `venue`, in basis points
+    fn fee(&self, venue: &str) -> f32 {
+        *self.fees.get(venue).unwrap_or(&0.0)
+    }
+
+    /// Logs the opportunities opening and closing with `event`
+    fn track_opportunity(&mut self, event: &SpreadEvent) {
+        let opportunity = (event.instrument.clone(), event.buy.clone(), event.sell.clone());
+        let reverse = (event.instrument.clone(), event.sell.clone(), event.buy.clone());
+
+        if self.open.remove(&reverse) {
+            println!("Opportunity closed on {}: buy on {}, sell on {}", reverse.0, reverse.1, reverse.2);
+        }
+
+        if event.edge_bps >= self.threshold_bps {
+            if self.open.insert(opportunity) {
+                println!("Opportunity on {}: buy on {} at {}, sell on {} at {}, {:.1} bps after fees",
+                    event.instrument, event.buy, event.buy_price, event.sell, event.sell_price, event.edge_bps);
+            }
+        } else if self.open.remove(&opportunity) {
+            println!("Opportunity closed on {}: buy on {}, sell on {}", event.instrument, event.buy, event.sell);
+        }
+    }
+}
+
+impl Sink for ArbitrageMonitor {
+    /// Applies the deltas to the books they belong to, and publishes the spreads of every book that changed.
+    /// Books are left out until they're seeded (see [`ConsolidatedBook::is_seeded`])
+    fn write_batch(&mut self, exchange: &str, deltas: &[Delta]) -> Result<(), Error> {
+        let ts = deltas.iter().fold(0.0, |ts, delta| delta.ts.max(ts));
+        let mut changed = vec![];
+
+        for (idx, book) in self.books.iter_mut().enumerate() {
+            let symbol = match book.symbol(exchange) {
+                Some(symbol) => symbol.to_owned(),
+                None => continue,
+            };
+
+            if !deltas.iter().any(|delta| delta.symbol == symbol && !delta.is_trade()) {
+                continue;
+            }
+
+            if let Err(e) = book.apply(exchange, deltas) {
+                println!("Log Error: {}", e);
+                continue;
+            }
+
+            // Spreads against a venue that is still filling up its book would be bogus
+            if self.seeded.contains(&idx) || book.is_seeded() {
+                self.seeded.insert(idx);
+                changed.push(idx);
+            }
+        }
+
+        let mut events = vec![];
+
+        for idx in changed {
+            events.extend(self.spreads(&self.books[idx], ts));
+        }
+
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        for event in &events {
+            self.track_opportunity(event);
+        }
+
+        let payload = serde_json::to_vec(&events)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+
+        self.publisher.publish(&self.channel, &payload)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Books are kept as they are, as they only ever hold the latest state
+    fn rotate(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,350 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+
+use exchange::{Asset, Exchange};
+use orderbook::{Book, Delta, Snapshot};
+
+/// Pair traded on several exchanges under different symbols, such as BTC-USD on GDAX and XBTUSD on BitMEX
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Instrument {
+    /// Asset being bought and sold (e.g. BTC)
+    pub asset: Asset,
+    /// Asset it's priced in (e.g. USD)
+    pub market: Asset,
+}
+
+impl Instrument {
+    /// Symbol of the instrument on `exchange`, if it's traded there
+    pub fn symbol(&self, exchange: &Exchange) -> Option<String> {
+        let asset = exchange.normalize_asset(&self.asset)?;
+        let market = exchange.normalize_asset(&self.market)?;
+
+        Some(match exchange.market_first() {
+            true => format!("{}{}{}", market, exchange.asset_separator(), asset),
+            false => format!("{}{}{}", asset, exchange.asset_separator(), market),
+        })
+    }
+}
+
+impl fmt::Display for Instrument {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}-{:?}", self.asset, self.market)
+    }
+}
+
+/// Best price on one side of a venue's book
+#[derive(Clone, Debug, PartialEq)]
+pub struct Quote {
+    /// Exchange quoting the price
+    pub venue: String,
+    /// Level price
+    pub price: f32,
+    /// Level size
+    pub size: f32,
+}
+
+/// Price level of the consolidated book, along with how much of it every venue contributes
+#[derive(Clone, Debug, PartialEq)]
+pub struct Level {
+    /// Level price
+    pub price: f32,
+    /// Total size across venues
+    pub size: f32,
+    /// Size quoted by every venue at this price, largest first
+    pub venues: Vec<(String, f32)>,
+}
+
+/// Consolidated best bid and offer, NBBO-style
+#[derive(Clone, Debug, PartialEq)]
+pub struct BestBidOffer {
+    /// Highest bid across venues
+    pub bid: Quote,
+    /// Lowest ask across venues
+    pub ask: Quote,
+}
+
+impl BestBidOffer {
+    /// Gets the consolidated spread (i.e. `ask - bid`). Negative when the market is crossed
+    pub fn spread(&self) -> f32 {
+        self.ask.price - self.bid.price
+    }
+    /// Gets the consolidated mid price
+    pub fn mid_price(&self) -> f32 {
+        (self.ask.price + self.bid.price) / 2.0
+    }
+    /// Returns `true` if someone bids more than someone else asks
+    pub fn is_crossed(&self) -> bool {
+        self.bid.price > self.ask.price
+    }
+    /// Returns `true` if the best bid and ask are at the same price
+    pub fn is_locked(&self) -> bool {
+        self.bid.price == self.ask.price
+    }
+}
+
+/// Pair of venues where the bid on one is at or above the ask on the other
+#[derive(Clone, Debug, PartialEq)]
+pub struct CrossedMarket {
+    /// Best bid of the venue bidding too high
+    pub bid: Quote,
+    /// Best ask of the venue asking too little
+    pub ask: Quote,
+}
+
+/// Book of one venue taking part in a [`ConsolidatedBook`]
+struct Venue {
+    /// Exchange name, as used by the listener and the sinks (e.g. "gdax")
+    name: String,
+    /// Symbol of the instrument on that exchange
+    symbol: String,
+    /// Latest state of the venue's book
+    book: Book,
+}
+
+/// Merges the books of several venues trading the same [`Instrument`] into one. Every level remembers which
+/// venues it came from, and the consolidated best bid and offer show who's quoting the best prices.
+///
+/// Venues are added with a book built with their own tick size. The books are then either replaced wholesale
+/// with [`ConsolidatedBook::update`], or kept up to date with the deltas the collectors publish through
+/// [`ConsolidatedBook::apply`], which gives a real-time view across venues.
+pub struct ConsolidatedBook {
+    /// Instrument every venue trades
+    pub instrument: Instrument,
+    /// Books of every venue, in the order they were added
+    venues: Vec<Venue>,
+}
+
+impl ConsolidatedBook {
+    /// Creates an empty consolidated book for `instrument`
+    pub fn new(instrument: Instrument) -> ConsolidatedBook {
+        ConsolidatedBook {
+            instrument,
+            venues: vec![],
+        }
+    }
+
+    /// Adds `venue` (or replaces its book), where the instrument trades as `symbol`. Books that weren't
+    /// initialized yet are initialized empty, ready for deltas.
+    pub fn add_venue(&mut self, venue: &str, symbol: &str, mut book: Book) {
+        if book.state.is_empty() {
+            book.initialize(&Snapshot {
+                market: book.market.clone(),
+                asset: book.asset.clone(),
+                bids: vec![],
+                asks: vec![],
+            });
+        }
+
+        self.remove_venue(venue);
+        self.venues.push(Venue {
+            name: venue.into(),
+            symbol: symbol.into(),
+            book,
+        });
+    }
+
+    /// Stops consolidating `venue`, e.g. once its feed has gone stale
+    pub fn remove_venue(&mut self, venue: &str) {
+        self.venues.retain(|v| v.name != venue);
+    }
+
+    /// Book of `venue`, if it's part of the consolidated book
+    pub fn venue(&self, venue: &str) -> Option<&Book> {
+        self.venues.iter().find(|v| v.name == venue).map(|v| &v.book)
+    }
+
+    /// Symbol the instrument trades as on `venue`
+    pub fn symbol(&self, venue: &str) -> Option<&str> {
+        self.venues.iter().find(|v| v.name == venue).map(|v| v.symbol.as_str())
+    }
+
+    /// Whether every venue quotes both sides, without its own bid reaching its ask. The collectors only publish
+    /// deltas, so books are built up from the levels that change, and are meaningless until then.
+    pub fn is_seeded(&self) -> bool {
+        self.venues.iter().all(|v| !v.book.bid_price_points.is_empty() && !v.book.ask_price_points.is_empty()
+            && v.book.best_bid < v.book.best_ask)
+    }
+
+    /// Names of every venue, in the order they were added
+    pub fn venues(&self) -> Vec<&str> {
+        self.venues.iter().map(|v| v.name.as_str()).collect()
+    }
+
+    /// Replaces the book of `venue` with `book`, keeping its symbol
+    pub fn update(&mut self, venue: &str, book: Book) -> Result<(), Error> {
+        let v = self.venue_mut(venue)?;
+        v.book = book;
+
+        Ok(())
+    }
+
+    /// Applies the deltas published on `exchange`'s channel to its book. Deltas of other symbols and trades
+    /// are skipped, so whole batches can be handed over as they come off the transport. Does nothing if
+    /// `exchange` isn't one of the venues, and leaves the book untouched if any delta is off the book.
+    pub fn apply(&mut self, exchange: &str, deltas: &[Delta]) -> Result<(), Error> {
+        let venue = match self.venues.iter_mut().find(|v| v.name == exchange) {
+            Some(venue) => venue,
+            None => return Ok(()),
+        };
+
+        let symbol = &venue.symbol;
+        let book = &mut venue.book;
+
+        let mut updates = vec![];
+
+        for delta in deltas.iter().filter(|delta| delta.symbol == *symbol && !delta.is_trade()) {
+            let price = book.price_index(delta.price);
+
+            if price as usize >= book.state.len() {
+                return Err(Error::new(ErrorKind::InvalidInput,
+                    format!("{} {} is off the {} book", delta.side(), delta.price, exchange)));
+            }
+
+            updates.push((price, delta.size, delta.is_bid()));
+        }
+
+        book.new_state(&updates);
+
+        Ok(())
+    }
+
+    /// Best bid of every venue quoting one, highest first
+    pub fn best_bids(&self) -> Vec<Quote> {
+        let mut bids: Vec<Quote> = self.venues.iter()
+            .filter(|v| !v.book.bid_price_points.is_empty())
+            .map(|v| Quote {
+                venue: v.name.clone(),
+                price: v.book.real_price(v.book.best_bid),
+                size: v.book.best_bid_size,
+            })
+            .collect();
+
+        bids.sort_by(|a, b| b.price.partial_cmp(&a.price).unwrap_or(Ordering::Equal));
+        bids
+    }
+
+    /// Best ask of every venue quoting one, lowest first
+    pub fn best_asks(&self) -> Vec<Quote> {
+        let mut asks: Vec<Quote> = self.venues.iter()
+            .filter(|v| !v.book.ask_price_points.is_empty())
+            .map(|v| Quote {
+                venue: v.name.clone(),
+                price: v.book.real_price(v.book.best_ask),
+                size: v.book.best_ask_size,
+            })
+            .collect();
+
+        asks.sort_by(|a, b| a.price.partial_cmp(&b.price).unwrap_or(Ordering::Equal));
+        asks
+    }
+
+    /// Consolidated best bid and offer. `None` until at least one venue quotes each side.
+    /// Ties go to the venue added first.
+    pub fn best_bid_offer(&self) -> Option<BestBidOffer> {
+        let bid = self.best_bids().into_iter().next()?;
+        let ask = self.best_asks().into_iter().next()?;
+
+        Some(BestBidOffer {
+            bid,
+            ask,
+        })
+    }
+
+    /// Every pair of venues where one bids at or above what the other asks, most crossed first.
+    /// Empty when the consolidated market is neither crossed nor locked.
+    pub fn crossed_markets(&self) -> Vec<CrossedMarket> {
+        let mut crossed = vec![];
+
+        for bid in self.best_bids() {
+            for ask in self.best_asks() {
+                if bid.venue != ask.venue && bid.price >= ask.price {
+                    crossed.push(CrossedMarket {
+                        bid: bid.clone(),
+                        ask,
+                    });
+                }
+            }
+        }
+
+        crossed.sort_by(|a, b| (b.bid.price - b.ask.price).partial_cmp(&(a.bid.price - a.ask.price))
+            .unwrap_or(Ordering::Equal));
+        crossed
+    }
+
+    /// Bid levels across venues, best first, merging levels of different venues at the same price
+    pub fn bids(&self, depth: usize) -> Vec<Level> {
+        self.levels(true, depth)
+    }
+
+    /// Ask levels across venues, best first, merging levels of different venues at the same price
+    pub fn asks(&self, depth: usize) -> Vec<Level> {
+        self.levels(false, depth)
+    }
+
+    /// Merges the levels on one side of every venue's book. Venues may have different tick sizes, so prices
+    /// closer than half the smallest tick are taken to be the same level.
+    fn levels(&self, bids: bool, depth: usize) -> Vec<Level> {
+        let mut quotes: Vec<(f32, f32, &str)> = vec![];
+
+        for v in &self.venues {
+            let price_points = if bids { &v.book.bid_price_points } else { &v.book.ask_price_points };
+
+            for price in price_points {
+                if let Some(size) = v.book.state[*price as usize] {
+                    quotes.push((v.book.real_price(*price), size, v.name.as_str()));
+                }
+            }
+        }
+
+        if bids {
+            quotes.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+        } else {
+            quotes.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+        }
+
+        let tolerance = self.venues.iter()
+            .map(|v| v.book.tick_size)
+            .fold(::std::f32::MAX, f32::min) / 2.0;
+
+        let mut levels: Vec<Level> = vec![];
+
+        for (price, size, venue) in quotes {
+            let merged = match levels.last_mut() {
+                Some(level) if (level.price - price).abs() < tolerance => {
+                    level.size += size;
+                    level.venues.push((venue.into(), size));
+                    true
+                },
+                _ => false,
+            };
+
+            if !merged {
+                if levels.len() == depth {
+                    break;
+                }
+
+                levels.push(Level {
+                    price,
+                    size,
+                    venues: vec![(venue.into(), size)],
+                });
+            }
+        }
+
+        for level in &mut levels {
+            level.venues.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+        }
+
+        levels
+    }
+
+    /// Venue named `venue`, or an error if it isn't part of the consolidated book
+    fn venue_mut(&mut self, venue: &str) -> Result<&mut Venue, Error> {
+        let instrument = self.instrument.to_string();
+
+        self.venues.iter_mut()
+            .find(|v| v.name == venue)
+            .ok_or(Error::new(ErrorKind::NotFound, format!("{} isn't a venue of the {} book", venue, instrument)))
+    }
+}
@@ -0,0 +1,208 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use orderbook::Delta;
+use orderbook::tectonic;
+
+/// Every DTF file starts with these bytes ("DTF9001")
+const MAGIC_VALUE: &[u8] = &[0x44, 0x54, 0x46, 0x90, 0x01];
+/// The database name is stored right after the magic value, padded with spaces to this length
+const SYMBOL_LEN: usize = 20;
+/// Where the amount of updates stored in the file is
+const LEN_OFFSET: u64 = 25;
+/// Where the updates start. The rest of the header is zeroed
+const MAIN_OFFSET: u64 = 80;
+
+/// Marks the start of a batch
+const BATCH_REFERENCE: u8 = 1;
+/// Update is on the bid side
+const FLAG_IS_BID: u8 = 0b01;
+/// Update is a trade
+const FLAG_IS_TRADE: u8 = 0b10;
+
+/// Single update, as TectonicDB stores it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Update {
+    /// Timestamp, in milliseconds
+    pub ts: u64,
+    /// Sequence number
+    pub seq: u32,
+    /// Update is a trade
+    pub is_trade: bool,
+    /// Update is on the bid side
+    pub is_bid: bool,
+    /// Price level
+    pub price: f32,
+    /// Size at the price level (or traded)
+    pub size: f32,
+}
+
+/// Header of a DTF file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    /// Database the file was written for (e.g. `gdax_BTC-USD`)
+    pub symbol: String,
+    /// Amount of updates stored in the file
+    pub nums: u64,
+    /// Timestamp of the earliest update, in milliseconds
+    pub min_ts: u64,
+    /// Timestamp of the latest update, in milliseconds
+    pub max_ts: u64,
+}
+
+/// Reads the header of the DTF file at `path`, without decoding the updates. The earliest timestamp is
+/// taken from the first batch, which starts with it.
+pub fn read_meta(path: &str) -> Result<Metadata, Error> {
+    let mut rdr = BufReader::new(File::open(path)?);
+    let (symbol, nums, max_ts) = read_header(&mut rdr)?;
+
+    let min_ts = if nums > 0 {
+        rdr.read_u8()?;
+        rdr.read_u64::<BigEndian>()?
+    } else {
+        0
+    };
+
+    Ok(Metadata { symbol, nums, min_ts, max_ts })
+}
+
+/// Decodes every update stored in the DTF file at `path`
+pub fn decode(path: &str) -> Result<Vec<Update>, Error> {
+    let mut rdr = BufReader::new(File::open(path)?);
+    let (_, nums, _) = read_header(&mut rdr)?;
+    let mut updates = Vec::with_capacity(nums as usize);
+
+    while (updates.len() as u64) < nums {
+        if rdr.read_u8()? != BATCH_REFERENCE {
+            return Err(invalid(format!("Expected a batch reference in {}", path)));
+        }
+
+        let ref_ts = rdr.read_u64::<BigEndian>()?;
+        let ref_seq = rdr.read_u32::<BigEndian>()?;
+        let count = rdr.read_u16::<BigEndian>()?;
+
+        for _ in 0..count {
+            let ts = ref_ts + rdr.read_u16::<BigEndian>()? as u64;
+            let seq = ref_seq + rdr.read_u8()? as u32;
+            let flags = rdr.read_u8()?;
+
+            updates.push(Update {
+                ts,
+                seq,
+                is_trade: flags & FLAG_IS_TRADE == FLAG_IS_TRADE,
+                is_bid: flags & FLAG_IS_BID == FLAG_IS_BID,
+                price: rdr.read_f32::<BigEndian>()?,
+                size: rdr.read_f32::<BigEndian>()?,
+            });
+        }
+    }
+
+    Ok(updates)
+}
+
+/// Writes `updates` to a new DTF file at `path`, for the database `symbol`. Updates are stored in batches, each
+/// relative to its first update, so a new batch starts whenever an update can't be stored relative to it.
+pub fn encode(path: &str, symbol: &str, updates: &[Update]) -> Result<(), Error> {
+    if symbol.len() > SYMBOL_LEN {
+        return Err(invalid(format!("Database name {} is longer than {} bytes", symbol, SYMBOL_LEN)));
+    }
+
+    let mut wtr = BufWriter::new(File::create(path)?);
+    let max_ts = updates.iter().map(|update| update.ts).max().unwrap_or(0);
+
+    wtr.write_all(MAGIC_VALUE)?;
+    write!(wtr, "{:width$}", symbol, width = SYMBOL_LEN)?;
+    wtr.write_u64::<BigEndian>(updates.len() as u64)?;
+    wtr.write_u64::<BigEndian>(max_ts)?;
+    wtr.seek(SeekFrom::Start(MAIN_OFFSET))?;
+
+    let mut start = 0;
+
+    while start < updates.len() {
+        // The reference itself always fits, so every batch holds at least one update
+        let reference = &updates[start];
+        let count = updates[start..].iter()
+            .take(u16::max_value() as usize)
+            .take_while(|update| update.ts >= reference.ts && update.ts - reference.ts <= u16::max_value() as u64 &&
+                update.seq >= reference.seq && update.seq - reference.seq <= u8::max_value() as u32)
+            .count();
+
+        wtr.write_u8(BATCH_REFERENCE)?;
+        wtr.write_u64::<BigEndian>(reference.ts)?;
+        wtr.write_u32::<BigEndian>(reference.seq)?;
+        wtr.write_u16::<BigEndian>(count as u16)?;
+
+        for update in &updates[start..start + count] {
+            wtr.write_u16::<BigEndian>((update.ts - reference.ts) as u16)?;
+            wtr.write_u8((update.seq - reference.seq) as u8)?;
+
+            let mut flags = 0;
+            if update.is_bid {
+                flags |= FLAG_IS_BID;
+            }
+            if update.is_trade {
+                flags |= FLAG_IS_TRADE;
+            }
+
+            wtr.write_u8(flags)?;
+            wtr.write_f32::<BigEndian>(update.price)?;
+            wtr.write_f32::<BigEndian>(update.size)?;
+        }
+
+        start += count;
+    }
+
+    wtr.flush()
+}
+
+/// Reads every update stored in the DTF file at `path` and converts it to a [`Delta`].
+/// Returns the exchange and symbol the file belongs to (taken from the database name stored
+/// in the file header), along with the deltas.
+///
+/// DTF files store timestamps in milliseconds, whereas deltas use seconds.
+pub fn read_deltas(path: &str) -> Result<(String, String, Vec<Delta>), Error> {
+    let meta = read_meta(path)?;
+    let (exchange, symbol) = tectonic::split_db_name(&meta.symbol);
+
+    let deltas = decode(path)?
+        .into_iter()
+        .map(|update| Delta {
+            symbol: symbol.clone(),
+            price: update.price,
+            size: update.size,
+            seq: update.seq,
+            event: Delta::event_from_flags(update.is_trade, update.is_bid, update.size),
+            ts: update.ts as f64 * 0.001f64,
+        })
+        .collect();
+
+    Ok((exchange, symbol, deltas))
+}
+
+/// Reads the magic value, database name, amount of updates, and latest timestamp, leaving `rdr` at the first batch
+fn read_header<R: Read + Seek>(rdr: &mut R) -> Result<(String, u64, u64), Error> {
+    let mut magic = [0; 5];
+    rdr.read_exact(&mut magic)?;
+
+    if magic != MAGIC_VALUE {
+        return Err(invalid("Not a DTF file".into()));
+    }
+
+    let mut symbol = [0; SYMBOL_LEN];
+    rdr.read_exact(&mut symbol)?;
+    let symbol = String::from_utf8_lossy(&symbol).trim_matches(|c| c == ' ' || c == '\0').to_owned();
+
+    rdr.seek(SeekFrom::Start(LEN_OFFSET))?;
+    let nums = rdr.read_u64::<BigEndian>()?;
+    let max_ts = rdr.read_u64::<BigEndian>()?;
+    rdr.seek(SeekFrom::Start(MAIN_OFFSET))?;
+
+    Ok((symbol, nums, max_ts))
+}
+
+/// DTF files that can't be read, or updates that can't be written
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
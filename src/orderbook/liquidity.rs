@@ -0,0 +1,181 @@
+use std::cmp::Ordering;
+
+use orderbook::{Book, Snapshot};
+
+/// Side of the book
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Side {
+    /// Buy orders
+    Bid,
+    /// Sell orders
+    Ask,
+}
+
+/// Outcome of walking the book to execute a market order
+#[derive(Clone, Debug, PartialEq)]
+pub struct Execution {
+    /// Size asked for
+    pub quantity: f32,
+    /// Size the book could fill. Less than `quantity` if the side ran out of levels
+    pub filled: f32,
+    /// Volume weighted average price of the fill
+    pub vwap: f32,
+    /// Price of the last level reached
+    pub worst_price: f32,
+    /// How much worse the average price is than the mid price, in basis points. Includes half of the spread
+    pub slippage_bps: f32,
+    /// What the fill cost over executing everything at the mid price, in the market asset
+    pub cost: f32,
+}
+
+/// Levels of both sides of a book, best first, at their real prices. This is what depth and liquidity
+/// analytics are computed on, and is built from a [`Snapshot`] or straight from a [`Book`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ladder {
+    /// Bid levels as `(price, size)`, highest first
+    pub bids: Vec<(f32, f32)>,
+    /// Ask levels as `(price, size)`, lowest first
+    pub asks: Vec<(f32, f32)>,
+}
+
+impl Ladder {
+    /// Sorts the levels of a snapshot, leaving out empty levels
+    pub fn from_snapshot(snapshot: &Snapshot) -> Ladder {
+        Ladder::new(snapshot.bids.clone(), snapshot.asks.clone())
+    }
+
+    /// Reads the levels of `book` at their real prices
+    pub fn from_book(book: &Book) -> Ladder {
+        let levels = |price_points: &Vec<u64>| price_points.iter()
+            .filter_map(|price| book.state[*price as usize].map(|size| (book.real_price(*price), size)))
+            .collect();
+
+        Ladder::new(levels(&book.bid_price_points), levels(&book.ask_price_points))
+    }
+
+    /// Sorts `bids` and `asks` best first, leaving out empty levels and levels without a price (NaN)
+    fn new(mut bids: Vec<(f32, f32)>, mut asks: Vec<(f32, f32)>) -> Ladder {
+        bids.retain(|level| level.1 > 0.0 && !level.0.is_nan());
+        asks.retain(|level| level.1 > 0.0 && !level.0.is_nan());
+
+        bids.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+        asks.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+
+        Ladder {
+            bids,
+            asks,
+        }
+    }
+
+    /// Levels of `side`, best first
+    pub fn side(&self, side: Side) -> &[(f32, f32)] {
+        match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        }
+    }
+
+    /// Gets mid price (i.e. `(best_ask + best_bid) / 2`). `None` if either side is empty
+    pub fn mid_price(&self) -> Option<f32> {
+        match (self.bids.first(), self.asks.first()) {
+            (Some(bid), Some(ask)) => Some((bid.0 + ask.0) / 2.0),
+            _ => None,
+        }
+    }
+
+    /// Total size of the best `levels` levels of `side`
+    pub fn depth(&self, side: Side, levels: usize) -> f32 {
+        self.side(side).iter()
+            .take(levels)
+            .map(|level| level.1)
+            .sum()
+    }
+
+    /// Total size of `side` quoted within `bps` basis points of the mid price. Zero if either side is empty
+    pub fn depth_within(&self, side: Side, bps: f32) -> f32 {
+        let mid = match self.mid_price() {
+            Some(mid) => mid,
+            None => return 0.0,
+        };
+        let distance = mid * bps / 10_000.0;
+
+        self.side(side).iter()
+            .take_while(|level| (level.0 - mid).abs() <= distance)
+            .map(|level| level.1)
+            .sum()
+    }
+
+    /// Depth curve of `side`: every level's price along with the total size up to and including it
+    pub fn cumulative_depth(&self, side: Side) -> Vec<(f32, f32)> {
+        self.side(side).iter()
+            .scan(0.0, |total, level| {
+                *total += level.1;
+                Some((level.0, *total))
+            })
+            .collect()
+    }
+
+    /// Walks the asks to buy `quantity`. `None` if the book is missing either side
+    pub fn buy(&self, quantity: f32) -> Option<Execution> {
+        self.execute(Side::Ask, quantity)
+    }
+
+    /// Walks the bids to sell `quantity`. `None` if the book is missing either side
+    pub fn sell(&self, quantity: f32) -> Option<Execution> {
+        self.execute(Side::Bid, quantity)
+    }
+
+    /// Fills `quantity` against the levels of `side`, best first
+    fn execute(&self, side: Side, quantity: f32) -> Option<Execution> {
+        let mid = self.mid_price()?;
+
+        let mut filled = 0.0;
+        let mut notional = 0.0;
+        let mut worst_price = mid;
+
+        for &(price, size) in self.side(side) {
+            if filled >= quantity {
+                break;
+            }
+
+            let fill = size.min(quantity - filled);
+            filled += fill;
+            notional += fill * price;
+            worst_price = price;
+        }
+
+        if filled == 0.0 {
+            return None;
+        }
+
+        let vwap = notional / filled;
+
+        // Buying above the mid price and selling below it are both a cost
+        let cost = match side {
+            Side::Ask => notional - mid * filled,
+            Side::Bid => mid * filled - notional,
+        };
+
+        Some(Execution {
+            quantity,
+            filled,
+            vwap,
+            worst_price,
+            slippage_bps: cost / (mid * filled) * 10_000.0,
+            cost,
+        })
+    }
+
+    /// Order book imbalance over the best `levels` levels: `(bids - asks) / (bids + asks)`, from -1 (only asks)
+    /// to 1 (only bids). `None` if both sides are empty
+    pub fn imbalance(&self, levels: usize) -> Option<f32> {
+        let bids = self.depth(Side::Bid, levels);
+        let asks = self.depth(Side::Ask, levels);
+
+        if bids + asks == 0.0 {
+            return None;
+        }
+
+        Some((bids - asks) / (bids + asks))
+    }
+}
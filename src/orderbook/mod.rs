@@ -2,7 +2,14 @@ use chrono::prelude::*;
 //use ndarray;
 use rayon::prelude::*;
 use exchange::Asset;
-
+use orderbook::liquidity::Ladder;
+
+/// Order books merged across exchanges
+pub mod consolidated;
+/// Reads and writes the DTF files TectonicDB stores its databases in
+pub mod dtf;
+/// Depth and liquidity analytics
+pub mod liquidity;
 /// TectonicDB client bindings
 pub mod tectonic;
 
@@ -39,6 +46,46 @@ pub struct Delta {
 
 }
 
+impl Delta {
+    /// Rebuilds the `event` flags from the fields TectonicDB (and DTF files) store. The original event
+    /// type is lost on insertion, so non-trade events are marked as `REMOVE` if the size is zero and `UPDATE` otherwise.
+    pub fn event_from_flags(is_trade: bool, is_bid: bool, size: f32) -> u8 {
+        let side = if is_bid { BID } else { ASK };
+
+        if is_trade {
+            side | TRADE
+        } else if size == 0.0 {
+            side | REMOVE
+        } else {
+            side | UPDATE
+        }
+    }
+    /// Returns `true` if this is a bid side event
+    pub fn is_bid(&self) -> bool {
+        self.event & BID == BID
+    }
+    /// Returns `true` if this is a trade event
+    pub fn is_trade(&self) -> bool {
+        self.event & TRADE == TRADE
+    }
+    /// Order side decoded from `event` as text: "bid" or "ask"
+    pub fn side(&self) -> &'static str {
+        if self.is_bid() { "bid" } else { "ask" }
+    }
+    /// Event type decoded from `event` as text: "trade", "insert", "remove", or "update"
+    pub fn event_type(&self) -> &'static str {
+        if self.is_trade() {
+            "trade"
+        } else if self.event & INSERT == INSERT {
+            "insert"
+        } else if self.event & REMOVE == REMOVE {
+            "remove"
+        } else {
+            "update"
+        }
+    }
+}
+
 /// Before we can start applying deltas, we must have a snapshot to build off of. This is the initial state of the
 /// orderbook that we build off of, and will use to analyze the orderbook.
 #[derive(Clone)]
@@ -54,6 +101,13 @@ pub struct Snapshot {
     pub asks: Vec<(f32, f32)>,
 }
 
+impl Snapshot {
+    /// Sorted levels of the snapshot, for depth and liquidity analytics
+    pub fn ladder(&self) -> Ladder {
+        Ladder::from_snapshot(self)
+    }
+}
+
 /// Orderbook state and related fields. This struct encodes all information related to the orderbook 
 /// that we maintain. A few fields have been added for performance reasons and convienience, such as `best_bid`,
 /// `best_bid_size`, `best_ask`, `best_ask_size`. 
@@ -123,12 +177,12 @@ impl Book {
     pub fn initialize(&mut self, snapshot: &Snapshot) {
         let mut bids: Vec<(u64, f32)> = snapshot.bids
             .iter()
-            .map(|bid| ((bid.0 / self.tick_size) as u64, bid.1))
+            .map(|bid| (self.price_index(bid.0), bid.1))
             .collect();
 
         let mut asks: Vec<(u64, f32)> = snapshot.asks
             .iter()
-            .map(|ask| ((ask.0 / self.tick_size) as u64, ask.1))
+            .map(|ask| (self.price_index(ask.0), ask.1))
             .collect();
 
         // Run these here because they return nothing.
@@ -185,98 +239,69 @@ impl Book {
                 // the ask side of the orderbook (in most cases).
 
                 if *size == 0.0 {
-                    // Process cancelation event. We can't ensure that an option will
-                    // be put in place, so we have to take it upon ourselves to see that it will.
-                    if *price == self.best_bid {
-                        // Walk backwards to find the next best bid information.
-                        // But first, let's sort the "bid_price_point" vector and get the result below the best bid
-                        self.bid_price_points.sort();
+                    // Void the level, and forget about its price
+                    self.state[price_usize] = None;
+                    self.bid_price_points.retain(|p| *p != *price);
 
-                        let level_price = self.bid_price_points[self.bid_price_points.len() - 2];
-                        let bid_level_size = self.state[level_price as usize];
-
-                        self.best_bid = level_price;
-                        self.best_bid_size = bid_level_size.unwrap();
-
-                        // Remove the best bid from price points
-                        self.bid_price_points.pop();
-
-                        // Void the best level bid after having handled best-bid updates (if any)
-                        self.state[price_usize] = None;
-
-                    } else {
-                        // Void the best level bid after having handled best-bid updates (if any)
-                        self.state[price_usize] = None;
-                        self.bid_price_points.remove_item(price);
+                    if *price == self.best_bid {
+                        // Walk backwards to find the next best bid. The side is empty if there's none left
+                        match self.bid_price_points.iter().max() {
+                            Some(level_price) => {
+                                self.best_bid = *level_price;
+                                self.best_bid_size = self.state[*level_price as usize].unwrap_or(0.0);
+                            },
+                            None => self.best_bid_size = 0.0,
+                        }
                     }
 
                 } else {
                     // Updates limit order
+                    self.state[price_usize] = Some(*size);
 
-                    let new_size = Some(*size);
-                    self.state[price_usize] = new_size;
-
-                    // Check for duplicates before adding anything to the vector
+                    // Check for duplicates before adding anything to the vector. The first level of an empty side
+                    // is the best one, whatever the previous best was
+                    let was_empty = self.bid_price_points.is_empty();
                     if !self.bid_price_points.iter().any(|p| *p == *price) {
                         self.bid_price_points.push(*price);
                     }
 
-                    if *price == self.best_bid {
-                        // Update the `best_bid_size` member to the new size
-                        self.best_bid_size = new_size.unwrap();
-                        return
-                    }
-                    if *price > self.best_bid {
+                    if was_empty || *price >= self.best_bid {
                         // If price greater than the best bid, we have a new best bid.
                         self.best_bid = *price;
-                        self.best_bid_size = new_size.unwrap();
+                        self.best_bid_size = *size;
                     }
                 }
             } else {
                 // Handle ask order here
                 if *size == 0.0 {
-                    // Process cancelation event. We can't ensure that an option will
-                    // be put in place, so we have to take it upon ourselves to see that it will.
+                    // Void the level, and forget about its price
+                    self.state[price_usize] = None;
+                    self.ask_price_points.retain(|p| *p != *price);
+
                     if *price == self.best_ask {
-                        // First, sort the `ask_price_points` vector
-                        self.ask_price_points.sort();
-                        
-                        let level_price = self.ask_price_points[1];
-                        
-                        self.best_ask = level_price;
-                        self.best_ask_size= self.state[level_price as usize].unwrap();
-
-                        // TODO: This may be inefficient...
-                        self.ask_price_points = self.ask_price_points[1..].to_vec();
-
-                        // Void the best level bid after having handled best-bid updates (if any)
-                        self.state[price_usize] = None;
-
-                    } else {
-                        // Void the best level bid after having handled best-bid updates (if any)
-                        self.state[price_usize] = None;
-                        self.ask_price_points.remove_item(price);
+                        // Walk forwards to find the next best ask. The side is empty if there's none left
+                        match self.ask_price_points.iter().min() {
+                            Some(level_price) => {
+                                self.best_ask = *level_price;
+                                self.best_ask_size = self.state[*level_price as usize].unwrap_or(0.0);
+                            },
+                            None => self.best_ask_size = 0.0,
+                        }
                     }
 
                 } else {
                     // Updates limit order. Make sure to handle `best_ask` case scenario.
-                    let new_size = Some(*size);
-
-                    self.state[price_usize] = new_size;
+                    self.state[price_usize] = Some(*size);
 
+                    let was_empty = self.ask_price_points.is_empty();
                     if !self.ask_price_points.iter().any(|p| *p == *price) {
                         self.ask_price_points.push(*price);
                     }
 
-                    if *price == self.best_ask {
-                        // Update the `best_bid_size` member to the new size
-                        self.best_ask_size = new_size.unwrap();
-                        return
-                    }
-                    if *price < self.best_ask {
+                    if was_empty || *price <= self.best_ask {
                         // If our current ask's price is less than the best, then that becomes the new best ask.
                         self.best_ask = *price;
-                        self.best_ask_size = new_size.unwrap();
+                        self.best_ask_size = *size;
                     }
                 }
             }
@@ -285,7 +310,8 @@ impl Book {
 
     /// Returns a snapshot of the orderbook at the current state. This is very useful for analyzing the orderbook
     /// as it evolves. From snapshot, we can then begin to transform the snapshot into a more meaningful format more
-    /// suitable for analysis, such as `SnapshotAnalysis`.
+    /// suitable for analysis, such as `SnapshotAnalysis`. Levels are at their real prices, like those the book was
+    /// initialized from, rather than array indexes.
     /// TODO: Consider just making it return a `SnapshotAnalysis` from the get-go instead
     pub fn get_snapshot(&self) -> Snapshot {
         Snapshot {
@@ -295,7 +321,7 @@ impl Book {
             bids: { let bids: Vec<(f32, f32)> = self.bid_price_points[..]
                 .par_iter()
                 .map(|level_price| {
-                    (*level_price as f32, self.state[*level_price as usize].unwrap_or(0.0))
+                    (self.real_price(*level_price), self.state[*level_price as usize].unwrap_or(0.0))
                 })
                 .collect();
 
@@ -305,7 +331,7 @@ impl Book {
             asks: { let asks: Vec<(f32, f32)> = self.ask_price_points[..]
                 .par_iter()
                 .map(|level_price| {
-                    (*level_price as f32, self.state[*level_price as usize].unwrap_or(0.0))
+                    (self.real_price(*level_price), self.state[*level_price as usize].unwrap_or(0.0))
                 })
                 .collect();
 
@@ -318,6 +344,11 @@ impl Book {
     pub fn real_price(&self, fake_price: u64) -> f32 {
         fake_price as f32 * self.tick_size
     }
+    /// Array index of a real price, rounded to the nearest tick (i.e. the opposite of `real_price`).
+    /// Rounding rather than truncating keeps prices such as `0.3 / 0.1 = 2.9999998` on the right level
+    pub fn price_index(&self, price: f32) -> u64 {
+        (price / self.tick_size).round() as u64
+    }
     /// Gets bid-ask spread (i.e. `best_ask - best_bid`)
     pub fn bid_ask_spread(&self) -> f32 {
         self.real_price(self.best_ask) - self.real_price(self.best_bid)
@@ -334,4 +365,8 @@ impl Book {
     pub fn ask_relative_price(&self, price: f32) -> f32 {
         price - self.real_price(self.best_ask)
     }
+    /// Current levels of the book at their real prices, for depth and liquidity analytics
+    pub fn ladder(&self) -> Ladder {
+        Ladder::from_book(self)
+    }
 }
\ No newline at end of file
@@ -1,10 +1,22 @@
 use std::net::TcpStream;
-use std::io::{Error, Read, Write};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Write};
+use std::ops::{Deref, DerefMut};
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
 use std::time::Duration;
-use std::str;
+
+use serde_json;
 
 use orderbook::{self, Delta};
 
+/// Maximum amount of idle connections a [`TectonicPool`] will hold on to
+const MAX_IDLE_CONNECTIONS: usize = 8;
+/// How long commands wait on a reply before giving up on the server
+const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+/// Time to wait before polling a subscription again after the server had nothing new for us
+const SUBSCRIPTION_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
 /// Contains all fields necessary for a successful connection to TectonicDB.
 pub struct TectonicConnection {
     /// TectonicDB host
@@ -14,54 +26,145 @@ pub struct TectonicConnection {
     
     /// TCP client connection for internal use
     pub connection: TcpStream,
+    /// Reads replies off `connection` a line at a time
+    reader: BufReader<TcpStream>,
 
     /// Currently selected database
     pub db: Option<String>,
 }
 
 impl TectonicConnection {
-    /// Clones the structure
-    pub fn clone(&self) -> Self {
-        Self {
-            host: self.host.clone(),
-            port: self.port.clone(),
-
-            connection: self.connection.try_clone().expect("Failed to clone tectonic TcpStream"),
+    /// Opens a brand new connection to the same server. Unlike `TcpStream::try_clone`, the
+    /// returned connection does not share a socket with `self`, so both can be written to at once.
+    pub fn try_clone(&self) -> Result<Self, Error> {
+        let mut connection = TectonicConnection::new(Some(self.host.clone()), Some(self.port))?;
 
-            db: Some(self.db.as_ref().unwrap_or(&String::from("")).clone())
+        if let Some(db_name) = self.db.clone() {
+            connection.use_db(db_name)?;
         }
+
+        Ok(connection)
     }
     /// Creates a new TectonicDB connection. If no host or port are provided, the connection defaults to `localhost:9001`
     pub fn new(host: Option<String>, port: Option<u16>) -> Result<TectonicConnection, Error>{
         let host = host.unwrap_or("127.0.0.1".into());
         let port = port.unwrap_or(9001);
 
-        let connect_address = format!("{}:{}", host, port);
-
-        // Set socket timeout to 1s
-        let connection = TcpStream::connect_timeout(&connect_address.parse().unwrap(), Duration::new(1,0))?;
-        // Resolves issue #1. Please remove this comment if this line is changed
-        let _ = connection.set_read_timeout(Some(Duration::new(1, 0)));
+        let connection = Self::connect(&host, port)?;
+        let reader = BufReader::new(connection.try_clone()?);
 
         return Ok(TectonicConnection {
             host,
             port,
 
             connection,
+            reader,
 
             db: None,
         })
     }
+    /// Opens the TCP stream to the TectonicDB server
+    fn connect(host: &String, port: u16) -> Result<TcpStream, Error> {
+        let connect_address = format!("{}:{}", host, port);
+
+        // Set socket timeout to 1s
+        let connection = TcpStream::connect_timeout(&connect_address.parse().unwrap(), Duration::new(1,0))?;
+        // Resolves issue #1. Please remove this comment if this line is changed
+        let _ = connection.set_read_timeout(Some(REPLY_TIMEOUT));
+
+        Ok(connection)
+    }
+    /// Drops the current socket and connects to the server again. This is used to recover
+    /// from TectonicDB restarts without having to rebuild the whole connection structure.
+    /// The database we were using is selected again, as the server forgets it along with the old socket.
+    pub fn reconnect(&mut self) -> Result<(), Error> {
+        self.connection = Self::connect(&self.host, self.port)?;
+        self.reader = BufReader::new(self.connection.try_clone()?);
 
-    /// Sends a message to the TectonicDB server
+        if let Some(db_name) = self.db.clone() {
+            self.use_db(db_name)?;
+        }
+
+        Ok(())
+    }
+    /// Selects the database `db_name`, which is what commands without `INTO` work on. It stays selected across reconnects
+    pub fn use_db(&mut self, db_name: String) -> Result<String, Error> {
+        // Not through `cmd`, as reconnecting selects the database again
+        let reply = self.send(&format!("USE {}", db_name))?;
+
+        if reply.starts_with("ERR") {
+            return Err(Error::new(ErrorKind::Other, reply.trim().to_owned()))
+        }
+
+        self.db = Some(db_name);
+        Ok(reply)
+    }
+    /// Health check. Returns `true` if the server answers our `PING`
+    pub fn is_alive(&mut self) -> bool {
+        match self.ping() {
+            Ok(reply) => reply.starts_with("PONG"),
+            Err(_) => false,
+        }
+    }
+
+    /// Sends a message to the TectonicDB server. If the server went away (i.e. broken pipe), we reconnect
+    /// so that the next message goes through, and send the message again unless it only makes sense on the
+    /// connection it started on (see [`is_repeatable`]). An insert that reached the server right before it went
+    /// away may be stored twice this way, but none are lost.
+    ///
+    /// If the server doesn't reply in time, we also reconnect, so that a late reply isn't taken for the reply
+    /// to the next message, and fail with `TimedOut`.
     pub fn cmd(&mut self, message: String) -> Result<String, Error> { 
-        // Convert the message into bytes using the `.as_bytes()` method
-        let _ = self.connection.write(format!("{}\n", message).as_bytes());
+        match self.send(&message) {
+            Err(e) => if is_disconnect(&e) {
+                println!("Lost connection to TectonicDB ({}). Reconnecting...", e);
 
-        let mut buf = [0; 256];
-        let _ = self.connection.read(&mut buf);
+                self.reconnect()?;
+
+                if is_repeatable(&message) {
+                    self.send(&message)
+                } else {
+                    Err(e)
+                }
+            } else if is_timeout(&e) {
+                self.reconnect()?;
+                Err(e)
+            } else {
+                Err(e)
+            },
+            result => result,
+        }
+    }
+    /// Writes a single message to the socket and reads back the reply. Fails with `TimedOut` if none arrives in time
+    fn send(&mut self, message: &String) -> Result<String, Error> {
+        self.write_line(message)?;
 
-        Ok(str::from_utf8(&buf).unwrap().to_owned())
+        self.read_reply()?
+            .ok_or_else(|| Error::new(ErrorKind::TimedOut, format!("TectonicDB didn't reply to \"{}\"", message)))
+    }
+    /// Writes a single message to the socket, without waiting on a reply. Only for messages that don't get one
+    fn write_line(&mut self, message: &str) -> Result<(), Error> {
+        // Convert the message into bytes using the `.as_bytes()` method
+        self.connection.write_all(format!("{}\n", message).as_bytes())
+    }
+    /// Reads the next reply, up to and including its newline. Returns `None` if nothing arrived before
+    /// the read timeout. Replies that started arriving are always read to the end, however long they are.
+    fn read_reply(&mut self) -> Result<Option<String>, Error> {
+        let mut reply = vec![];
+
+        loop {
+            match self.reader.read_until(b'\n', &mut reply) {
+                // A zero length read means that the server has closed the connection on us
+                Ok(0) => return Err(Error::new(ErrorKind::UnexpectedEof, "TectonicDB closed the connection")),
+                Ok(_) => if reply.ends_with(b"\n") {
+                    return Ok(Some(String::from_utf8_lossy(&reply).into_owned()));
+                },
+                Err(ref e) if is_timeout(e) => if reply.is_empty() {
+                    return Ok(None);
+                },
+                Err(e) => return Err(e),
+            }
+        }
     }
     /// Return help dialog
     pub fn help(&mut self) -> Result<String, Error> {
@@ -111,32 +214,149 @@ impl TectonicConnection {
     }
     /// Bulk-add deltas to the tectonic server
     pub fn bulk_add(&mut self, deltas: &Vec<Delta>) -> Result<String, Error> {
-        let _ = self.cmd("BULKADD".into());
+        self.bulk_add_with("BULKADD".into(), deltas)
+    }
+    /// Bulk-add deltas into a specified database `db_name`
+    pub fn bulk_add_into(&mut self, db_name: String, deltas: &Vec<Delta>) -> Result<String, Error> {
+        self.bulk_add_with(format!("BULKADD INTO {}", db_name), deltas)
+    }
+    /// Sends `header`, a row per delta, and the end of the bulk add. If the server goes away midway, we reconnect
+    /// and send the whole bulk add again, as the rows are meaningless on a connection that didn't start it.
+    fn bulk_add_with(&mut self, header: String, deltas: &Vec<Delta>) -> Result<String, Error> {
+        match self.bulk_add_once(&header, deltas) {
+            Err(e) => if is_disconnect(&e) {
+                println!("Lost connection to TectonicDB ({}). Reconnecting...", e);
 
-        for event in deltas {
-            let is_trade: String = if event.event & orderbook::TRADE == orderbook::TRADE {"t".into()} else {"f".into()};
-            let is_bid: String = if event.event & orderbook::BID == orderbook::BID {"t".into()} else {"f".into()};
+                self.reconnect()?;
+                self.bulk_add_once(&header, deltas)
+            } else {
+                Err(e)
+            },
+            result => result,
+        }
+    }
+    /// Only the end of a bulk add gets a reply, so the header and rows are written without waiting on one
+    fn bulk_add_once(&mut self, header: &str, deltas: &Vec<Delta>) -> Result<String, Error> {
+        self.write_line(header)?;
 
-            let _ = self.cmd(format!("{:.3}, {}, {}, {}, {}, {};", event.ts, event.seq, is_trade, is_bid, event.price, event.size));
+        for event in deltas {
+            self.write_line(&format!("{:.3}, {}, {}, {}, {}, {};",
+                event.ts,
+                event.seq,
+                if event.event & orderbook::TRADE == orderbook::TRADE {"t"} else {"f"},
+                if event.event & orderbook::BID == orderbook::BID {"t"} else {"f"},
+                event.price,
+                event.size))?;
         }
 
-        self.cmd("DDAKLUB".into())
+        self.send(&"DDAKLUB".into())
     }
-    /// Bulk-add deltas into a specified database `db_name`
-    pub fn bulk_add_into(&mut self, db_name: String, deltas: &Vec<Delta>) -> Result<String, Error> {
-        let _ = self.cmd(format!("BULKADD INTO {}", db_name));
+    /// Subscribes to the inserts happening on database `db_name`. The subscription gets its own
+    /// connection to the server (a subscribed connection can't be used for anything else), and a
+    /// background thread polls it and forwards every update as a [`Delta`]. TectonicDB buffers the inserts
+    /// of subscribed databases, and hands them over in reply to every message the subscriber sends.
+    ///
+    /// Updates that can't be parsed are sent on as errors, after which the subscription ends, as we can't
+    /// tell what we missed. If the server goes away, we reconnect and subscribe again. Polling stops once
+    /// the [`Subscription`] is dropped.
+    ///
+    /// The symbol of the deltas is taken from the database name (see [`split_db_name`]).
+    pub fn subscribe(&self, db_name: String) -> Result<Subscription, Error> {
+        let mut connection = self.try_clone()?;
+        let subscribe_cmd = format!("SUBSCRIBE {}", db_name);
+        connection.subscribe_once(&subscribe_cmd)?;
 
-        for event in deltas {
-            let _ = self.cmd(format!("{:.3}, {}, {}, {}, {}, {};", 
-                event.ts, 
-                event.seq, 
-                if event.event & orderbook::TRADE == orderbook::TRADE {String::from("t")} else {String::from("f")},
-                if event.event & orderbook::BID == orderbook::BID {String::from("t")} else {String::from("f")},
-                event.price, 
-                event.size));
+        let symbol = split_db_name(&db_name).1;
+        let (tx, rx) = mpsc::channel();
+        let active = Arc::new(AtomicBool::new(true));
+        let polling = active.clone();
+
+        thread::spawn(move || {
+            // Set once we've subscribed again, and cleared by the next successful poll. Failing to
+            // poll right after subscribing again means the server is gone for good
+            let mut resubscribed = false;
+
+            // Nobody is listening anymore, so we can stop polling
+            while polling.load(Ordering::SeqCst) {
+                let polled = connection.poll().and_then(|reply| if reply.starts_with("ERR") {
+                    // We were most likely reconnected to a restarted server, which forgot about us
+                    Err(Error::new(ErrorKind::NotConnected, reply.trim().to_owned()))
+                } else {
+                    Ok(reply)
+                });
+
+                let reply = match polled {
+                    Ok(reply) => {
+                        resubscribed = false;
+                        reply
+                    },
+                    Err(ref e) if is_disconnect(e) && !resubscribed => {
+                        println!("Lost TectonicDB subscription to {} ({}). Subscribing again...", subscribe_cmd, e);
+                        resubscribed = true;
+
+                        let resubscribe = connection.reconnect()
+                            .and_then(|_| connection.subscribe_once(&subscribe_cmd));
+
+                        match resubscribe {
+                            Ok(_) => continue,
+                            Err(e) => {
+                                let _ = tx.send(Err(e));
+                                return;
+                            }
+                        }
+                    },
+                    Err(e) => {
+                        let _ = tx.send(Err(e));
+                        return;
+                    }
+                };
+                let reply = reply.trim();
+
+                if reply.is_empty() || reply.starts_with("NONE") {
+                    thread::sleep(SUBSCRIPTION_POLL_INTERVAL);
+                    continue;
+                }
+
+                let updates = match parse_updates(reply) {
+                    Ok(updates) => updates,
+                    Err(e) => {
+                        let _ = tx.send(Err(e));
+                        return;
+                    }
+                };
+
+                for update in updates {
+                    // Receiver was dropped. Nobody is listening anymore, so we can stop polling
+                    if tx.send(Ok(update.into_delta(&symbol))).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        Ok(Subscription {
+            db_name,
+            deltas: rx,
+            active,
+        })
+    }
+    /// Asks a subscribed connection for the inserts buffered since the last poll. The server answers with
+    /// `NONE` if there are none. A poll that isn't answered in time is taken to have found nothing, and its reply
+    /// is read by the next poll.
+    fn poll(&mut self) -> Result<String, Error> {
+        self.connection.write_all(b"\n")?;
+
+        Ok(self.read_reply()?.unwrap_or_default())
+    }
+    /// Sends `SUBSCRIBE`, failing if the server refuses it
+    fn subscribe_once(&mut self, subscribe_cmd: &str) -> Result<(), Error> {
+        let reply = self.cmd(subscribe_cmd.into())?;
+
+        if reply.starts_with("ERR") {
+            return Err(Error::new(ErrorKind::Other, reply.trim().to_owned()))
         }
 
-        self.cmd("DDAKLUB".into())
+        Ok(())
     }
     /// Create new database `db_name`
     pub fn create(&mut self, db_name: String) -> Result<String, Error> {
@@ -152,8 +372,19 @@ impl TectonicConnection {
             delta.price, 
             delta.size))
     }
-    /// Insert into the database `db_name`
+    /// Insert into the database `db_name`. If the database doesn't exist (e.g. TectonicDB was restarted
+    /// and lost it), we create it and try the insert once more. Other errors are returned as they are.
     pub fn insert_into(&mut self, db_name: String, delta: &Delta) -> Result<String, Error> {
+        let reply = self.insert_into_once(&db_name, delta)?;
+
+        if !is_missing_db(&reply) {
+            return Ok(reply)
+        }
+
+        let _ = self.create(db_name.clone())?;
+        self.insert_into_once(&db_name, delta)
+    }
+    fn insert_into_once(&mut self, db_name: &String, delta: &Delta) -> Result<String, Error> {
         self.cmd(format!("INSERT {:.3}, {}, {}, {}, {}, {}; INTO {}", 
             delta.ts, 
             delta.seq, 
@@ -165,17 +396,209 @@ impl TectonicConnection {
     }
 }
 
-impl Clone for TectonicConnection {
-    fn clone(&self) -> Self {
-        Self {
-            host: self.host.clone(),
-            port: self.port.clone(), 
+/// Single insert as TectonicDB sends it to subscribers
+#[derive(Deserialize)]
+struct SubscriptionUpdate {
+    ts: f64,
+    seq: u32,
+    is_trade: bool,
+    is_bid: bool,
+    price: f32,
+    size: f32,
+}
 
-            connection: self.connection
-                .try_clone()
-                .expect("Failed to clone Tectonic TCP Connection"),
+/// Parses a line pushed to subscribers, which holds either a single update or an array of them
+fn parse_updates(reply: &str) -> Result<Vec<SubscriptionUpdate>, Error> {
+    serde_json::from_str::<Vec<SubscriptionUpdate>>(reply)
+        .or_else(|_| serde_json::from_str::<SubscriptionUpdate>(reply).map(|update| vec![update]))
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("Invalid TectonicDB subscription update \"{}\": {}", reply, e)))
+}
+
+impl SubscriptionUpdate {
+    /// Converts the update back into a [`Delta`]
+    fn into_delta(self, symbol: &String) -> Delta {
+        Delta {
+            symbol: symbol.clone(),
+            price: self.price,
+            size: self.size,
+            seq: self.seq,
+            event: Delta::event_from_flags(self.is_trade, self.is_bid, self.size),
+            ts: self.ts,
+        }
+    }
+}
+
+/// Live stream of the deltas inserted into a TectonicDB database. Created with [`TectonicConnection::subscribe`].
+/// Iterating over the subscription blocks until the next delta arrives. The subscription ends with an error
+/// once the server connection is lost for good or sends something we can't read. Dropping it stops the polling thread.
+pub struct Subscription {
+    /// Database we're subscribed to
+    pub db_name: String,
+    /// Deltas received by the subscription thread
+    deltas: mpsc::Receiver<Result<Delta, Error>>,
+    /// Cleared once the subscription is dropped, which tells the polling thread to stop
+    active: Arc<AtomicBool>,
+}
+
+impl Subscription {
+    /// Waits up to `timeout` for the next delta. Useful if you'd rather not block for good like iterating does.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Result<Delta, Error>, mpsc::RecvTimeoutError> {
+        self.deltas.recv_timeout(timeout)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.active.store(false, Ordering::SeqCst);
+    }
+}
+
+impl Iterator for Subscription {
+    type Item = Result<Delta, Error>;
+
+    fn next(&mut self) -> Option<Result<Delta, Error>> {
+        self.deltas.recv().ok()
+    }
+}
+
+/// Splits a database name into its exchange and symbol. Databases are named `exchange_symbol`
+/// (e.g. `gdax_BTC-USD`), so the symbol is everything after the first underscore.
+pub fn split_db_name(db_name: &str) -> (String, String) {
+    match db_name.find('_') {
+        Some(idx) => (db_name[..idx].to_owned(), db_name[idx + 1..].to_owned()),
+        None => (String::new(), db_name.to_owned()),
+    }
+}
+
+/// Returns `true` if `reply` is TectonicDB telling us the database we wrote to doesn't exist
+/// (i.e. `ERR: DB gdax_BTC-USD not found.`)
+fn is_missing_db(reply: &str) -> bool {
+    reply.starts_with("ERR") && reply.contains("not found")
+}
+
+/// Returns `true` if `message` can be sent again on a new connection. Bulk adds can't, as their rows are
+/// meaningless on a connection that didn't start them. They're sent again as a whole instead.
+fn is_repeatable(message: &str) -> bool {
+    match message.split_whitespace().next().unwrap_or("") {
+        "BULKADD" | "DDAKLUB" => false,
+        // Rows of a bulk add start with their timestamp
+        word => !word.starts_with(|c: char| c.is_digit(10)),
+    }
+}
+
+/// Returns `true` if the error is a read timing out
+fn is_timeout(e: &Error) -> bool {
+    e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut
+}
+
+/// Returns `true` if the error means that the server has gone away
+fn is_disconnect(e: &Error) -> bool {
+    match e.kind() {
+        ErrorKind::BrokenPipe |
+        ErrorKind::ConnectionReset |
+        ErrorKind::ConnectionAborted |
+        ErrorKind::NotConnected |
+        ErrorKind::UnexpectedEof => true,
+        _ => false,
+    }
+}
+
+/// Pool of TectonicDB connections. Every handle checked out of the pool owns its own `TcpStream`,
+/// so exchanges and listeners running on separate threads never interleave bytes on one socket.
+/// Cloning the pool is cheap, and all clones share the same idle connections.
+#[derive(Clone)]
+pub struct TectonicPool {
+    /// TectonicDB host
+    host: String,
+    /// Port
+    port: u16,
+
+    /// Connections that have been handed back to the pool and can be reused
+    idle: Arc<Mutex<Vec<TectonicConnection>>>,
+}
+
+impl TectonicPool {
+    /// Creates a new pool. We open the first connection right away so that we fail early if
+    /// the server can't be reached. Host and port defaults are the same as [`TectonicConnection::new`]
+    pub fn new(host: Option<String>, port: Option<u16>) -> Result<TectonicPool, Error> {
+        let connection = TectonicConnection::new(host, port)?;
+
+        Ok(TectonicPool {
+            host: connection.host.clone(),
+            port: connection.port,
+
+            idle: Arc::new(Mutex::new(vec![connection])),
+        })
+    }
+    /// Creates a new pool without connecting. The first connection is opened once it's checked out.
+    pub fn lazy(host: Option<String>, port: Option<u16>) -> TectonicPool {
+        TectonicPool {
+            host: host.unwrap_or("127.0.0.1".into()),
+            port: port.unwrap_or(9001),
+
+            idle: Arc::new(Mutex::new(vec![])),
+        }
+    }
+    /// Checks a connection out of the pool. Idle connections are health checked with a `PING`
+    /// before being handed out, and a new connection is opened if none of them are usable.
+    pub fn get(&self) -> Result<PooledConnection, Error> {
+        loop {
+            // Don't hold the lock while we're waiting on the server to answer our ping
+            let idle = self.idle.lock().unwrap().pop();
+
+            match idle {
+                Some(mut connection) => if connection.is_alive() {
+                    return Ok(PooledConnection {
+                        connection: Some(connection),
+                        pool: self.clone(),
+                    })
+                },
+                None => break,
+            }
+        }
+
+        Ok(PooledConnection {
+            connection: Some(TectonicConnection::new(Some(self.host.clone()), Some(self.port))?),
+            pool: self.clone(),
+        })
+    }
+    /// Hands a connection back to the pool. Connections are dropped if we already have enough idle ones.
+    fn put(&self, connection: TectonicConnection) {
+        let mut idle = self.idle.lock().unwrap();
+
+        if idle.len() < MAX_IDLE_CONNECTIONS {
+            idle.push(connection);
+        }
+    }
+}
+
+/// Connection checked out of a [`TectonicPool`]. Dereferences to [`TectonicConnection`], and
+/// returns the connection to the pool once dropped.
+pub struct PooledConnection {
+    /// Always `Some` until the handle is dropped
+    connection: Option<TectonicConnection>,
+    /// Pool we return the connection to
+    pool: TectonicPool,
+}
 
-            db: self.db.clone(),
+impl Deref for PooledConnection {
+    type Target = TectonicConnection;
+
+    fn deref(&self) -> &TectonicConnection {
+        self.connection.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledConnection {
+    fn deref_mut(&mut self) -> &mut TectonicConnection {
+        self.connection.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            self.pool.put(connection);
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::fs::{create_dir_all, File, OpenOptions};
+use std::io::{BufWriter, Error, ErrorKind, Write};
+use std::path::PathBuf;
+
+use chrono::prelude::*;
+use serde_json;
+
+use config::UploadConfig;
+use orderbook::Delta;
+use sink::Sink;
+use uploader::{self, ArchiveInfo};
+
+/// Plain text formats the [`FileSink`] can write
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileFormat {
+    /// Comma separated values, with a header row
+    Csv,
+    /// One JSON encoded delta per line
+    Ndjson,
+}
+
+impl FileFormat {
+    /// File extension used for this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            FileFormat::Csv => "csv",
+            FileFormat::Ndjson => "ndjson",
+        }
+    }
+
+    /// First line of every file, if the format has one
+    pub fn header(&self) -> Option<&'static str> {
+        match self {
+            FileFormat::Csv => Some("exchange,symbol,ts,seq,price,size,side,type,event\n"),
+            FileFormat::Ndjson => None,
+        }
+    }
+
+    /// Formats a single delta as one line of output
+    pub fn format_line(&self, exchange: &str, delta: &Delta) -> Result<String, Error> {
+        match self {
+            FileFormat::Csv => Ok(format!("{},{},{:.3},{},{},{},{},{},{}\n",
+                exchange,
+                delta.symbol,
+                delta.ts,
+                delta.seq,
+                delta.price,
+                delta.size,
+                delta.side(),
+                delta.event_type(),
+                delta.event)),
+            FileFormat::Ndjson => {
+                let line = serde_json::to_string(delta)
+                    .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+                Ok(line + "\n")
+            },
+        }
+    }
+}
+
+/// Determines when a file gets closed and a new one opened in its place
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rotation {
+    /// Start a new file every hour
+    Hourly,
+    /// Start a new file every day
+    Daily,
+    /// Start a new file once the current one has grown to this many bytes
+    Size(u64),
+}
+
+/// File that is currently being written to
+struct OpenFile {
+    /// Location of the file on disk
+    path: PathBuf,
+    /// Buffered writer to the file
+    writer: BufWriter<File>,
+    /// Amount of bytes written so far
+    written: u64,
+    /// Time period the file belongs to. Only used with time-based rotation
+    period: String,
+    /// What's been written to the file so far. Becomes the object's key and metadata when uploaded
+    info: ArchiveInfo,
+}
+
+/// File that's been closed, but has yet to make it to every storage backend
+struct FinishedFile {
+    /// Location of the file on disk
+    path: String,
+    /// Key the file is uploaded under
+    key: String,
+    /// Metadata the file is uploaded with
+    metadata: HashMap<String, String>,
+}
+
+/// Writes deltas as CSV or newline-delimited JSON, with one file per exchange/symbol pair
+/// stored under `root/exchange/symbol/`. Files are rotated hourly, daily, or once they reach a given size.
+/// Rotated files are handed to the uploader if uploads are enabled. Files that fail to upload are retried on every rotation.
+pub struct FileSink {
+    /// Folder the files are written under
+    root: PathBuf,
+    /// Output format
+    format: FileFormat,
+    /// Rotation policy
+    rotation: Rotation,
+    /// Files currently open, keyed by (exchange, symbol)
+    files: HashMap<(String, String), OpenFile>,
+    /// Files opened so far. Used to give every file a unique name
+    files_opened: u64,
+    /// Upload policy for rotated files
+    upload: UploadConfig,
+    /// Closed files that failed to upload
+    failed_uploads: Vec<FinishedFile>,
+}
+
+impl FileSink {
+    /// Creates a new sink writing under `root`
+    pub fn new(root: &str, format: FileFormat, rotation: Rotation, upload: UploadConfig) -> FileSink {
+        FileSink {
+            root: PathBuf::from(root),
+            format,
+            rotation,
+            files: HashMap::new(),
+            files_opened: 0,
+            upload,
+            failed_uploads: vec![],
+        }
+    }
+
+    /// Time period a delta falls under. Deltas in different periods go to different files.
+    fn period(&self, ts: f64) -> String {
+        let time = Utc.timestamp(ts as i64, 0);
+
+        match self.rotation {
+            Rotation::Hourly => time.format("%Y-%m-%dT%H").to_string(),
+            Rotation::Daily => time.format("%Y-%m-%d").to_string(),
+            Rotation::Size(_) => String::new(),
+        }
+    }
+
+    /// Opens a new file for the exchange/symbol pair, writing the CSV header if needed.
+    /// `ts` is the timestamp of the first delta that will be written to it.
+    fn open(&mut self, exchange: &str, symbol: &str, period: String, ts: f64) -> Result<OpenFile, Error> {
+        let dir = self.root.join(exchange).join(symbol);
+        create_dir_all(&dir)?;
+
+        let path = dir.join(format!("{}-{}.{}",
+            Utc::now().format("%Y%m%dT%H%M%S"),
+            self.files_opened,
+            self.format.extension()));
+        self.files_opened += 1;
+
+        let mut writer = BufWriter::new(OpenOptions::new().create(true).append(true).open(&path)?);
+        let mut written = 0;
+
+        if let Some(header) = self.format.header() {
+            writer.write_all(header.as_bytes())?;
+            written += header.len() as u64;
+        }
+
+        Ok(OpenFile {
+            path,
+            writer,
+            written,
+            period,
+            info: ArchiveInfo::new(exchange, symbol, ts),
+        })
+    }
+
+    /// Closes `file` and hands it off to the uploader, recording it in the catalog once uploaded
+    fn finish(&mut self, mut file: OpenFile) -> Result<(), Error> {
+        file.writer.flush()?;
+        drop(file.writer);
+
+        if !self.upload.enabled {
+            return Ok(());
+        }
+
+        let mut metadata = file.info.metadata();
+        metadata.insert("format".into(), self.format.extension().into());
+
+        let key = file.info.key(&file.path.file_name().unwrap().to_string_lossy());
+
+        self.upload_file(FinishedFile {
+            path: file.path.to_string_lossy().into_owned(),
+            key,
+            metadata,
+        })
+    }
+
+    /// Uploads `file`, keeping it around to be retried on the next rotation if that fails
+    fn upload_file(&mut self, file: FinishedFile) -> Result<(), Error> {
+        let uploaded = uploader::upload_archive(&file.path, &file.key, &file.metadata, &self.upload);
+
+        if uploaded.is_err() {
+            self.failed_uploads.push(file);
+        }
+
+        uploaded
+    }
+}
+
+impl Sink for FileSink {
+    fn write_batch(&mut self, exchange: &str, deltas: &[Delta]) -> Result<(), Error> {
+        for delta in deltas {
+            let key = (exchange.to_owned(), delta.symbol.clone());
+            let period = self.period(delta.ts);
+
+            // Rotate the file if the delta belongs to a new period or the file has grown too large
+            let needs_rotation = match self.files.get(&key) {
+                Some(file) => match self.rotation {
+                    Rotation::Size(max_size) => file.written >= max_size,
+                    _ => file.period != period,
+                },
+                None => false,
+            };
+
+            // A failed upload is retried on the next rotation, so there's no reason to drop the rest of the batch
+            if needs_rotation {
+                let file = self.files.remove(&key).unwrap();
+
+                if let Err(e) = self.finish(file) {
+                    println!("Log Error: {}", e);
+                }
+            }
+            if !self.files.contains_key(&key) {
+                let file = self.open(exchange, &delta.symbol, period, delta.ts)?;
+                self.files.insert(key.clone(), file);
+            }
+
+            let line = self.format.format_line(exchange, delta)?;
+            let file = self.files.get_mut(&key).unwrap();
+
+            file.writer.write_all(line.as_bytes())?;
+            file.written += line.len() as u64;
+            file.info.include(delta.ts);
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        for file in self.files.values_mut() {
+            file.writer.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Retries the uploads that failed so far, then closes every open file and hands them to the uploader.
+    /// New files are opened as deltas come in. Every file is attempted, and the first error is returned.
+    fn rotate(&mut self) -> Result<(), Error> {
+        let mut result = Ok(());
+
+        let failed: Vec<FinishedFile> = self.failed_uploads.drain(..).collect();
+        for file in failed {
+            result = result.and(self.upload_file(file));
+        }
+
+        let files: Vec<OpenFile> = self.files.drain().map(|(_, file)| file).collect();
+        for file in files {
+            result = result.and(self.finish(file));
+        }
+
+        result
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        self.rotate()
+    }
+}
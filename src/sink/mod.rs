@@ -0,0 +1,93 @@
+use std::env;
+use std::fs::{read_dir, remove_dir_all};
+use std::io::Error;
+use std::path::{Path, PathBuf};
+
+use chrono::prelude::*;
+
+use encryption::EncryptionKey;
+use orderbook::{dtf, Delta};
+use uploader;
+
+/// CSV and newline-delimited JSON file sinks
+pub mod file;
+/// Columnar export of deltas as partitioned Parquet files
+pub mod parquet;
+/// Embedded SQLite database sink
+pub mod sqlite;
+/// Sink that writes deltas into TectonicDB
+pub mod tectonic;
+
+/// Amount of deltas we hand to a sink at once when replaying archives
+const REPLAY_BATCH_SIZE: usize = 10_000;
+
+/// Destination for the deltas we collect. The listener fans every batch it receives out to all of
+/// its configured sinks, so adding a new storage backend only requires implementing this trait.
+pub trait Sink: Send {
+    /// Writes a batch of deltas that was published on `exchange`'s channel
+    fn write_batch(&mut self, exchange: &str, deltas: &[Delta]) -> Result<(), Error>;
+    /// Makes sure everything written so far has been persisted
+    fn flush(&mut self) -> Result<(), Error>;
+    /// Finishes the current unit of storage (database, file, ...) and starts a new one.
+    /// The listener calls this once every upload period.
+    fn rotate(&mut self) -> Result<(), Error>;
+    /// Flushes and releases any resources held. Nothing should be written to the sink afterwards.
+    fn close(&mut self) -> Result<(), Error>;
+}
+
+/// Replays a database archive (as created by [`uploader::archive_databases`]) into `sink`.
+/// This lets us backfill any sink from data we've already archived.
+/// `dictionary` is required for zstd archives compressed with one, and `key` for encrypted archives.
+pub fn replay_archive(archive: &String, dictionary: Option<&String>, key: Option<&EncryptionKey>, sink: &mut Sink) -> Result<(), Error> {
+    read_archive(archive, dictionary, key, |exchange, batch| sink.write_batch(exchange, batch))?;
+
+    sink.flush()
+}
+
+/// Streams the deltas of a database archive to `on_batch`, along with the exchange they belong to.
+/// The archive is unpacked into a temporary folder, and every DTF file inside it is read and handed over
+/// in batches. Stops at the first error `on_batch` returns.
+/// `dictionary` is required for zstd archives compressed with one, and `key` for encrypted archives.
+pub fn read_archive<F>(archive: &String, dictionary: Option<&String>, key: Option<&EncryptionKey>, mut on_batch: F) -> Result<(), Error>
+    where F: FnMut(&str, &[Delta]) -> Result<(), Error> {
+
+    let unpack_dir = env::temp_dir()
+        .join(format!("rusty_road_replay_{}", Utc::now().timestamp_nanos()))
+        .to_string_lossy()
+        .into_owned();
+
+    let result = uploader::decompress_archive(archive, &unpack_dir, dictionary, key)
+        .and_then(|_| {
+            let mut dtf_files = vec![];
+            find_dtf_files(Path::new(&unpack_dir), &mut dtf_files)?;
+
+            for dtf_file in dtf_files {
+                let (exchange, _, deltas) = dtf::read_deltas(&dtf_file.to_string_lossy())?;
+
+                for batch in deltas.chunks(REPLAY_BATCH_SIZE) {
+                    on_batch(&exchange, batch)?;
+                }
+            }
+
+            Ok(())
+        });
+
+    let _ = remove_dir_all(&unpack_dir);
+
+    result
+}
+
+/// Recursively collects every `.dtf` file found under `dir`
+fn find_dtf_files(dir: &Path, dtf_files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            find_dtf_files(&path, dtf_files)?;
+        } else if path.extension().map(|ext| ext == "dtf").unwrap_or(false) {
+            dtf_files.push(path);
+        }
+    }
+
+    Ok(())
+}
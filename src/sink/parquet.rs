@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::fs::{create_dir_all, remove_file, File};
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use chrono::prelude::*;
+use parquet::column::writer::ColumnWriter;
+use parquet::data_type::ByteArray;
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::{FileWriter, RowGroupWriter, SerializedFileWriter};
+use parquet::schema::parser::parse_message_type;
+use parquet::schema::types::Type;
+
+use encryption::EncryptionKey;
+use orderbook::Delta;
+use sink::{self, Sink};
+
+/// Schema of the Parquet files we write. The `event` bit flags are kept as they are,
+/// but are also decoded into the `side` and `type` columns to make them easy to query.
+const DELTA_SCHEMA: &str = "
+    message delta {
+        REQUIRED BINARY exchange (UTF8);
+        REQUIRED BINARY symbol (UTF8);
+        REQUIRED DOUBLE ts;
+        REQUIRED INT32 seq (UINT_32);
+        REQUIRED FLOAT price;
+        REQUIRED FLOAT size;
+        REQUIRED BINARY side (UTF8);
+        REQUIRED BINARY type (UTF8);
+        REQUIRED INT32 event (UINT_8);
+    }
+";
+
+/// Amount of buffered deltas that will trigger a write to disk
+const DEFAULT_MAX_BUFFERED: usize = 500_000;
+
+/// Writes deltas to Parquet files, partitioned as `root/exchange=.../symbol=.../date=yyyy-mm-dd/`.
+/// The partition layout is the one Spark, Arrow, and friends expect, so a whole folder can be loaded as one dataset.
+///
+/// Parquet files can't be appended to, so deltas are buffered in memory and every flush writes
+/// one new file per partition.
+pub struct ParquetSink {
+    /// Folder the partitions are written under
+    root: PathBuf,
+    /// Buffered deltas per partition (exchange, symbol, date)
+    buffers: HashMap<(String, String, String), Vec<Delta>>,
+    /// Total amount of deltas currently buffered
+    buffered: usize,
+    /// Amount of buffered deltas that will trigger a flush
+    pub max_buffered: usize,
+    /// Files written so far. Used to give every file a unique name
+    files_written: u64,
+}
+
+impl ParquetSink {
+    /// Creates a new sink writing its partitions under `root`
+    pub fn new(root: &str) -> ParquetSink {
+        ParquetSink {
+            root: PathBuf::from(root),
+            buffers: HashMap::new(),
+            buffered: 0,
+            max_buffered: DEFAULT_MAX_BUFFERED,
+            files_written: 0,
+        }
+    }
+
+    /// Writes `deltas` to a new file inside the partition folder
+    fn write_partition(&mut self, exchange: &str, symbol: &str, date: &str, deltas: &[Delta]) -> Result<(), Error> {
+        let dir = self.root
+            .join(format!("exchange={}", exchange))
+            .join(format!("symbol={}", symbol))
+            .join(format!("date={}", date));
+
+        create_dir_all(&dir)?;
+
+        let path = dir.join(format!("{}-{}.parquet", Utc::now().timestamp_millis(), self.files_written));
+        self.files_written += 1;
+
+        let schema = Rc::new(parse_message_type(DELTA_SCHEMA).map_err(to_io_error)?);
+        let props = Rc::new(WriterProperties::builder().build());
+
+        let written = File::create(&path)
+            .and_then(|file| write_file(file, schema, props, exchange, deltas).map_err(to_io_error));
+
+        // Don't leave a truncated file behind for readers to trip on. The deltas are written again on the next flush
+        if written.is_err() {
+            let _ = remove_file(&path);
+        }
+
+        written
+    }
+}
+
+impl Sink for ParquetSink {
+    fn write_batch(&mut self, exchange: &str, deltas: &[Delta]) -> Result<(), Error> {
+        for delta in deltas {
+            let date = Utc.timestamp(delta.ts as i64, 0).format("%Y-%m-%d").to_string();
+
+            self.buffers.entry((exchange.to_owned(), delta.symbol.clone(), date))
+                .or_insert_with(Vec::new)
+                .push(delta.clone());
+        }
+
+        self.buffered += deltas.len();
+
+        if self.buffered >= self.max_buffered {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes every buffered partition. Partitions that fail to be written are kept buffered, so that
+    /// they're retried on the next flush. A failure doesn't stop the other partitions from
+    /// being written; the first error is returned once they're all done.
+    fn flush(&mut self) -> Result<(), Error> {
+        let buffers: Vec<_> = self.buffers.drain().collect();
+        let mut result = Ok(());
+
+        for ((exchange, symbol, date), deltas) in buffers {
+            match self.write_partition(&exchange, &symbol, &date, &deltas) {
+                Ok(_) => self.buffered -= deltas.len(),
+                Err(e) => {
+                    println!("Log Error: Failed to write partition {}/{}/{}: {}", exchange, symbol, date, e);
+                    self.buffers.insert((exchange, symbol, date), deltas);
+                    result = result.and(Err(e));
+                },
+            }
+        }
+
+        result
+    }
+
+    fn rotate(&mut self) -> Result<(), Error> {
+        self.flush()
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        self.flush()
+    }
+}
+
+/// Converts an existing database archive into Parquet partitions under `root`.
+/// `dictionary` is required for zstd archives compressed with one, and `key` for encrypted archives.
+pub fn convert_archive(archive: &String, dictionary: Option<&String>, key: Option<&EncryptionKey>, root: &str) -> Result<(), Error> {
+    sink::replay_archive(archive, dictionary, key, &mut ParquetSink::new(root))
+}
+
+/// Writes `deltas` to `file` as a single row group
+fn write_file(file: File, schema: Rc<Type>, props: Rc<WriterProperties>, exchange: &str, deltas: &[Delta]) -> Result<(), ParquetError> {
+    let mut writer = SerializedFileWriter::new(file, schema, props)?;
+    let mut row_group = writer.next_row_group()?;
+
+    write_columns(&mut row_group, exchange, deltas)?;
+
+    writer.close_row_group(row_group)?;
+    writer.close()
+}
+
+/// Writes every column of `deltas` into the row group. Columns come in the same order as in [`DELTA_SCHEMA`].
+fn write_columns(row_group: &mut Box<RowGroupWriter>, exchange: &str, deltas: &[Delta]) -> Result<(), ParquetError> {
+    let mut column = 0;
+
+    while let Some(mut column_writer) = row_group.next_column()? {
+        match column_writer {
+            ColumnWriter::ByteArrayColumnWriter(ref mut typed) => {
+                let values: Vec<ByteArray> = deltas.iter()
+                    .map(|delta| match column {
+                        0 => exchange,
+                        1 => delta.symbol.as_str(),
+                        6 => delta.side(),
+                        _ => delta.event_type(),
+                    })
+                    .map(|value| ByteArray::from(value.as_bytes().to_vec()))
+                    .collect();
+
+                typed.write_batch(&values, None, None)?;
+            },
+            ColumnWriter::DoubleColumnWriter(ref mut typed) => {
+                let values: Vec<f64> = deltas.iter().map(|delta| delta.ts).collect();
+                typed.write_batch(&values, None, None)?;
+            },
+            ColumnWriter::FloatColumnWriter(ref mut typed) => {
+                let values: Vec<f32> = deltas.iter()
+                    .map(|delta| if column == 4 { delta.price } else { delta.size })
+                    .collect();
+
+                typed.write_batch(&values, None, None)?;
+            },
+            ColumnWriter::Int32ColumnWriter(ref mut typed) => {
+                let values: Vec<i32> = deltas.iter()
+                    .map(|delta| if column == 3 { delta.seq as i32 } else { delta.event as i32 })
+                    .collect();
+
+                typed.write_batch(&values, None, None)?;
+            },
+            _ => return Err(ParquetError::General(format!("Unexpected column type at index {}", column))),
+        }
+
+        row_group.close_column(column_writer)?;
+        column += 1;
+    }
+
+    Ok(())
+}
+
+/// Parquet errors are converted to IO errors, as that's what the [`Sink`] trait returns
+fn to_io_error(e: ParquetError) -> Error {
+    Error::new(ErrorKind::Other, e.to_string())
+}
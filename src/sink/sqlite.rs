@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+
+use rusqlite::{self, Connection};
+use rusqlite::types::ToSql;
+
+use encryption::EncryptionKey;
+use orderbook::{self, Delta, Snapshot};
+use sink::{self, Sink};
+
+/// Tables and indexes we store the data in. Every table is indexed on `(symbol, ts)`,
+/// as that's how the data is almost always queried.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS deltas (
+        exchange TEXT NOT NULL,
+        symbol TEXT NOT NULL,
+        ts REAL NOT NULL,
+        seq INTEGER NOT NULL,
+        price REAL NOT NULL,
+        size REAL NOT NULL,
+        side TEXT NOT NULL,
+        type TEXT NOT NULL,
+        event INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS deltas_symbol_ts ON deltas (symbol, ts);
+
+    CREATE TABLE IF NOT EXISTS trades (
+        exchange TEXT NOT NULL,
+        symbol TEXT NOT NULL,
+        ts REAL NOT NULL,
+        seq INTEGER NOT NULL,
+        price REAL NOT NULL,
+        size REAL NOT NULL,
+        side TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS trades_symbol_ts ON trades (symbol, ts);
+
+    CREATE TABLE IF NOT EXISTS snapshots (
+        exchange TEXT NOT NULL,
+        symbol TEXT NOT NULL,
+        ts REAL NOT NULL,
+        side TEXT NOT NULL,
+        price REAL NOT NULL,
+        size REAL NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS snapshots_symbol_ts ON snapshots (symbol, ts);
+";
+
+/// Amount of buffered deltas that will trigger a write to the database
+const DEFAULT_BATCH_SIZE: usize = 10_000;
+/// Seconds between two snapshots of the same book
+const DEFAULT_SNAPSHOT_INTERVAL: f64 = 60.0;
+
+/// Levels of a book, rebuilt from its deltas. Levels are keyed by the bits of their price
+struct Levels {
+    /// Size of every bid level
+    bids: HashMap<u32, f32>,
+    /// Size of every ask level
+    asks: HashMap<u32, f32>,
+    /// Time of the last snapshot taken, or of the first delta if none was taken yet
+    last_snapshot: f64,
+}
+
+impl Levels {
+    /// Applies a delta to its side of the book. Empty levels are removed
+    fn apply(&mut self, delta: &Delta) {
+        let side = if delta.is_bid() { &mut self.bids } else { &mut self.asks };
+
+        if delta.size == 0.0 || delta.event & orderbook::REMOVE == orderbook::REMOVE {
+            side.remove(&delta.price.to_bits());
+        } else {
+            side.insert(delta.price.to_bits(), delta.size);
+        }
+    }
+
+    /// Current levels of the book
+    fn snapshot(&self) -> Snapshot {
+        let levels = |side: &HashMap<u32, f32>| side.iter()
+            .map(|(price, size)| (f32::from_bits(*price), *size))
+            .collect();
+
+        Snapshot {
+            market: None,
+            asset: None,
+            bids: levels(&self.bids),
+            asks: levels(&self.asks),
+        }
+    }
+}
+
+/// Writes deltas, trades, and snapshots into an embedded SQLite database. Handy for running SQL
+/// over a few days of data without having to set up a server.
+///
+/// Trade events are stored in the `trades` table, every other event goes into `deltas`.
+/// Deltas are buffered and inserted in batches, with one transaction per batch.
+///
+/// The sink also rebuilds every book from its deltas, and stores its levels in `snapshots` once every
+/// `snapshot_interval` seconds of data. Snapshots are timed by the deltas themselves, so replaying an archive
+/// gives the same snapshots as the live listener did. Books start out empty, so a snapshot only holds the
+/// levels that changed since the sink started.
+pub struct SqliteSink {
+    /// SQLite database connection
+    connection: Connection,
+    /// Deltas waiting to be inserted, along with the exchange they came from
+    buffer: Vec<(String, Delta)>,
+    /// Amount of buffered deltas that will trigger an insert
+    pub batch_size: usize,
+    /// Books rebuilt from the deltas, keyed by (exchange, symbol)
+    books: HashMap<(String, String), Levels>,
+    /// Snapshots waiting to be inserted, as (exchange, symbol, ts, snapshot)
+    snapshots: Vec<(String, String, f64, Snapshot)>,
+    /// Seconds of data between two snapshots of the same book
+    pub snapshot_interval: f64,
+}
+
+impl SqliteSink {
+    /// Opens (or creates) the SQLite database at `path` and sets up the tables
+    pub fn new(path: &str) -> Result<SqliteSink, Error> {
+        let connection = Connection::open(path).map_err(to_io_error)?;
+        connection.execute_batch(SCHEMA).map_err(to_io_error)?;
+
+        Ok(SqliteSink {
+            connection,
+            buffer: Vec::with_capacity(DEFAULT_BATCH_SIZE),
+            batch_size: DEFAULT_BATCH_SIZE,
+            books: HashMap::new(),
+            snapshots: vec![],
+            snapshot_interval: DEFAULT_SNAPSHOT_INTERVAL,
+        })
+    }
+
+    /// Stores every level of `snapshot` as it was at time `ts`
+    pub fn write_snapshot(&mut self, exchange: &str, symbol: &str, ts: f64, snapshot: &Snapshot) -> Result<(), Error> {
+        let tx = self.connection.transaction().map_err(to_io_error)?;
+        insert_snapshot(&tx, exchange, symbol, ts, snapshot)?;
+
+        tx.commit().map_err(to_io_error)
+    }
+
+    /// Applies `delta` to its book, and takes a snapshot of the book if one is due
+    fn update_book(&mut self, exchange: &str, delta: &Delta) {
+        if delta.is_trade() {
+            return;
+        }
+
+        let book = self.books.entry((exchange.to_owned(), delta.symbol.clone()))
+            .or_insert_with(|| Levels {
+                bids: HashMap::new(),
+                asks: HashMap::new(),
+                last_snapshot: delta.ts,
+            });
+
+        book.apply(delta);
+
+        if delta.ts - book.last_snapshot >= self.snapshot_interval {
+            book.last_snapshot = delta.ts;
+            self.snapshots.push((exchange.to_owned(), delta.symbol.clone(), delta.ts, book.snapshot()));
+        }
+    }
+
+    /// Inserts the snapshots taken so far in a single transaction. They're kept for the next flush if that fails
+    fn flush_snapshots(&mut self) -> Result<(), Error> {
+        if self.snapshots.is_empty() {
+            return Ok(())
+        }
+
+        let tx = self.connection.transaction().map_err(to_io_error)?;
+
+        for (exchange, symbol, ts, snapshot) in &self.snapshots {
+            insert_snapshot(&tx, exchange, symbol, *ts, snapshot)?;
+        }
+
+        tx.commit().map_err(to_io_error)?;
+        self.snapshots.clear();
+
+        Ok(())
+    }
+}
+
+impl Sink for SqliteSink {
+    fn write_batch(&mut self, exchange: &str, deltas: &[Delta]) -> Result<(), Error> {
+        for delta in deltas {
+            self.buffer.push((exchange.to_owned(), delta.clone()));
+            self.update_book(exchange, delta);
+        }
+
+        if self.buffer.len() >= self.batch_size {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Inserts every buffered delta in a single transaction, followed by the snapshots taken since the last flush
+    fn flush(&mut self) -> Result<(), Error> {
+        if self.buffer.is_empty() {
+            return self.flush_snapshots();
+        }
+
+        let tx = self.connection.transaction().map_err(to_io_error)?;
+        {
+            let mut insert_delta = tx.prepare_cached(
+                "INSERT INTO deltas (exchange, symbol, ts, seq, price, size, side, type, event) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)")
+                .map_err(to_io_error)?;
+            let mut insert_trade = tx.prepare_cached(
+                "INSERT INTO trades (exchange, symbol, ts, seq, price, size, side) VALUES (?, ?, ?, ?, ?, ?, ?)")
+                .map_err(to_io_error)?;
+
+            for (exchange, delta) in &self.buffer {
+                let seq = delta.seq as i64;
+                let price = delta.price as f64;
+                let size = delta.size as f64;
+                let side = delta.side().to_owned();
+
+                if delta.is_trade() {
+                    insert_trade.execute(&[exchange as &ToSql, &delta.symbol, &delta.ts, &seq, &price, &size, &side])
+                        .map_err(to_io_error)?;
+                } else {
+                    insert_delta.execute(&[exchange as &ToSql, &delta.symbol, &delta.ts, &seq, &price, &size, &side,
+                        &delta.event_type().to_owned(), &(delta.event as i64)])
+                        .map_err(to_io_error)?;
+                }
+            }
+        }
+
+        tx.commit().map_err(to_io_error)?;
+        self.buffer.clear();
+
+        self.flush_snapshots()
+    }
+
+    /// Everything lives in a single database file, so there's nothing to rotate besides flushing
+    fn rotate(&mut self) -> Result<(), Error> {
+        self.flush()
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        self.flush()
+    }
+}
+
+/// Imports an existing database archive into the SQLite database at `path`.
+/// `dictionary` is required for zstd archives compressed with one, and `key` for encrypted archives.
+pub fn import_archive(archive: &String, dictionary: Option<&String>, key: Option<&EncryptionKey>, path: &str) -> Result<(), Error> {
+    sink::replay_archive(archive, dictionary, key, &mut SqliteSink::new(path)?)
+}
+
+/// Inserts every level of `snapshot`, as it was at time `ts`
+fn insert_snapshot(connection: &Connection, exchange: &str, symbol: &str, ts: f64, snapshot: &Snapshot) -> Result<(), Error> {
+    let mut stmt = connection.prepare_cached(
+        "INSERT INTO snapshots (exchange, symbol, ts, side, price, size) VALUES (?, ?, ?, ?, ?, ?)")
+        .map_err(to_io_error)?;
+
+    let exchange = exchange.to_owned();
+    let symbol = symbol.to_owned();
+    let levels = snapshot.bids.iter().map(|level| ("bid", level))
+        .chain(snapshot.asks.iter().map(|level| ("ask", level)));
+
+    for (side, &(price, size)) in levels {
+        stmt.execute(&[&exchange as &ToSql, &symbol, &ts, &side.to_owned(), &(price as f64), &(size as f64)])
+            .map_err(to_io_error)?;
+    }
+
+    Ok(())
+}
+
+/// SQLite errors are converted to IO errors, as that's what the [`Sink`] trait returns
+fn to_io_error(e: rusqlite::Error) -> Error {
+    Error::new(ErrorKind::Other, e.to_string())
+}
@@ -0,0 +1,88 @@
+use std::io::Error;
+
+use config::UploadConfig;
+use encryption::EncryptionKey;
+use orderbook::Delta;
+use orderbook::tectonic::{PooledConnection, TectonicPool};
+use sink::Sink;
+use uploader::{self, Codec};
+
+/// Writes deltas into TectonicDB. Deltas are stored in the database named `exchange_symbol`.
+/// Rotating the sink archives every TectonicDB database (see [`uploader::archive_databases`]).
+pub struct TectonicSink {
+    /// Connection checked out of the pool for the lifetime of the sink
+    connection: PooledConnection,
+    /// Folder the TectonicDB server writes its DTF files to
+    db_path: String,
+    /// Upload policy for the archives
+    upload: UploadConfig,
+    /// Compression used for the archives
+    codec: Codec,
+    /// Key the archives are encrypted with, if any
+    key: Option<EncryptionKey>,
+}
+
+impl TectonicSink {
+    /// Creates a new sink with its own connection checked out of `pool`. `db_path` must be the
+    /// folder the server writes its DTF files to, as that's what gets archived on rotation.
+    pub fn new(pool: &TectonicPool, db_path: &str, upload: UploadConfig, codec: Codec, key: Option<EncryptionKey>) -> Result<TectonicSink, Error> {
+        Ok(TectonicSink {
+            connection: pool.get()?,
+            db_path: db_path.into(),
+            upload,
+            codec,
+            key,
+        })
+    }
+}
+
+impl Sink for TectonicSink {
+    fn write_batch(&mut self, exchange: &str, deltas: &[Delta]) -> Result<(), Error> {
+        for delta in deltas {
+            let _ = self.connection.insert_into(format!("{}_{}", exchange, delta.symbol), delta)?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        let _ = self.connection.flush_all()?;
+
+        Ok(())
+    }
+
+    /// Flushes TectonicDB data to disk, compresses every database into its own archive and deletes the DTF files.
+    /// The archives are uploaded to every storage backend and added to the catalog if uploads are enabled. A failed upload doesn't stop the others from
+    /// being attempted; the first error is returned once they're all done.
+    /// Archives left over by failed uploads are retried on every rotation.
+    fn rotate(&mut self) -> Result<(), Error> {
+        print!("Flushing TectonicDB data to disk... ");
+        self.flush()?;
+
+        uploader::archive_databases(&self.db_path, &self.upload.archive_dir, &self.codec, self.key.as_ref())?;
+
+        if self.upload.enabled {
+            let mut result = Ok(());
+
+            // Archives that failed to upload on a previous rotation are still around, and are retried along with the new ones
+            for archive in uploader::leftover_archives(&self.upload.archive_dir)? {
+                let uploaded = uploader::upload_archive(&archive.path, &archive.key, &archive.metadata, &self.upload);
+
+                if let Err(e) = uploaded {
+                    println!("Log Error: Failed to upload {}: {}", archive.path, e);
+                    result = result.and(Err(e));
+                }
+            }
+
+            result?;
+        }
+
+        println!("Success");
+
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        self.flush()
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::fs::{canonicalize, create_dir_all, read_dir, remove_file, rename, File};
+use std::io::{self, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use chrono::prelude::*;
+
+use storage::{HashingWriter, Storage, UploadedObject};
+
+/// Directory archives are copied into, laid out like their keys. Works just as well on an NFS or SMB share.
+/// Metadata isn't stored alongside the archives, as it's already in the catalog.
+pub struct LocalStorage {
+    /// Absolute path of the directory, so that locations stay valid wherever they're read from
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    /// Stores objects under `root`, creating it if needed
+    pub fn new(root: &str) -> Result<LocalStorage, Error> {
+        create_dir_all(root)?;
+
+        Ok(LocalStorage {
+            root: canonicalize(root)?,
+        })
+    }
+
+    /// Path of the object stored under `key`
+    pub fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+impl Storage for LocalStorage {
+    /// Copies the file to `key.partial` and renames it once synced, so that a crash never leaves a truncated
+    /// archive behind under `key`
+    fn put(&self, path: &str, key: &str, metadata: &HashMap<String, String>) -> Result<UploadedObject, Error> {
+        let dest = self.path(key);
+        let partial_name = format!("{}.partial", dest.display());
+
+        if let Some(parent) = dest.parent() {
+            create_dir_all(parent)?;
+        }
+
+        let mut partial = HashingWriter::new(File::create(&partial_name)?);
+        let copied = io::copy(&mut File::open(path)?, &mut partial)
+            .and_then(|size| partial.writer.sync_all().map(|_| size));
+
+        let size = match copied {
+            Ok(size) => size,
+            Err(e) => {
+                let _ = remove_file(&partial_name);
+                return Err(e);
+            }
+        };
+
+        rename(&partial_name, &dest)?;
+
+        let md5 = format!("{:x}", partial.digest.compute());
+        println!("Copied {} ({} bytes) to {}", path, size, dest.display());
+
+        Ok(UploadedObject {
+            location: self.location(key),
+            key: key.into(),
+            etag: md5.clone(),
+            md5,
+            size,
+            metadata: metadata.clone(),
+        })
+    }
+
+    fn get(&self, key: &str, dest: &str) -> Result<String, Error> {
+        let mut copy = HashingWriter::new(File::create(dest)?);
+        io::copy(&mut File::open(self.path(key))?, &mut copy)?;
+
+        Ok(format!("{:x}", copy.digest.compute()))
+    }
+
+    /// Files are dated by their last modification, which for archives is when they were copied.
+    /// Unfinished copies are left out.
+    fn list(&self) -> Result<Vec<(String, DateTime<Utc>)>, Error> {
+        let mut files = vec![];
+        list_files(&self.root, &mut files)?;
+
+        files.into_iter()
+            .filter(|path| path.extension().map(|ext| ext != "partial").unwrap_or(true))
+            .map(|path| {
+                let stored: DateTime<Utc> = path.metadata()?.modified()?.into();
+                let key = path.strip_prefix(&self.root)
+                    .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                    .collect::<Vec<String>>()
+                    .join("/");
+
+                Ok((key, stored))
+            })
+            .collect()
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Error> {
+        remove_file(self.path(key))
+    }
+
+    fn location(&self, key: &str) -> String {
+        format!("file://{}", self.path(key).display())
+    }
+}
+
+/// Recursively collects every file found under `dir`
+fn list_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            list_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Write};
+
+use chrono::prelude::*;
+use chrono::Duration;
+use md5;
+
+use catalog::Catalog;
+
+/// Directory on the local filesystem, or a network share mounted as one
+pub mod local;
+/// Amazon S3 and S3-compatible servers
+pub mod s3;
+
+/// Somewhere archives are kept once they leave the archive folder. Objects are addressed by keys such as
+/// [`uploader::Archive::key`], so every backend lays archives out the same way.
+pub trait Storage: Send {
+    /// Stores the file at `path` under `key`, along with `metadata`. The file itself is left alone.
+    /// Returns the object as the backend has it, ready to be added to the [`catalog`].
+    fn put(&self, path: &str, key: &str, metadata: &HashMap<String, String>) -> Result<UploadedObject, Error>;
+    /// Copies the object stored under `key` to the file `dest`. Returns the MD5 of the file in hex,
+    /// to be checked against [`catalog::CatalogEntry::md5`]
+    fn get(&self, key: &str, dest: &str) -> Result<String, Error>;
+    /// Keys of every stored object, along with when they were stored
+    fn list(&self) -> Result<Vec<(String, DateTime<Utc>)>, Error>;
+    /// Deletes the object stored under `key`
+    fn delete(&self, key: &str) -> Result<(), Error>;
+    /// Location of `key`, as recorded in the catalog (e.g. `s3://bucket/key`)
+    fn location(&self, key: &str) -> String;
+}
+
+/// Storage backend along with its retention policy
+pub struct Backend {
+    /// Where the archives are stored
+    pub storage: Box<Storage>,
+    /// How long archives are kept once stored. Kept forever if `None`
+    pub retention: Option<Duration>,
+}
+
+/// Object a [`Storage`] confirmed to have
+#[derive(Clone, Debug)]
+pub struct UploadedObject {
+    /// Where the object is stored, as returned by [`Storage::location`]
+    pub location: String,
+    /// Key the object is stored under
+    pub key: String,
+    /// ETag the backend gave the object
+    pub etag: String,
+    /// MD5 of the whole file, in hex. Unlike the ETag of a multipart upload, it can be checked with `md5sum`
+    pub md5: String,
+    /// Size in bytes
+    pub size: u64,
+    /// Metadata stored with the object, as returned by the backend
+    pub metadata: HashMap<String, String>,
+}
+
+/// Deletes the archives of `backend` that were uploaded longer ago than its retention, and removes them from `catalog`.
+/// Only archives the catalog places in this backend are considered, so nothing else sharing the bucket or folder is
+/// ever touched. Does nothing for backends that keep everything. Returns the amount of archives deleted.
+pub fn apply_retention(backend: &Backend, catalog: &Catalog) -> Result<usize, Error> {
+    let retention = match backend.retention {
+        Some(retention) => retention,
+        None => return Ok(0),
+    };
+
+    let cutoff = Utc::now() - retention;
+    let mut deleted = 0;
+
+    for entry in catalog.stored_under(&backend.storage.location(""))? {
+        let uploaded = DateTime::parse_from_rfc3339(&entry.uploaded)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{} has an invalid upload date: {}", entry.location, e)))?;
+
+        if uploaded.with_timezone(&Utc) < cutoff {
+            // Archives removed by hand only need to be forgotten
+            match backend.storage.delete(&entry.key) {
+                Err(ref e) if e.kind() == ErrorKind::NotFound => (),
+                deleted => deleted?,
+            }
+            catalog.remove(&entry.location)?;
+
+            println!("Deleted {}, uploaded on {}", entry.location, entry.uploaded);
+            deleted += 1;
+        }
+    }
+
+    Ok(deleted)
+}
+
+/// Passes writes through to `writer`, hashing everything written
+pub struct HashingWriter<W: Write> {
+    /// Destination of the writes
+    pub writer: W,
+    /// MD5 of everything written so far
+    pub digest: md5::Context,
+}
+
+impl<W: Write> HashingWriter<W> {
+    /// Hashes everything written to `writer`
+    pub fn new(writer: W) -> HashingWriter<W> {
+        HashingWriter {
+            writer,
+            digest: md5::Context::new(),
+        }
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let written = self.writer.write(buf)?;
+        self.digest.consume(&buf[..written]);
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,356 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs::{remove_file, rename, File};
+use std::io::{self, Error, ErrorKind, Read, Seek, SeekFrom};
+use std::thread;
+use std::time::Duration;
+
+use base64;
+use chrono::prelude::*;
+use md5;
+use rusoto_core;
+use rusoto_s3;
+use rusoto_s3::{S3, S3Client};
+
+use storage::{HashingWriter, Storage, UploadedObject};
+
+/// Size of the parts archives are uploaded to S3 in. S3 requires every part but the last to be at least 5 MiB
+pub const PART_SIZE: usize = 16 * 1024 * 1024;
+/// Attempts made at every S3 request before an upload is given up on
+pub const MAX_ATTEMPTS: u32 = 5;
+/// Delay before retrying a failed S3 request for the first time
+pub const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Amazon S3 bucket, or a bucket on an S3-compatible server
+pub struct S3Storage {
+    /// Client for the bucket's region
+    client: S3Client,
+    /// Bucket name
+    bucket: String,
+    /// Storage class new objects are given
+    storage_class: String,
+}
+
+impl S3Storage {
+    /// Stores objects in `bucket` with `storage_class`. `region` is an AWS region, or a custom region pointing
+    /// at an S3-compatible server (see [`config::UploadConfig::region`]). Credentials are read from the usual AWS sources.
+    pub fn new(bucket: &str, storage_class: &str, region: rusoto_core::Region) -> S3Storage {
+        let credentials = rusoto_core::credential::ChainProvider::new();
+
+        S3Storage {
+            client: S3Client::new_with(
+                rusoto_core::request::HttpClient::new().unwrap(),
+                credentials,
+                region),
+            bucket: bucket.into(),
+            storage_class: storage_class.into(),
+        }
+    }
+
+    /// Finds an unfinished multipart upload of `key`, left behind by a failed [`S3Storage::put`]
+    fn find_multipart_upload(&self, key: &String) -> Result<Option<String>, Error> {
+        let list_req = rusoto_s3::ListMultipartUploadsRequest {
+            bucket: self.bucket.clone(),
+            prefix: Some(key.clone()),
+            ..Default::default()
+        };
+
+        let uploads = with_retries("ListMultipartUploads", || self.client.list_multipart_uploads(list_req.clone()).sync())?
+            .uploads
+            .unwrap_or(vec![]);
+
+        Ok(uploads.into_iter()
+            .filter(|upload| upload.key.as_ref() == Some(key))
+            .filter_map(|upload| upload.upload_id)
+            .next())
+    }
+
+    /// ETags of the parts S3 already has for `upload_id`, keyed by part number
+    fn uploaded_parts(&self, key: &String, upload_id: &String) -> Result<HashMap<i64, String>, Error> {
+        let mut parts = HashMap::new();
+        let mut part_number_marker = None;
+
+        loop {
+            let list_req = rusoto_s3::ListPartsRequest {
+                bucket: self.bucket.clone(),
+                key: key.clone(),
+                upload_id: upload_id.clone(),
+                part_number_marker,
+                ..Default::default()
+            };
+
+            let output = with_retries("ListParts", || self.client.list_parts(list_req.clone()).sync())?;
+
+            for part in output.parts.unwrap_or(vec![]) {
+                if let (Some(part_number), Some(e_tag)) = (part.part_number, part.e_tag) {
+                    parts.insert(part_number, e_tag);
+                }
+            }
+
+            if output.is_truncated != Some(true) {
+                return Ok(parts);
+            }
+
+            part_number_marker = output.next_part_number_marker;
+        }
+    }
+}
+
+impl Storage for S3Storage {
+    /// Sends the archive as a multipart upload in [`PART_SIZE`] parts, read from disk one at a time, each with
+    /// a `Content-MD5` header so that S3 rejects corrupted parts. Every request is retried up to [`MAX_ATTEMPTS`]
+    /// times with exponential backoff. If an unfinished upload of the same key is found, we resume it and skip
+    /// the parts that were already uploaded intact, ignoring `metadata`. The object is only returned once its
+    /// ETag and size match what we sent.
+    ///
+    /// Failed uploads are left open on purpose so they can be resumed. Add a lifecycle rule that aborts
+    /// incomplete multipart uploads to the bucket, so that abandoned ones don't pile up.
+    fn put(&self, path: &str, key: &str, metadata: &HashMap<String, String>) -> Result<UploadedObject, Error> {
+        let key = key.to_owned();
+
+        let mut archive = File::open(path)?;
+        let size = archive.metadata()?.len();
+
+        let upload_id = match self.find_multipart_upload(&key)? {
+            Some(upload_id) => {
+                println!("Resuming upload of {}", key);
+                upload_id
+            },
+            None => {
+                let create_req = rusoto_s3::CreateMultipartUploadRequest {
+                    bucket: self.bucket.clone(),
+                    key: key.clone(),
+                    metadata: Some(metadata.clone()),
+                    // Infrequent access by default. This is to save money on long term storage, while
+                    // still being able to retrieve the data at a reasonable price compared to AWS Glacier.
+                    storage_class: Some(self.storage_class.clone()),
+
+                    ..Default::default()
+                };
+
+                with_retries("CreateMultipartUpload", || self.client.create_multipart_upload(create_req.clone()).sync())?
+                    .upload_id
+                    .ok_or(Error::new(ErrorKind::Other, "S3 did not return an upload ID"))?
+            },
+        };
+
+        let uploaded = self.uploaded_parts(&key, &upload_id)?;
+
+        let mut completed_parts = vec![];
+        let mut part_digests = vec![];
+        let mut file_digest = md5::Context::new();
+        let mut part_number = 1;
+        let mut offset = 0;
+
+        // S3 needs at least one part, even for empty archives
+        while offset < size || part_number == 1 {
+            let mut part = vec![0u8; cmp::min(PART_SIZE as u64, size - offset) as usize];
+            archive.seek(SeekFrom::Start(offset))?;
+            archive.read_exact(&mut part)?;
+
+            let digest = md5::compute(&part);
+            let etag = format!("\"{:x}\"", digest);
+
+            // Parts left over from a previous attempt are only reused if they're intact
+            if uploaded.get(&part_number) != Some(&etag) {
+                let content_md5 = base64::encode(&*digest);
+
+                let output = with_retries(&format!("UploadPart {}", part_number), || {
+                    // Request bodies are consumed when sent, so every attempt gets its own request
+                    let part_req = rusoto_s3::UploadPartRequest {
+                        bucket: self.bucket.clone(),
+                        key: key.clone(),
+                        upload_id: upload_id.clone(),
+                        part_number,
+                        body: Some(part.clone().into()),
+                        content_length: Some(part.len() as i64),
+                        content_md5: Some(content_md5.clone()),
+                        ..Default::default()
+                    };
+
+                    self.client.upload_part(part_req).sync()
+                })?;
+
+                if output.e_tag.as_ref() != Some(&etag) {
+                    return Err(Error::new(ErrorKind::InvalidData,
+                        format!("Part {} of {} was corrupted in transit (expected ETag {}, got {:?})", part_number, key, etag, output.e_tag)));
+                }
+            }
+
+            completed_parts.push(rusoto_s3::CompletedPart {
+                e_tag: Some(etag),
+                part_number: Some(part_number),
+            });
+            part_digests.extend_from_slice(&*digest);
+            file_digest.consume(&part);
+
+            offset += part.len() as u64;
+            part_number += 1;
+        }
+
+        let complete_req = rusoto_s3::CompleteMultipartUploadRequest {
+            bucket: self.bucket.clone(),
+            key: key.clone(),
+            upload_id,
+            multipart_upload: Some(rusoto_s3::CompletedMultipartUpload {
+                parts: Some(completed_parts),
+            }),
+            ..Default::default()
+        };
+
+        with_retries("CompleteMultipartUpload", || self.client.complete_multipart_upload(complete_req.clone()).sync())?;
+
+        // Make sure S3 has the object we think we uploaded before anyone gets rid of their copy
+        let head_req = rusoto_s3::HeadObjectRequest {
+            bucket: self.bucket.clone(),
+            key: key.clone(),
+            ..Default::default()
+        };
+        let object = with_retries("HeadObject", || self.client.head_object(head_req.clone()).sync())?;
+        let expected_etag = format!("\"{:x}-{}\"", md5::compute(&part_digests), part_number - 1);
+
+        if object.e_tag.as_ref() != Some(&expected_etag) || object.content_length != Some(size as i64) {
+            return Err(Error::new(ErrorKind::InvalidData,
+                format!("Uploaded object {} does not match the archive (expected ETag {} and {} bytes, got {:?} and {:?} bytes)",
+                    key, expected_etag, size, object.e_tag, object.content_length)));
+        }
+
+        // TODO: implement logging
+        println!("Uploaded {} ({} bytes) as {}", path, size, expected_etag);
+
+        Ok(UploadedObject {
+            location: self.location(&key),
+            key,
+            etag: expected_etag,
+            md5: format!("{:x}", file_digest.compute()),
+            size,
+            metadata: object.metadata.unwrap_or_default(),
+        })
+    }
+
+    /// Streams the object to `dest.partial`, retrying like [`S3Storage::put`] does, and only renames it to `dest`
+    /// once complete, so `dest` never holds a truncated download
+    fn get(&self, key: &str, dest: &str) -> Result<String, Error> {
+        let partial_name = format!("{}.partial", dest);
+
+        let get_req = rusoto_s3::GetObjectRequest {
+            bucket: self.bucket.clone(),
+            key: key.into(),
+            ..Default::default()
+        };
+
+        let downloaded = with_retries(&format!("GetObject {}", key), || -> Result<md5::Digest, String> {
+            let object = self.client.get_object(get_req.clone()).sync().map_err(|e| e.to_string())?;
+            let body = object.body.ok_or(format!("{} has no body", key))?;
+
+            // Hash as we write, so that the file doesn't have to be read again
+            let mut partial = HashingWriter::new(File::create(&partial_name).map_err(|e| e.to_string())?);
+
+            io::copy(&mut body.into_blocking_read(), &mut partial).map_err(|e| e.to_string())?;
+            partial.writer.sync_all().map_err(|e| e.to_string())?;
+
+            Ok(partial.digest.compute())
+        });
+
+        let digest = match downloaded {
+            Ok(digest) => digest,
+            Err(e) => {
+                let _ = remove_file(&partial_name);
+                return Err(e);
+            }
+        };
+
+        rename(&partial_name, dest)?;
+
+        Ok(format!("{:x}", digest))
+    }
+
+    /// Objects are dated by their last modification, which for archives is when they were uploaded
+    fn list(&self) -> Result<Vec<(String, DateTime<Utc>)>, Error> {
+        let mut objects = vec![];
+        let mut continuation_token = None;
+
+        loop {
+            let list_req = rusoto_s3::ListObjectsV2Request {
+                bucket: self.bucket.clone(),
+                continuation_token,
+                ..Default::default()
+            };
+
+            let output = with_retries("ListObjectsV2", || self.client.list_objects_v2(list_req.clone()).sync())?;
+
+            for object in output.contents.unwrap_or(vec![]) {
+                if let (Some(key), Some(last_modified)) = (object.key, object.last_modified) {
+                    let stored = DateTime::parse_from_rfc3339(&last_modified)
+                        .map_err(|e| Error::new(ErrorKind::InvalidData,
+                            format!("{} has an invalid last modified date \"{}\": {}", key, last_modified, e)))?;
+
+                    objects.push((key, stored.with_timezone(&Utc)));
+                }
+            }
+
+            if output.is_truncated != Some(true) {
+                return Ok(objects);
+            }
+
+            continuation_token = output.next_continuation_token;
+        }
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Error> {
+        let delete_req = rusoto_s3::DeleteObjectRequest {
+            bucket: self.bucket.clone(),
+            key: key.into(),
+            ..Default::default()
+        };
+
+        with_retries(&format!("DeleteObject {}", key), || self.client.delete_object(delete_req.clone()).sync())?;
+
+        Ok(())
+    }
+
+    fn location(&self, key: &str) -> String {
+        format!("s3://{}/{}", self.bucket, key)
+    }
+}
+
+/// Splits an `s3://bucket/key` location into its bucket and key
+pub fn parse_location(location: &str) -> Result<(&str, &str), Error> {
+    if !location.starts_with("s3://") {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("{} isn't an s3:// location", location)));
+    }
+
+    let mut parts = location["s3://".len()..].splitn(2, '/');
+    let bucket = parts.next().unwrap_or_default();
+    let key = parts.next()
+        .ok_or(Error::new(ErrorKind::InvalidInput, format!("{} is missing a key", location)))?;
+
+    Ok((bucket, key))
+}
+
+/// Runs an S3 request until it succeeds, giving up after [`MAX_ATTEMPTS`]. The delay between attempts
+/// starts at [`RETRY_BACKOFF`] and doubles every time.
+fn with_retries<T, E: Debug, F: FnMut() -> Result<T, E>>(request_name: &str, mut request: F) -> Result<T, Error> {
+    let mut backoff = RETRY_BACKOFF;
+    let mut attempt = 1;
+
+    loop {
+        match request() {
+            Ok(output) => return Ok(output),
+            Err(e) => {
+                if attempt == MAX_ATTEMPTS {
+                    return Err(Error::new(ErrorKind::Other,
+                        format!("{} failed after {} attempts: {:?}", request_name, attempt, e)));
+                }
+
+                println!("Log Error: {} failed (attempt {} of {}), retrying in {:?}: {:?}",
+                    request_name, attempt, MAX_ATTEMPTS, backoff, e);
+
+                thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            },
+        }
+    }
+}
@@ -0,0 +1,58 @@
+#[test]
+fn catalog_finds_overlapping_archives() {
+    use std::env;
+    use std::fs::remove_file;
+
+    use catalog::{Catalog, CatalogEntry};
+    use storage::UploadedObject;
+    use uploader::{ArchiveInfo, Codec};
+
+    let path = env::temp_dir().join("rusty_road_catalog_test.sqlite").to_string_lossy().into_owned();
+    let _ = remove_file(&path);
+    let catalog = Catalog::open(&path).unwrap();
+
+    // One archive a day for BTC-USD, and one for ETH-USD on the second day
+    let archives = vec![
+        ("BTC-USD", 1538352000.0, 1538438399.5),
+        ("BTC-USD", 1538438400.0, 1538524799.5),
+        ("BTC-USD", 1538524800.0, 1538611199.5),
+        ("ETH-USD", 1538438400.0, 1538524799.5),
+    ];
+
+    for (symbol, start, end) in archives {
+        let mut info = ArchiveInfo::new("gdax", symbol, start);
+        info.include(start);
+        info.include(end);
+
+        let mut metadata = info.metadata();
+        metadata.extend(Codec::default().metadata());
+
+        let object = UploadedObject {
+            location: format!("s3://cuteq/{}", info.key("archive.tar.xz")),
+            key: info.key("archive.tar.xz"),
+            etag: "\"etag-1\"".into(),
+            md5: "md5".into(),
+            size: 1024,
+            metadata,
+        };
+
+        catalog.record(&CatalogEntry::from_object(&object).unwrap()).unwrap();
+    }
+
+    // Noon of the first day to noon of the second overlaps the first two archives
+    let found = catalog.find("gdax", "BTC-USD", 1538395200.0, 1538481600.0).unwrap();
+    let locations: Vec<&str> = found.iter().map(|entry| entry.location.as_str()).collect();
+    assert_eq!(locations, vec![
+        "s3://cuteq/gdax/BTC-USD/2018/10/01/archive.tar.xz",
+        "s3://cuteq/gdax/BTC-USD/2018/10/02/archive.tar.xz",
+    ]);
+    assert_eq!(found[0].rows, 2);
+    assert_eq!(found[0].end, 1538438399.5);
+    assert_eq!(found[0].codec, Some("xz".into()));
+
+    assert!(catalog.find("gdax", "BTC-USD", 0.0, 1538351999.0).unwrap().is_empty());
+    assert!(catalog.find("bitmex", "BTC-USD", 0.0, 2e9).unwrap().is_empty());
+    assert_eq!(catalog.symbols().unwrap()[&("gdax".to_owned(), "BTC-USD".to_owned())], 3);
+
+    remove_file(&path).unwrap();
+}
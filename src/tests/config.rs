@@ -0,0 +1,121 @@
+#[test]
+fn config_defaults_and_sections() {
+    use config::Config;
+    use exchange::Asset;
+
+    // An empty file keeps the collectors we had before the configuration existed
+    let config = Config::from_toml("").unwrap();
+    assert_eq!(config.exchanges.len(), 2);
+    assert_eq!(config.sinks[0].kind, "tectonic");
+    assert_eq!(config.upload.period, 86400);
+
+    let config = Config::from_toml(r#"
+        [transport]
+        kind = "zeromq"
+        wire_format = "binary"
+
+        [tectonic]
+        port = 9002
+
+        [[exchanges]]
+        name = "gdax"
+        symbols = [["ETH", "USD"]]
+        channels = ["level2"]
+
+        [[sinks]]
+        kind = "csv"
+        path = "/tmp/csv"
+        rotation = "size"
+        max_size = 1024
+    "#).unwrap();
+
+    assert_eq!(config.transport.kind, "zeromq");
+    assert_eq!(config.tectonic.port, 9002);
+    assert_eq!(config.tectonic.host, "127.0.0.1");
+    assert_eq!(config.exchanges.len(), 1);
+    assert_eq!(config.exchanges[0].symbols, vec![[Asset::ETH, Asset::USD]]);
+    assert_eq!(config.sinks[0].max_size, Some(1024));
+}
+
+#[test]
+fn config_upload_region() {
+    use rusoto_core::Region;
+
+    use config::Config;
+
+    let config = Config::from_toml("[upload]\nregion = \"eu-west-1\"").unwrap();
+    assert_eq!(config.upload.region().unwrap(), Region::EuWest1);
+
+    // Any region name goes with a custom endpoint, as S3-compatible servers pick their own
+    let config = Config::from_toml("[upload]\nregion = \"minio\"\nendpoint = \"http://localhost:9000\"").unwrap();
+    assert_eq!(config.upload.region().unwrap(), Region::Custom {
+        name: "minio".into(),
+        endpoint: "http://localhost:9000".into(),
+    });
+}
+
+#[test]
+fn config_validation_errors() {
+    use config::Config;
+
+    let errors = vec![
+        ("[transport]\nkind = \"kafka\"", "transport.kind"),
+        ("[transport]\nwire_format = \"xml\"", "transport.wire_format"),
+        ("[upload]\nperiod = 0", "upload.period"),
+        ("[upload]\nregion = \"mars-north-1\"", "upload.region"),
+        ("[upload]\nendpoint = \"localhost 9000\"", "upload.endpoint"),
+        ("[upload]\nencryption_key = \"/nonexistent/archive.key\"", "upload.encryption_key"),
+        ("[[upload.backends]]\nkind = \"ftp\"", "upload.backends[0]"),
+        ("[[upload.backends]]\nkind = \"local\"", "require a path"),
+        ("[[upload.backends]]\nkind = \"s3\"\nretention_days = 0", "retention_days"),
+        ("[[exchanges]]\nname = \"kraken\"\nsymbols = [[\"BTC\", \"USD\"]]", "exchanges[0]"),
+        ("[[exchanges]]\nname = \"bitmex\"\nsymbols = [[\"BTC\", \"USDC\"]]", "USDC"),
+        ("[[exchanges]]\nname = \"gdax\"\nsymbols = []", "no symbols"),
+        ("[[sinks]]\nkind = \"parquet\"", "require a path"),
+        ("[[sinks]]\nkind = \"csv\"\npath = \"/tmp\"\nrotation = \"size\"", "max_size"),
+        ("[tectonic]\nhots = \"localhost\"", "hots"),
+        ("[monitor]\nchannel = \"gdax\"", "monitor.channel"),
+        ("[monitor]\nperpetuals = [\"kraken\"]", "kraken"),
+        ("[monitor.fees]\ngdax = -1.0", "negative fee"),
+        ("[transport]\nkind = \"zeromq\"\n[monitor]\nenabled = true", "monitor.zmq_endpoint"),
+    ];
+
+    for (contents, expected) in errors {
+        let error = Config::from_toml(contents).unwrap_err().to_string();
+        assert!(error.contains(expected), "\"{}\" doesn't mention \"{}\"", error, expected);
+    }
+}
+
+#[test]
+fn config_local_backend_outside_archive_dir() {
+    use std::env;
+    use std::fs::remove_dir_all;
+    use std::os::unix::fs::symlink;
+
+    use config::Config;
+
+    let root = env::temp_dir().join("rusty_road_config_backend_test");
+    let _ = remove_dir_all(&root);
+    let archive_dir = root.join("archives");
+
+    let validate = |path: &str| Config::from_toml(&format!(
+        "[upload]\narchive_dir = \"{}\"\n[[upload.backends]]\nkind = \"local\"\npath = \"{}\"",
+        archive_dir.display(), path));
+
+    // The same folder however it's written, through a symlink, or nested either way
+    validate(&root.join("nfs").to_string_lossy()).unwrap();
+    symlink(&archive_dir, root.join("link")).unwrap();
+
+    for path in vec![
+        format!("{}/", archive_dir.display()),
+        format!("{}/../archives", archive_dir.display()),
+        root.join("link").to_string_lossy().into_owned(),
+        archive_dir.join("nested").to_string_lossy().into_owned(),
+        root.to_string_lossy().into_owned(),
+    ] {
+        let error = validate(&path).unwrap_err().to_string();
+        assert!(error.contains("upload.archive_dir"), "{} was accepted: {}", path, error);
+    }
+
+    remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,112 @@
+#[test]
+fn consolidated_best_bid_offer() {
+    use exchange::{Asset, Exchange};
+    use orderbook::{Book, Snapshot};
+    use orderbook::consolidated::{ConsolidatedBook, Instrument};
+
+    let instrument = Instrument {
+        asset: Asset::BTC,
+        market: Asset::USD,
+    };
+
+    assert_eq!(instrument.to_string(), "BTC-USD");
+    assert_eq!(instrument.symbol(&Exchange::GDAX), Some("BTC-USD".into()));
+
+    let book = |tick_size: f32, bids: Vec<(f32, f32)>, asks: Vec<(f32, f32)>| {
+        let mut book = Book {
+            tick_size,
+            ..Default::default()
+        };
+        book.initialize(&Snapshot {
+            market: None,
+            asset: None,
+            bids,
+            asks,
+        });
+        book
+    };
+
+    let mut consolidated = ConsolidatedBook::new(instrument);
+    assert!(consolidated.best_bid_offer().is_none());
+
+    consolidated.add_venue("gdax", "BTC-USD", book(0.5, vec![(302.0, 5.0), (303.5, 2.0)], vec![(305.0, 1.0), (306.0, 4.0)]));
+    consolidated.add_venue("bitmex", "XBTUSD", book(1.0, vec![(303.0, 10.0), (304.0, 3.0)], vec![(306.0, 6.0), (307.0, 8.0)]));
+
+    let bbo = consolidated.best_bid_offer().unwrap();
+    assert_eq!((bbo.bid.venue.as_str(), bbo.bid.price, bbo.bid.size), ("bitmex", 304.0, 3.0));
+    assert_eq!((bbo.ask.venue.as_str(), bbo.ask.price, bbo.ask.size), ("gdax", 305.0, 1.0));
+    assert_eq!(bbo.spread(), 1.0);
+    assert!(!bbo.is_crossed() && !bbo.is_locked());
+    assert!(consolidated.crossed_markets().is_empty());
+
+    // Levels quoted on both venues are merged, with the largest contributor first
+    let asks = consolidated.asks(2);
+    assert_eq!(asks.len(), 2);
+    assert_eq!((asks[1].price, asks[1].size), (306.0, 10.0));
+    assert_eq!(asks[1].venues, vec![("bitmex".to_string(), 6.0), ("gdax".to_string(), 4.0)]);
+
+    let bids = consolidated.bids(10);
+    let prices: Vec<f32> = bids.iter().map(|level| level.price).collect();
+    assert_eq!(prices, vec![304.0, 303.5, 303.0, 302.0]);
+}
+
+#[test]
+fn consolidated_crossed_markets() {
+    use exchange::Asset;
+    use orderbook::{self, Book, Delta};
+    use orderbook::consolidated::{ConsolidatedBook, Instrument};
+
+    let mut consolidated = ConsolidatedBook::new(Instrument {
+        asset: Asset::BTC,
+        market: Asset::USD,
+    });
+
+    let tick = Book {
+        tick_size: 0.5,
+        ..Default::default()
+    };
+    consolidated.add_venue("gdax", "BTC-USD", tick.clone());
+    consolidated.add_venue("bitmex", "XBTUSD", tick);
+
+    let delta = |symbol: &str, price: f32, size: f32, event: u8| Delta {
+        symbol: symbol.into(),
+        price,
+        size,
+        seq: 0,
+        event,
+        ts: 1538000000.0,
+    };
+
+    consolidated.apply("gdax", &[
+        delta("BTC-USD", 6500.0, 1.0, orderbook::BID | orderbook::INSERT),
+        delta("BTC-USD", 6501.0, 2.0, orderbook::ASK | orderbook::INSERT),
+        // Trades and other symbols don't touch the book
+        delta("BTC-USD", 6400.0, 9.0, orderbook::BID | orderbook::TRADE),
+        delta("ETH-USD", 200.0, 9.0, orderbook::BID | orderbook::INSERT),
+    ]).unwrap();
+    consolidated.apply("bitmex", &[
+        delta("XBTUSD", 6499.0, 3.0, orderbook::BID | orderbook::INSERT),
+        delta("XBTUSD", 6502.0, 4.0, orderbook::ASK | orderbook::INSERT),
+    ]).unwrap();
+
+    assert_eq!(consolidated.venue("gdax").unwrap().bid_price_points.len(), 1);
+    assert!(consolidated.crossed_markets().is_empty());
+
+    // BitMEX bids above what GDAX asks
+    consolidated.apply("bitmex", &[delta("XBTUSD", 6501.5, 5.0, orderbook::BID | orderbook::INSERT)]).unwrap();
+
+    let bbo = consolidated.best_bid_offer().unwrap();
+    assert!(bbo.is_crossed());
+    assert_eq!(bbo.spread(), -0.5);
+
+    let crossed = consolidated.crossed_markets();
+    assert_eq!(crossed.len(), 1);
+    assert_eq!((crossed[0].bid.venue.as_str(), crossed[0].ask.venue.as_str()), ("bitmex", "gdax"));
+
+    // Emptying a side takes the venue out of the BBO on that side
+    consolidated.apply("gdax", &[delta("BTC-USD", 6501.0, 0.0, orderbook::ASK | orderbook::REMOVE)]).unwrap();
+    assert!(consolidated.crossed_markets().is_empty());
+    assert_eq!(consolidated.best_bid_offer().unwrap().ask.venue, "bitmex");
+
+    assert!(consolidated.apply("gdax", &[delta("BTC-USD", 1e9, 1.0, orderbook::BID | orderbook::INSERT)]).is_err());
+}
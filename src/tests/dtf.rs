@@ -0,0 +1,33 @@
+#[test]
+fn dtf_round_trip() {
+    use std::env;
+    use std::fs::remove_file;
+
+    use orderbook::dtf::{self, Update};
+
+    let path = env::temp_dir().join("rusty_road_dtf_test.dtf");
+    let path = path.to_string_lossy();
+
+    // Gaps too large for a single batch (over a minute, or 255 sequence numbers apart) start new ones
+    let updates: Vec<Update> = [(0, 0), (1000, 1), (70000, 2), (70001, 300), (70002, 301)].iter()
+        .map(|&(ts, seq)| Update {
+            ts: 1538000000000 + ts,
+            seq,
+            is_trade: seq == 1,
+            is_bid: seq % 2 == 0,
+            price: 6500.5,
+            size: 0.25,
+        })
+        .collect();
+    dtf::encode(&path, "gdax_BTC-USD", &updates).unwrap();
+
+    let meta = dtf::read_meta(&path).unwrap();
+    assert_eq!(meta.symbol, "gdax_BTC-USD");
+    assert_eq!(meta.nums, 5);
+    assert_eq!(meta.min_ts, 1538000000000);
+    assert_eq!(meta.max_ts, 1538000070002);
+
+    assert_eq!(dtf::decode(&path).unwrap(), updates);
+
+    let _ = remove_file(&*path);
+}
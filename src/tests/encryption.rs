@@ -0,0 +1,72 @@
+#[test]
+fn encryption_round_trip() {
+    use std::io::{ErrorKind, Read, Write};
+
+    use encryption::{DecryptingReader, EncryptingWriter, EncryptionKey, CHUNK_SIZE};
+
+    let key = EncryptionKey::from_base64("AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=").unwrap();
+
+    // Empty, exactly one chunk, and a partial last chunk
+    for size in vec![0, CHUNK_SIZE, 2 * CHUNK_SIZE + 5] {
+        let plaintext: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+
+        let mut writer = EncryptingWriter::new(Vec::new(), &key).unwrap();
+        writer.write_all(&plaintext).unwrap();
+        let encrypted = writer.finish().unwrap();
+        assert_eq!(encrypted.len(), 16 + size + (size / CHUNK_SIZE + 1) * 16);
+
+        let mut decrypted = vec![];
+        DecryptingReader::new(&encrypted[..], &key).unwrap().read_to_end(&mut decrypted).unwrap();
+        assert_eq!(decrypted, plaintext);
+
+        // Truncated archives, tampered archives, trailing data, and the wrong key are all caught
+        let truncated = &encrypted[..encrypted.len() - 1];
+        assert!(DecryptingReader::new(truncated, &key).unwrap().read_to_end(&mut vec![]).is_err());
+
+        let mut tampered = encrypted.clone();
+        tampered[20] ^= 1;
+        assert!(DecryptingReader::new(&tampered[..], &key).unwrap().read_to_end(&mut vec![]).is_err());
+
+        let mut extended = encrypted.clone();
+        extended.extend_from_slice(b"trailing");
+        let error = DecryptingReader::new(&extended[..], &key).unwrap().read_to_end(&mut vec![]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        let wrong_key = EncryptionKey::from_base64("HwECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=").unwrap();
+        assert!(DecryptingReader::new(&encrypted[..], &wrong_key).unwrap().read_to_end(&mut vec![]).is_err());
+    }
+
+    assert!(EncryptionKey::from_base64("c2hvcnQ=").is_err());
+}
+
+#[test]
+fn encrypted_archive_round_trip() {
+    use std::env;
+    use std::fs::{create_dir_all, read, remove_dir_all, write};
+    use std::io::ErrorKind;
+
+    use encryption::{EncryptionKey, ENCRYPTED_MAGIC};
+    use uploader::{self, Codec};
+
+    let root = env::temp_dir().join("rusty_road_encrypted_archive_test");
+    let _ = remove_dir_all(&root);
+
+    let db_path = root.join("db");
+    create_dir_all(&db_path).unwrap();
+    let dtf_file = db_path.join("gdax_BTC-USD.dtf");
+    write(&dtf_file, b"licensed data").unwrap();
+
+    let key = EncryptionKey::from_base64("AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=").unwrap();
+    let archive = root.join("archive.tar.xz.enc").to_string_lossy().into_owned();
+    uploader::compress_files_and_delete(&archive, &[dtf_file], &Codec::default(), Some(&key)).unwrap();
+    assert!(read(&archive).unwrap().starts_with(&ENCRYPTED_MAGIC));
+
+    let unpacked = root.join("unpacked").to_string_lossy().into_owned();
+    let error = uploader::decompress_archive(&archive, &unpacked, None, None).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+
+    uploader::decompress_archive(&archive, &unpacked, None, Some(&key)).unwrap();
+    assert_eq!(read(root.join("unpacked/db/gdax_BTC-USD.dtf")).unwrap(), b"licensed data".to_vec());
+
+    remove_dir_all(&root).unwrap();
+}
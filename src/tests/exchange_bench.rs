@@ -7,6 +7,7 @@ fn bitmex_bench() {
 
     use exchange::{Asset, AssetExchange};
     use exchange::bitmex;
+    use transport::Transport;
 
     // Redis client is setup here so that we can provide it a host, password, and database
     let r = redis::Client::open("redis://127.0.0.1:6379/0").unwrap();
@@ -19,8 +20,10 @@ fn bitmex_bench() {
     bitmex_settings.metadata.asset_pair = Some(vec![
         [Asset::BTC, Asset::USD],]);
 
-    bitmex_settings.r = r.clone();
-    bitmex_settings.r_password = r_password.as_ref().cloned();
+    bitmex_settings.transport = Transport::Redis {
+        client: r,
+        password: r_password,
+    };
 
     let exchange = thread::spawn(move || bitmex::WSExchange::run(Some(&bitmex_settings)));
     let _ = exchange.join();
@@ -35,6 +38,7 @@ fn gdax_bench() {
 
     use exchange::{Asset, AssetExchange};
     use exchange::gdax_l2;
+    use transport::Transport;
 
     // Redis client is setup here so that we can provide it a host, password, and database
     let r = redis::Client::open("redis://127.0.0.1:6379/0").unwrap();
@@ -50,8 +54,10 @@ fn gdax_bench() {
         [Asset::LTC, Asset::USD],
         [Asset::BTC, Asset::USDC],
     ]);
-    gdax_settings.r = r.clone();
-    gdax_settings.r_password = r_password.as_ref().cloned();
+    gdax_settings.transport = Transport::Redis {
+        client: r,
+        password: r_password,
+    };
 
     let exchange = thread::spawn(move || gdax_l2::WSExchange::run(Some(&gdax_settings)));
     let _ = exchange.join();
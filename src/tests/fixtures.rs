@@ -0,0 +1,65 @@
+use std::env;
+use std::fs::{create_dir_all, remove_dir_all};
+use std::path::{Path, PathBuf};
+
+use orderbook::{self, Delta};
+use orderbook::dtf::{self, Update};
+
+/// Empty folder named `name` in the temporary folder. Whatever a previous run left there is removed first
+pub fn temp_root(name: &str) -> PathBuf {
+    let root = env::temp_dir().join(name);
+    let _ = remove_dir_all(&root);
+    create_dir_all(&root).unwrap();
+
+    root
+}
+
+/// Empty `db` folder under `root`, standing in for TectonicDB's data folder
+pub fn db_folder(root: &Path) -> PathBuf {
+    let db_path = root.join("db");
+    create_dir_all(&db_path).unwrap();
+
+    db_path
+}
+
+/// Writes `updates` as the `gdax_BTC-USD` database of a new `db` folder under `root`, and returns the folder
+pub fn database(root: &Path, updates: &[Update]) -> PathBuf {
+    let db_path = db_folder(root);
+    dtf::encode(&db_path.join("gdax_BTC-USD.dtf").to_string_lossy(), "gdax_BTC-USD", updates).unwrap();
+
+    db_path
+}
+
+/// Update of size 1, a second after update `seq - 1`
+pub fn update(seq: u32, price: f32, is_bid: bool, is_trade: bool) -> Update {
+    Update {
+        ts: 1538000000000 + seq as u64 * 1000,
+        seq,
+        is_trade,
+        is_bid,
+        price,
+        size: 1.0,
+    }
+}
+
+/// Delta of size 1, with sequence number 0. Set anything else with struct update syntax
+pub fn delta(symbol: &str, price: f32, event: u8, ts: f64) -> Delta {
+    Delta {
+        symbol: symbol.into(),
+        price,
+        size: 1.0,
+        seq: 0,
+        event,
+        ts,
+    }
+}
+
+/// Deltas on the bid side of BTC-USD, one second apart
+pub fn bids(count: u32) -> Vec<Delta> {
+    (0..count)
+        .map(|seq| Delta {
+            seq,
+            ..delta("BTC-USD", 6500.0 + seq as f32, orderbook::BID | orderbook::UPDATE, 1538000000.0 + seq as f64)
+        })
+        .collect()
+}
@@ -0,0 +1,81 @@
+#[test]
+fn ladder_depth_and_imbalance() {
+    use orderbook::{Book, Snapshot};
+    use orderbook::liquidity::Side;
+
+    let snapshot = Snapshot {
+        market: None,
+        asset: None,
+
+        // Out of order, with an empty level, as they come off an exchange
+        bids: vec![(98.0, 3.0), (99.5, 1.0), (99.0, 2.0), (97.0, 0.0)],
+        asks: vec![(101.0, 4.0), (100.5, 1.0), (102.0, 5.0)],
+    };
+
+    let ladder = snapshot.ladder();
+    assert_eq!(ladder.bids, vec![(99.5, 1.0), (99.0, 2.0), (98.0, 3.0)]);
+    assert_eq!(ladder.mid_price(), Some(100.0));
+
+    assert_eq!(ladder.depth(Side::Bid, 2), 3.0);
+    assert_eq!(ladder.depth(Side::Ask, 10), 10.0);
+
+    // 100 bps of 100 reaches down to 99 and up to 101
+    assert_eq!(ladder.depth_within(Side::Bid, 100.0), 3.0);
+    assert_eq!(ladder.depth_within(Side::Ask, 100.0), 5.0);
+
+    assert_eq!(ladder.cumulative_depth(Side::Ask), vec![(100.5, 1.0), (101.0, 5.0), (102.0, 10.0)]);
+    assert_eq!(ladder.imbalance(1), Some(0.0));
+    assert_eq!(ladder.imbalance(3), Some((6.0 - 10.0) / 16.0));
+
+    // Books give the same ladder as their snapshot
+    let mut book = Book {
+        tick_size: 0.5,
+        ..Default::default()
+    };
+    book.initialize(&snapshot);
+    assert_eq!(book.ladder(), ladder);
+    assert_eq!(book.get_snapshot().ladder(), book.ladder());
+}
+
+#[test]
+fn ladder_execution_cost() {
+    use orderbook::Snapshot;
+
+    let ladder = Snapshot {
+        market: None,
+        asset: None,
+        bids: vec![(99.0, 1.0), (98.0, 1.0)],
+        asks: vec![(101.0, 1.0), (103.0, 1.0)],
+    }.ladder();
+
+    let buy = ladder.buy(1.5).unwrap();
+    assert_eq!(buy.filled, 1.5);
+    assert_eq!(buy.worst_price, 103.0);
+    assert_eq!(buy.vwap, (101.0 + 0.5 * 103.0) / 1.5);
+    assert_eq!(buy.cost, 2.5);
+    assert!((buy.slippage_bps - 2.5 / 150.0 * 10_000.0).abs() < 0.01);
+
+    // Selling more than the book holds fills what's there
+    let sell = ladder.sell(5.0).unwrap();
+    assert_eq!((sell.quantity, sell.filled), (5.0, 2.0));
+    assert_eq!(sell.vwap, 98.5);
+    assert_eq!(sell.cost, 3.0);
+
+    let one_sided = Snapshot {
+        market: None,
+        asset: None,
+        bids: vec![(99.0, 1.0)],
+        asks: vec![],
+    }.ladder();
+    assert!(one_sided.sell(1.0).is_none());
+    assert_eq!(one_sided.imbalance(5), Some(1.0));
+
+    // Levels without a price are left out rather than breaking the sort
+    let with_nan = Snapshot {
+        market: None,
+        asset: None,
+        bids: vec![(99.0, 1.0), (::std::f32::NAN, 1.0), (100.0, 1.0)],
+        asks: vec![],
+    }.ladder();
+    assert_eq!(with_nan.bids, vec![(100.0, 1.0), (99.0, 1.0)]);
+}
@@ -0,0 +1,76 @@
+#[test]
+fn sink_rotation_does_not_block_ingestion() {
+    use std::io::Error;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use listener::{self, SinkWorker};
+    use orderbook::{self, Delta};
+    use sink::Sink;
+
+    /// Counts the deltas written to it, and takes `rotation` to rotate
+    struct Counter {
+        written: Arc<Mutex<usize>>,
+        rotation: Duration,
+    }
+
+    impl Sink for Counter {
+        fn write_batch(&mut self, _exchange: &str, deltas: &[Delta]) -> Result<(), Error> {
+            *self.written.lock().unwrap() += deltas.len();
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn rotate(&mut self) -> Result<(), Error> {
+            thread::sleep(self.rotation);
+            Ok(())
+        }
+
+        fn close(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    let slow = Arc::new(Mutex::new(0));
+    let fast = Arc::new(Mutex::new(0));
+
+    let workers = vec![
+        SinkWorker::spawn(Box::new(Counter { written: slow.clone(), rotation: Duration::from_millis(500) }), Duration::from_millis(10)),
+        SinkWorker::spawn(Box::new(Counter { written: fast.clone(), rotation: Duration::from_millis(0) }), Duration::from_millis(10)),
+    ];
+
+    // Let the slow sink start rotating
+    thread::sleep(Duration::from_millis(100));
+
+    let deltas = (0..3)
+        .map(|seq| Delta {
+            symbol: "BTC-USD".into(),
+            price: 6500.0,
+            size: 1.0,
+            seq,
+            event: orderbook::BID | orderbook::UPDATE,
+            ts: 1538000000.0,
+        })
+        .collect();
+
+    let sent = Instant::now();
+    let mut written = listener::write_to_sinks(&workers, "gdax", deltas);
+
+    // The other sink is written to while the slow one is still rotating
+    while *fast.lock().unwrap() < 3 {
+        assert!(sent.elapsed() < Duration::from_millis(300));
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    // The batch only counts as written once the slow sink is done with it too
+    assert_eq!(written.poll(), None);
+
+    // Everything queued is written before the sinks are closed
+    listener::close_sinks(workers).unwrap();
+    assert_eq!(*slow.lock().unwrap(), 3);
+    assert!(written.wait());
+}
@@ -1,4 +1,17 @@
+mod catalog;
+mod config;
+mod consolidated_book;
+mod dtf;
+mod encryption;
 mod exchange_bench;
+mod fixtures;
+mod liquidity;
 mod listener;
+mod monitor;
 mod orderbook_state;
-mod uploader;
\ No newline at end of file
+mod sink;
+mod storage;
+mod tectonic;
+mod transport;
+mod uploader;
+mod wire;
\ No newline at end of file
@@ -0,0 +1,91 @@
+#[test]
+fn arbitrage_monitor_spreads_and_basis() {
+    use std::io::Error;
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::{self, Value};
+
+    use config::Config;
+    use monitor::ArbitrageMonitor;
+    use orderbook::{self, Delta};
+    use sink::Sink;
+    use transport::Publisher;
+
+    struct Recorder(Arc<Mutex<Vec<(String, Vec<u8>)>>>);
+
+    impl Publisher for Recorder {
+        fn publish(&mut self, channel: &str, payload: &[u8]) -> Result<(), Error> {
+            self.0.lock().unwrap().push((channel.into(), payload.to_vec()));
+            Ok(())
+        }
+    }
+
+    let config = Config::from_toml(r#"
+        [monitor]
+        enabled = true
+        threshold_bps = 5.0
+        fees = { gdax = 10.0, bitmex = 5.0 }
+        tick_sizes = { gdax = 0.5, bitmex = 0.5 }
+
+        [[exchanges]]
+        name = "bitmex"
+        symbols = [["BTC", "USD"]]
+
+        [[exchanges]]
+        name = "gdax"
+        symbols = [["BTC", "USD"], ["ETH", "USD"]]
+    "#).unwrap();
+
+    let published = Arc::new(Mutex::new(vec![]));
+    let mut monitor = ArbitrageMonitor::new(&config.monitor, &config.exchanges, Box::new(Recorder(published.clone())));
+
+    // ETH-USD is only collected on GDAX, so there's nothing to compare it with
+    assert_eq!(monitor.books().len(), 1);
+    assert_eq!(monitor.books()[0].symbol("bitmex"), Some("XBTUSD"));
+
+    let delta = |symbol: &str, price: f32, event: u8| Delta {
+        symbol: symbol.into(),
+        price,
+        size: 1.0,
+        seq: 0,
+        event,
+        ts: 1538000000.0,
+    };
+
+    monitor.write_batch("gdax", &[
+        delta("BTC-USD", 6500.0, orderbook::BID | orderbook::INSERT),
+        delta("BTC-USD", 6501.0, orderbook::ASK | orderbook::INSERT),
+    ]).unwrap();
+
+    // Nothing to compare until BitMEX quotes both sides, or while its own book is crossed
+    assert!(published.lock().unwrap().is_empty());
+
+    monitor.write_batch("bitmex", &[
+        delta("XBTUSD", 6535.0, orderbook::BID | orderbook::INSERT),
+        delta("XBTUSD", 6531.0, orderbook::ASK | orderbook::INSERT),
+    ]).unwrap();
+    assert!(published.lock().unwrap().is_empty());
+
+    monitor.write_batch("bitmex", &[
+        Delta { size: 0.0, ..delta("XBTUSD", 6535.0, orderbook::BID | orderbook::REMOVE) },
+        delta("XBTUSD", 6530.0, orderbook::BID | orderbook::INSERT),
+    ]).unwrap();
+
+    let (channel, payload) = published.lock().unwrap().pop().unwrap();
+    assert_eq!(channel, "arbitrage");
+
+    let events: Value = serde_json::from_slice(&payload).unwrap();
+    let basis = &events[0];
+    assert_eq!(basis["kind"], "basis");
+    assert_eq!((basis["venue"].as_str(), basis["against"].as_str()), (Some("bitmex"), Some("gdax")));
+    assert_eq!(basis["spread"].as_f64(), Some(30.0));
+
+    // Buying the 6501 ask and selling the 6530 bid makes ~44.6 bps, minus 15 bps of fees
+    assert_eq!((basis["buy"].as_str(), basis["sell"].as_str()), (Some("gdax"), Some("bitmex")));
+    let edge = basis["edge_bps"].as_f64().unwrap();
+    assert!((edge - 29.6).abs() < 0.1, "edge is {}", edge);
+
+    // Batches that don't touch a followed book aren't published
+    monitor.write_batch("gdax", &[delta("ETH-USD", 200.0, orderbook::BID | orderbook::INSERT)]).unwrap();
+    assert!(published.lock().unwrap().is_empty());
+}
@@ -100,3 +100,38 @@ fn orderbook_initialize() {
     assert_eq!(new_ob.best_bid_size, new_ob.state[(303.0 / new_ob.tick_size) as usize].unwrap_or(-1.0));
     assert_eq!(new_ob.best_ask_size, new_ob.state[(304.0 / new_ob.tick_size) as usize].unwrap_or(-1.0));
 }
+
+#[test]
+fn orderbook_empty_sides_and_rounding() {
+    use orderbook;
+
+    let mut book = orderbook::Book {
+        tick_size: 0.1,
+        ..Default::default()
+    };
+
+    book.initialize(&orderbook::Snapshot {
+        market: None,
+        asset: None,
+
+        // 0.3 / 0.1 is just under 3 in floating point
+        bids: vec![(0.3, 1.0)],
+        asks: vec![(0.5, 2.0)],
+    });
+
+    assert_eq!(book.best_bid, 3);
+    assert_eq!(book.price_index(0.3), 3);
+
+    // Emptying both sides doesn't panic, and the next level on either side becomes the best one
+    book.new_state(&vec![(3, 0.0, true), (5, 0.0, false)]);
+    assert!(book.bid_price_points.is_empty() && book.ask_price_points.is_empty());
+    assert_eq!((book.best_bid_size, book.best_ask_size), (0.0, 0.0));
+
+    book.new_state(&vec![(2, 4.0, true), (7, 5.0, false)]);
+    assert_eq!((book.best_bid, book.best_ask), (2, 7));
+
+    // Updates after one to the best level are still applied
+    book.new_state(&vec![(2, 6.0, true), (4, 1.0, true), (6, 3.0, false)]);
+    assert_eq!((book.best_bid, book.best_bid_size), (4, 1.0));
+    assert_eq!((book.best_ask, book.best_ask_size), (6, 3.0));
+}
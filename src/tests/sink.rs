@@ -0,0 +1,288 @@
+#[test]
+fn file_sink_size_rotation() {
+    use std::fs::{read_dir, read_to_string, remove_dir_all};
+
+    use config::UploadConfig;
+    use sink::Sink;
+    use sink::file::{FileFormat, FileSink, Rotation};
+    use tests::fixtures;
+
+    let root = fixtures::temp_root("rusty_road_file_sink_test");
+    let deltas = fixtures::bids(10);
+
+    // Small enough that every file will hold a handful of lines before rotating
+    let mut sink = FileSink::new(&root.to_string_lossy(), FileFormat::Csv, Rotation::Size(200), UploadConfig::default());
+    sink.write_batch("gdax", &deltas).unwrap();
+    sink.close().unwrap();
+
+    let files: Vec<_> = read_dir(root.join("gdax").join("BTC-USD"))
+        .unwrap()
+        .map(|entry| read_to_string(entry.unwrap().path()).unwrap())
+        .collect();
+
+    assert!(files.len() > 1);
+    assert!(files.iter().all(|file| file.starts_with("exchange,symbol,ts,seq,price,size,side,type,event\n")));
+
+    // Every delta must have been written exactly once across all of the files
+    let rows: usize = files.iter().map(|file| file.lines().count() - 1).sum();
+    assert_eq!(rows, deltas.len());
+    assert!(files.iter().any(|file| file.contains("gdax,BTC-USD,1538000000.000,0,6500,1,bid,update,36")));
+
+    remove_dir_all(&root).unwrap();
+}
+
+/// Prices stored in every Parquet file directly under `dir`, sorted
+fn parquet_prices(dir: &::std::path::Path) -> Vec<f32> {
+    use std::fs::{read_dir, File};
+
+    use parquet::column::reader::ColumnReader;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    let mut prices = vec![];
+
+    for entry in read_dir(dir).unwrap() {
+        let reader = SerializedFileReader::new(File::open(entry.unwrap().path()).unwrap()).unwrap();
+        let rows = reader.metadata().file_metadata().num_rows() as usize;
+        let mut values = vec![0.0; rows];
+
+        // Price is the fifth column of the schema
+        match reader.get_row_group(0).unwrap().get_column_reader(4).unwrap() {
+            ColumnReader::FloatColumnReader(mut column) => {
+                assert_eq!(column.read_batch(rows, None, None, &mut values).unwrap().0, rows);
+            },
+            _ => panic!("price isn't a float column"),
+        }
+
+        prices.extend(values);
+    }
+
+    prices.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    prices
+}
+
+#[test]
+fn parquet_sink_partitions_round_trip() {
+    use std::fs::{remove_dir_all, remove_file, write};
+
+    use orderbook;
+    use sink::Sink;
+    use sink::parquet::ParquetSink;
+    use tests::fixtures;
+
+    let root = fixtures::temp_root("rusty_road_parquet_sink_test");
+    let delta = |symbol: &str, price: f32, ts: f64| fixtures::delta(symbol, price, orderbook::ASK | orderbook::UPDATE, ts);
+
+    let deltas = vec![
+        delta("BTC-USD", 6500.0, 1538000000.0),
+        delta("BTC-USD", 6501.0, 1538000001.0),
+        delta("BTC-USD", 6600.0, 1538100000.0),
+        delta("ETH-USD", 220.0, 1538000000.0),
+    ];
+
+    // Nothing can be written while the sink's folder is a file. The deltas are kept for the next flush
+    write(root.join("parquet"), b"").unwrap();
+    let mut sink = ParquetSink::new(&root.join("parquet").to_string_lossy());
+    sink.write_batch("gdax", &deltas).unwrap();
+    assert!(sink.flush().is_err());
+
+    remove_file(root.join("parquet")).unwrap();
+    sink.close().unwrap();
+
+    let partitions = root.join("parquet").join("exchange=gdax");
+    assert_eq!(parquet_prices(&partitions.join("symbol=BTC-USD").join("date=2018-09-26")), vec![6500.0, 6501.0]);
+    assert_eq!(parquet_prices(&partitions.join("symbol=BTC-USD").join("date=2018-09-28")), vec![6600.0]);
+    assert_eq!(parquet_prices(&partitions.join("symbol=ETH-USD").join("date=2018-09-26")), vec![220.0]);
+
+    remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn parquet_convert_archive_round_trip() {
+    use std::fs::remove_dir_all;
+
+    use orderbook::dtf::Update;
+
+    use sink::parquet::convert_archive;
+    use tests::fixtures;
+    use uploader::{self, Codec};
+
+    let root = fixtures::temp_root("rusty_road_parquet_convert_test");
+    let updates: Vec<Update> = (0..5)
+        .map(|seq| fixtures::update(seq, 6500.0 + seq as f32, seq % 2 == 0, false))
+        .collect();
+    let db_path = fixtures::database(&root, &updates);
+
+    let archives = uploader::archive_databases(&db_path.to_string_lossy(), &root.join("archives").to_string_lossy(),
+        &Codec::default(), None).unwrap();
+    assert_eq!(archives.len(), 1);
+
+    // Archives that haven't been uploaded yet can be picked up again, metadata included
+    let leftovers = uploader::leftover_archives(&root.join("archives").to_string_lossy()).unwrap();
+    assert_eq!(leftovers.len(), 1);
+    assert_eq!((&leftovers[0].key, &leftovers[0].metadata), (&archives[0].key, &archives[0].metadata));
+
+    let parquet_root = root.join("parquet");
+    convert_archive(&archives[0].path, None, None, &parquet_root.to_string_lossy()).unwrap();
+
+    let prices = parquet_prices(&parquet_root.join("exchange=gdax").join("symbol=BTC-USD").join("date=2018-09-26"));
+    assert_eq!(prices, updates.iter().map(|update| update.price).collect::<Vec<_>>());
+
+    remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn ndjson_sink_retries_failed_uploads() {
+    use std::fs::{read_dir, read_to_string, remove_dir_all, remove_file, write};
+
+    use serde_json;
+
+    use config::{BackendConfig, UploadConfig};
+    use orderbook::{self, Delta};
+    use sink::Sink;
+    use sink::file::{FileFormat, FileSink, Rotation};
+    use tests::fixtures;
+
+    let root = fixtures::temp_root("rusty_road_ndjson_sink_test");
+
+    let backend = root.join("nfs");
+    let mut upload = UploadConfig::default();
+    upload.enabled = true;
+    upload.catalog = root.join("catalog.sqlite").to_string_lossy().into_owned();
+    upload.backends = vec![BackendConfig {
+        kind: "local".into(),
+        path: Some(backend.to_string_lossy().into_owned()),
+        retention_days: None,
+    }];
+
+    let deltas: Vec<Delta> = [1538000000.0, 1538000001.0, 1538100000.0].iter()
+        .enumerate()
+        .map(|(seq, &ts)| Delta {
+            size: 0.5,
+            seq: seq as u32,
+            ..fixtures::delta("BTC-USD", 6500.0 + seq as f32, orderbook::ASK | orderbook::INSERT, ts)
+        })
+        .collect();
+
+    // The backend can't be created while a file is in its way
+    write(&backend, b"").unwrap();
+
+    let files = root.join("files");
+    let mut sink = FileSink::new(&files.to_string_lossy(), FileFormat::Ndjson, Rotation::Daily, upload);
+    sink.write_batch("gdax", &deltas).unwrap();
+    assert!(sink.rotate().is_err());
+
+    // Both days are still waiting to be uploaded
+    assert_eq!(read_dir(files.join("gdax").join("BTC-USD")).unwrap().count(), 2);
+
+    remove_file(&backend).unwrap();
+    sink.close().unwrap();
+    assert_eq!(read_dir(files.join("gdax").join("BTC-USD")).unwrap().count(), 0);
+
+    let days = backend.join("gdax").join("BTC-USD").join("2018").join("09");
+    let mut stored = vec![];
+
+    for day in &["26", "28"] {
+        for entry in read_dir(days.join(day)).unwrap() {
+            for line in read_to_string(entry.unwrap().path()).unwrap().lines() {
+                stored.push(serde_json::from_str::<Delta>(line).unwrap());
+            }
+        }
+    }
+
+    assert_eq!(stored.len(), deltas.len());
+    for (stored, delta) in stored.iter().zip(&deltas) {
+        assert_eq!((stored.seq, stored.price, stored.event, stored.ts), (delta.seq, delta.price, delta.event, delta.ts));
+    }
+
+    remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn sqlite_sink_deltas_trades_and_snapshots() {
+    use std::fs::remove_dir_all;
+
+    use rusqlite::Connection;
+
+    use orderbook::{self, Delta};
+    use sink::Sink;
+    use sink::sqlite::SqliteSink;
+    use tests::fixtures;
+
+    let root = fixtures::temp_root("rusty_road_sqlite_sink_test");
+    let path = root.join("ticks.sqlite");
+
+    let delta = |price: f32, size: f32, event: u8, ts: f64| Delta { size, ..fixtures::delta("BTC-USD", price, event, ts) };
+
+    let mut sink = SqliteSink::new(&path.to_string_lossy()).unwrap();
+    sink.batch_size = 2;
+    sink.snapshot_interval = 10.0;
+
+    sink.write_batch("gdax", &[
+        delta(6500.0, 1.0, orderbook::BID | orderbook::UPDATE, 1538000000.0),
+        delta(6499.0, 2.0, orderbook::BID | orderbook::UPDATE, 1538000001.0),
+        delta(6501.0, 1.0, orderbook::ASK | orderbook::TRADE, 1538000002.0),
+        delta(6500.0, 0.0, orderbook::BID | orderbook::REMOVE, 1538000005.0),
+        // Due for a snapshot, which leaves out the level removed above
+        delta(6502.0, 3.0, orderbook::ASK | orderbook::UPDATE, 1538000010.0),
+    ]).unwrap();
+    sink.close().unwrap();
+
+    let connection = Connection::open(&path).unwrap();
+    let count = |table: &str| connection
+        .query_row(&format!("SELECT COUNT(*) FROM {}", table), &[], |row| row.get::<_, i64>(0))
+        .unwrap();
+
+    assert_eq!(count("deltas"), 4);
+    assert_eq!(count("trades"), 1);
+
+    let mut stmt = connection.prepare("SELECT ts, side, price, size FROM snapshots ORDER BY side, price").unwrap();
+    let levels: Vec<(f64, String, f64, f64)> = stmt
+        .query_map(&[], |row| (row.get(0), row.get(1), row.get(2), row.get(3)))
+        .unwrap()
+        .map(|level| level.unwrap())
+        .collect();
+
+    assert_eq!(levels, vec![
+        (1538000010.0, "ask".into(), 6502.0, 3.0),
+        (1538000010.0, "bid".into(), 6499.0, 2.0),
+    ]);
+
+    remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn sqlite_import_archive() {
+    use std::fs::remove_dir_all;
+
+    use orderbook::dtf::Update;
+    use rusqlite::Connection;
+
+    use sink::sqlite::import_archive;
+    use tests::fixtures;
+    use uploader::{self, Codec};
+
+    let root = fixtures::temp_root("rusty_road_sqlite_import_test");
+    let updates: Vec<Update> = (0..4)
+        .map(|seq| fixtures::update(seq, 6500.0 - seq as f32, true, seq == 3))
+        .collect();
+    let db_path = fixtures::database(&root, &updates);
+
+    let archives = uploader::archive_databases(&db_path.to_string_lossy(), &root.join("archives").to_string_lossy(),
+        &Codec::default(), None).unwrap();
+
+    let sqlite = root.join("ticks.sqlite");
+    import_archive(&archives[0].path, None, None, &sqlite.to_string_lossy()).unwrap();
+
+    let connection = Connection::open(&sqlite).unwrap();
+    let prices: Vec<f64> = connection
+        .prepare("SELECT price FROM deltas WHERE exchange = 'gdax' AND symbol = 'BTC-USD' ORDER BY seq").unwrap()
+        .query_map(&[], |row| row.get(0)).unwrap()
+        .map(|price| price.unwrap())
+        .collect();
+    assert_eq!(prices, vec![6500.0, 6499.0, 6498.0]);
+
+    let trades: i64 = connection.query_row("SELECT COUNT(*) FROM trades", &[], |row| row.get(0)).unwrap();
+    assert_eq!(trades, 1);
+
+    remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,55 @@
+#[test]
+fn local_storage_round_trip_and_retention() {
+    use std::env;
+    use std::fs::{read, remove_dir_all, write};
+
+    use chrono::Duration;
+
+    use catalog::{Catalog, CatalogEntry};
+    use storage::{self, Backend, Storage};
+    use storage::local::LocalStorage;
+    use uploader::ArchiveInfo;
+
+    let root = env::temp_dir().join("rusty_road_storage_test");
+    let _ = remove_dir_all(&root);
+
+    let archive = root.join("archive.tar.xz").to_string_lossy().into_owned();
+    let storage = LocalStorage::new(&root.join("nfs").to_string_lossy()).unwrap();
+    write(&archive, b"archive").unwrap();
+
+    let mut info = ArchiveInfo::new("gdax", "BTC-USD", 1538352000.0);
+    info.include(1538438399.5);
+    let key = info.key("archive.tar.xz");
+
+    let object = storage.put(&archive, &key, &info.metadata()).unwrap();
+    assert_eq!(object.location, format!("file://{}", storage.path(&key).display()));
+    assert_eq!(object.md5, "888d0ee361af3603736f32131e7b20a2");
+    assert_eq!(read(storage.path(&key)).unwrap(), b"archive".to_vec());
+
+    let listed = storage.list().unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].0, "gdax/BTC-USD/2018/10/01/archive.tar.xz");
+
+    let copy = root.join("copy").to_string_lossy().into_owned();
+    assert_eq!(storage.get(&key, &copy).unwrap(), object.md5);
+
+    let catalog = Catalog::open(&root.join("catalog.sqlite").to_string_lossy()).unwrap();
+    catalog.record(&CatalogEntry::from_object(&object).unwrap()).unwrap();
+
+    // Archives younger than the retention are kept, older ones are deleted and forgotten
+    let mut backend = Backend {
+        storage: Box::new(storage),
+        retention: Some(Duration::days(7)),
+    };
+    assert_eq!(storage::apply_retention(&backend, &catalog).unwrap(), 0);
+
+    // Only what the catalog holds is deleted, whatever else shares the folder
+    write(root.join("nfs").join("notes.txt"), b"notes").unwrap();
+
+    backend.retention = Some(Duration::zero());
+    assert_eq!(storage::apply_retention(&backend, &catalog).unwrap(), 1);
+    assert_eq!(backend.storage.list().unwrap().into_iter().map(|(key, _)| key).collect::<Vec<_>>(), vec!["notes.txt"]);
+    assert!(catalog.get(&object.location).unwrap().is_none());
+
+    remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,224 @@
+#[test]
+fn tectonic_pool_reconnect() {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use orderbook::tectonic::TectonicPool;
+
+    // Fake TectonicDB server. The first connection gets dropped as soon as it sends
+    // a command to simulate a server restart. Every connection after that is answered normally.
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = server.local_addr().unwrap().port();
+
+    thread::spawn(move || {
+        for (n, stream) in server.incoming().enumerate() {
+            let mut stream = stream.unwrap();
+
+            thread::spawn(move || {
+                let reader = BufReader::new(stream.try_clone().unwrap());
+
+                for line in reader.lines() {
+                    let line = match line {
+                        Ok(line) => line,
+                        Err(_) => return,
+                    };
+                    if n == 0 {
+                        return;
+                    }
+
+                    let reply: &[u8] = if line == "PING" { b"PONG.\n" } else { b"1\n" };
+                    if stream.write_all(reply).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+
+    let pool = TectonicPool::new(None, Some(port)).unwrap();
+
+    // Health check on the first (dropped) connection forces a reconnect
+    let mut first = pool.get().unwrap();
+    assert!(first.exists("gdax_BTC-USD".into()).unwrap());
+
+    // A second handle taken out while the first one is in use must get its own socket
+    let second = pool.get().unwrap();
+    assert!(first.connection.local_addr().unwrap() != second.connection.local_addr().unwrap());
+}
+
+#[test]
+fn tectonic_subscribe() {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use orderbook::{self, tectonic::TectonicConnection};
+
+    // Fake TectonicDB server that only hands out inserts when polled, like the real one. Subscribers of
+    // gdax_BTC-USD find nothing on their first poll, a batch of inserts on the second, and garbage on the third.
+    // Subscribers of anything else never find anything, and their polls are counted.
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = server.local_addr().unwrap().port();
+    let idle_polls = Arc::new(AtomicUsize::new(0));
+    let counted_polls = idle_polls.clone();
+
+    thread::spawn(move || {
+        for stream in server.incoming() {
+            let mut stream = stream.unwrap();
+            let idle_polls = counted_polls.clone();
+
+            thread::spawn(move || {
+                let reader = BufReader::new(stream.try_clone().unwrap());
+                let mut db = None;
+                let mut polls = 0;
+
+                for line in reader.lines() {
+                    let line = match line {
+                        Ok(line) => line,
+                        Err(_) => return,
+                    };
+
+                    let reply = if line.starts_with("SUBSCRIBE ") {
+                        db = Some(line["SUBSCRIBE ".len()..].to_owned());
+                        "Subscribed\n".to_owned()
+                    } else if db.as_ref().map(|db| db.as_str()) == Some("gdax_BTC-USD") {
+                        polls += 1;
+
+                        match polls {
+                            1 => "NONE\n".to_owned(),
+                            // Long enough to arrive in several reads
+                            2 => format!("[{}]\n", (0..10)
+                                .map(|seq| format!(r#"{{"ts":1538000000.123,"seq":{},"is_trade":true,"is_bid":false,"price":6500.5,"size":0.25}}"#, seq))
+                                .collect::<Vec<String>>()
+                                .join(",")),
+                            _ => "not json\n".to_owned(),
+                        }
+                    } else {
+                        idle_polls.fetch_add(1, Ordering::SeqCst);
+                        "NONE\n".to_owned()
+                    };
+
+                    if stream.write_all(reply.as_bytes()).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+
+    let tectonic = TectonicConnection::new(None, Some(port)).unwrap();
+    let deltas: Vec<_> = tectonic.subscribe("gdax_BTC-USD".into())
+        .unwrap()
+        .collect();
+
+    assert_eq!(deltas.len(), 11);
+    assert!(deltas[10].is_err());
+
+    let delta = deltas[9].as_ref().unwrap();
+    assert_eq!(delta.symbol, "BTC-USD");
+    assert_eq!(delta.seq, 9);
+    assert_eq!(delta.price, 6500.5);
+    assert_eq!(delta.size, 0.25);
+    assert_eq!(delta.event, orderbook::ASK | orderbook::TRADE);
+
+    // Dropping a subscription that never got anything stops the polling
+    let quiet = tectonic.subscribe("gdax_ETH-USD".into()).unwrap();
+    assert!(quiet.recv_timeout(Duration::from_millis(100)).is_err());
+    assert!(idle_polls.load(Ordering::SeqCst) > 0);
+    drop(quiet);
+
+    thread::sleep(Duration::from_millis(100));
+    let polls = idle_polls.load(Ordering::SeqCst);
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(idle_polls.load(Ordering::SeqCst), polls);
+}
+
+#[test]
+fn tectonic_reconnect_replays_bulk_add() {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use orderbook::{self, tectonic::TectonicConnection, Delta};
+
+    // Fake TectonicDB server that records what every connection sends. Bulk adds only get a reply once
+    // they end, like on the real one. The first connection gets dropped once it ends a bulk add.
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = server.local_addr().unwrap().port();
+    let received = Arc::new(Mutex::new(vec![]));
+    let recorded = received.clone();
+
+    thread::spawn(move || {
+        for (n, stream) in server.incoming().enumerate() {
+            let mut stream = stream.unwrap();
+            let recorded = recorded.clone();
+
+            thread::spawn(move || {
+                let reader = BufReader::new(stream.try_clone().unwrap());
+                let mut adding = false;
+
+                for line in reader.lines() {
+                    let line = match line {
+                        Ok(line) => line,
+                        Err(_) => return,
+                    };
+                    recorded.lock().unwrap().push((n, line.clone()));
+
+                    if line == "BULKADD" {
+                        adding = true;
+                    } else if line == "DDAKLUB" {
+                        adding = false;
+
+                        if n == 0 {
+                            return;
+                        }
+                    }
+
+                    if !adding && line != "BULKADD" && stream.write_all(b"1\n").is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+
+    let deltas: Vec<Delta> = (0..3)
+        .map(|seq| Delta {
+            symbol: "BTC-USD".into(),
+            price: 6500.0,
+            size: 1.0,
+            seq,
+            event: orderbook::BID | orderbook::UPDATE,
+            ts: 1538000000.0,
+        })
+        .collect();
+
+    let mut tectonic = TectonicConnection::new(None, Some(port)).unwrap();
+    tectonic.use_db("gdax_BTC-USD".into()).unwrap();
+
+    // Rows don't wait on a reply that never comes
+    let started = Instant::now();
+    assert_eq!(tectonic.bulk_add(&deltas).unwrap(), "1\n");
+    assert!(started.elapsed() < Duration::from_secs(1));
+
+    // The new connection selects the database again, then gets the whole bulk add
+    let replayed: Vec<String> = received.lock().unwrap().iter()
+        .filter(|&&(n, _)| n == 1)
+        .map(|&(_, ref line)| line.clone())
+        .collect();
+
+    assert_eq!(replayed, vec![
+        "USE gdax_BTC-USD",
+        "BULKADD",
+        "1538000000.000, 0, f, t, 6500, 1;",
+        "1538000000.000, 1, f, t, 6500, 1;",
+        "1538000000.000, 2, f, t, 6500, 1;",
+        "DDAKLUB",
+    ]);
+}
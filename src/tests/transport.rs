@@ -0,0 +1,105 @@
+#[test]
+fn zeromq_round_trip() {
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use transport::{Transport, Written};
+
+    // Grab a free port for the listener to bind to
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let transport = Transport::ZeroMQ {
+        endpoint: format!("tcp://127.0.0.1:{}", port),
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let listener_transport = transport.clone();
+    let running = Arc::new(AtomicBool::new(true));
+    let listener_running = running.clone();
+
+    let listener = thread::spawn(move || {
+        listener_transport.listen(&vec![String::from("gdax")], &listener_running, |channel, payload| {
+            let _ = tx.send((channel.to_owned(), payload.to_vec()));
+            Written::done()
+        }).unwrap();
+    });
+
+    let mut publisher = transport.publisher().unwrap();
+    let mut received = None;
+
+    // PUB sockets drop messages until the subscription has propagated, so keep publishing until one arrives.
+    // Messages published on channels we aren't subscribed to must never show up.
+    for _ in 0..100 {
+        publisher.publish("bitmex", b"ignored").unwrap();
+        publisher.publish("gdax", b"[]").unwrap();
+
+        if let Ok(message) = rx.recv_timeout(Duration::from_millis(50)) {
+            received = Some(message);
+            break;
+        }
+    }
+
+    let (channel, payload) = received.expect("No message received over ZeroMQ");
+    assert_eq!(channel, "gdax");
+    assert_eq!(payload, b"[]".to_vec());
+
+    // Once stopped, the listener returns as soon as the publishers go quiet
+    running.store(false, Ordering::SeqCst);
+    listener.join().unwrap();
+}
+
+#[test]
+fn redis_streams_resume() {
+    use std::env;
+
+    use chrono::prelude::*;
+    use redis;
+
+    use transport::Publisher;
+    use transport::redis_streams::{StreamConsumer, StreamPublisher};
+
+    // Requires a local redis-server
+    let r = redis::Client::open("redis://127.0.0.1:6379/0").unwrap();
+    let r_password = match env::var_os("REDIS_AUTH") {
+        Some(password) => Some(password.into_string().unwrap()),
+        None => None
+    };
+
+    // Use a fresh stream every run so that leftovers from previous runs don't interfere
+    let streams = vec![format!("test_stream_{}", Utc::now().timestamp_nanos())];
+    let mut publisher = StreamPublisher::new(&r, r_password.as_ref(), 1000).unwrap();
+
+    let mut consumer = StreamConsumer::new(&r, r_password.as_ref(), &streams, "test_group", "test_consumer").unwrap();
+    publisher.publish(&streams[0], b"first").unwrap();
+    publisher.publish(&streams[0], b"second").unwrap();
+
+    let entries = consumer.read().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].stream, streams[0]);
+    assert_eq!(entries[0].payload, b"first".to_vec());
+
+    // Only acknowledge the first entry, then "crash" the listener
+    consumer.ack(&entries[0]).unwrap();
+    drop(consumer);
+
+    // Published while the listener is down
+    publisher.publish(&streams[0], b"third").unwrap();
+
+    // The restarted listener gets the entry it never acknowledged, followed by the one it missed
+    let mut consumer = StreamConsumer::new(&r, r_password.as_ref(), &streams, "test_group", "test_consumer").unwrap();
+
+    let pending = consumer.read().unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].payload, b"second".to_vec());
+    consumer.ack(&pending[0]).unwrap();
+
+    let missed = consumer.read().unwrap();
+    assert_eq!(missed.len(), 1);
+    assert_eq!(missed[0].payload, b"third".to_vec());
+    consumer.ack(&missed[0]).unwrap();
+
+    let _: i64 = redis::cmd("DEL").arg(&streams[0]).query(&r.get_connection().unwrap()).unwrap();
+}
@@ -0,0 +1,266 @@
+use std::path::PathBuf;
+
+/// DTF files to benchmark the archive codecs on. Set `BENCH_DTF_PATH` to a TectonicDB folder to use real data,
+/// otherwise we write a few files of random-walk deltas to a temporary folder named after `bench`.
+#[cfg(test)]
+fn sample_database(bench: &str) -> Vec<PathBuf> {
+    use std::env;
+    use std::fs::{create_dir_all, read_dir, write};
+
+    use orderbook::{self, Delta};
+    use transport::wire;
+
+    if let Ok(db_path) = env::var("BENCH_DTF_PATH") {
+        return read_dir(db_path).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.is_file())
+            .collect();
+    }
+
+    let db_path = env::temp_dir().join(format!("rusty_road_{}", bench));
+    create_dir_all(&db_path).unwrap();
+
+    ["gdax_BTC-USD", "gdax_ETH-USD", "bitmex_XBTUSD"].iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let mut price = 6500.0 / (i + 1) as f32;
+            let deltas: Vec<Delta> = (0..50_000)
+                .map(|seq| {
+                    // Cheap deterministic walk, so that runs are comparable
+                    price += ((seq * 7919 + i as u32) % 11) as f32 * 0.01 - 0.05;
+                    Delta {
+                        symbol: name.split('_').nth(1).unwrap().into(),
+                        price,
+                        size: (seq * 37 % 1000) as f32 / 100.0,
+                        seq,
+                        event: if seq % 2 == 0 { orderbook::BID } else { orderbook::ASK } ^ orderbook::UPDATE,
+                        ts: 1538000000.0 + seq as f64 * 0.01,
+                    }
+                })
+                .collect();
+
+            let path = db_path.join(format!("{}.dtf", name));
+            write(&path, wire::encode_binary(&deltas).unwrap()).unwrap();
+            path
+        })
+        .collect()
+}
+
+/// Compresses the sample database with `codec` on every iteration and reports the compression ratio
+#[cfg(test)]
+fn bench_codec(b: &mut ::test::Bencher, files: &[PathBuf], codec: &::uploader::Codec) {
+    use std::fs::metadata;
+
+    use uploader;
+
+    let size: u64 = files.iter().map(|file| metadata(file).unwrap().len()).sum();
+
+    let compressed = uploader::compress_files(Vec::new(), files, codec).unwrap().len();
+    println!("{:?}: {} -> {} bytes ({:.2}x)", codec, size, compressed, size as f64 / compressed as f64);
+
+    b.bytes = size;
+    b.iter(|| uploader::compress_files(Vec::new(), files, codec).unwrap());
+}
+
+#[test]
+fn compress_database_round_trip() {
+    use std::fs::{read, read_dir, remove_dir_all, write};
+
+    use tests::fixtures;
+    use uploader::{self, Codec};
+
+    let root = fixtures::temp_root("rusty_road_compress_test");
+    let db_path = fixtures::db_folder(&root);
+    write(db_path.join("gdax_BTC-USD.dtf"), vec![1u8; 100_000]).unwrap();
+    write(db_path.join("bitmex_XBTUSD.dtf"), b"bitmex").unwrap();
+
+    let archive = root.join("archive.tar.xz").to_string_lossy().into_owned();
+    uploader::compress_database_and_delete(&archive, Some(db_path.to_string_lossy().into_owned()), &Codec::Xz { level: 9, threads: 2 }).unwrap();
+
+    // The DTF files are gone, and nothing but the finished archive is left behind
+    assert_eq!(read_dir(&db_path).unwrap().count(), 0);
+    assert!(!root.join("archive.tar.xz.partial").exists());
+
+    let unpacked = root.join("unpacked").to_string_lossy().into_owned();
+    uploader::decompress_archive(&archive, &unpacked, None, None).unwrap();
+
+    assert_eq!(read(root.join("unpacked/db/gdax_BTC-USD.dtf")).unwrap(), vec![1u8; 100_000]);
+    assert_eq!(read(root.join("unpacked/db/bitmex_XBTUSD.dtf")).unwrap(), b"bitmex".to_vec());
+
+    remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn compress_database_failure_keeps_nothing() {
+    use std::env;
+    use std::path::Path;
+
+    use uploader::{self, Codec};
+
+    let archive = env::temp_dir().join("rusty_road_missing_db.tar.xz").to_string_lossy().into_owned();
+    let missing = env::temp_dir().join("rusty_road_missing_db").to_string_lossy().into_owned();
+
+    assert!(uploader::compress_database_and_delete(&archive, Some(missing), &Codec::default()).is_err());
+    assert!(!Path::new(&archive).exists());
+    assert!(!Path::new(&format!("{}.partial", archive)).exists());
+}
+
+#[test]
+fn restore_archive_into_data_folder() {
+    use std::fs::{read, remove_dir_all, write};
+    use std::io::ErrorKind;
+
+    use config::UploadConfig;
+    use tests::fixtures;
+    use uploader::{self, Codec};
+
+    let root = fixtures::temp_root("rusty_road_restore_test");
+    let db_path = fixtures::db_folder(&root);
+    write(db_path.join("gdax_BTC-USD.dtf"), b"gdax").unwrap();
+    write(db_path.join("bitmex_XBTUSD.dtf"), b"bitmex").unwrap();
+
+    let archive = root.join("archive.tar.xz").to_string_lossy().into_owned();
+    uploader::compress_database_and_delete(&archive, Some(db_path.to_string_lossy().into_owned()), &Codec::default()).unwrap();
+
+    // Local archives are used where they are
+    let (fetched, md5) = uploader::fetch_archive(&archive, &root.join("downloads").to_string_lossy(), &UploadConfig::default()).unwrap();
+    assert_eq!((fetched.as_str(), md5), (archive.as_str(), None));
+
+    let restored = uploader::restore_archive(&archive, &db_path.to_string_lossy(), None, None).unwrap();
+    assert_eq!(restored.len(), 2);
+    assert_eq!(read(db_path.join("gdax_BTC-USD.dtf")).unwrap(), b"gdax".to_vec());
+    assert_eq!(read(db_path.join("bitmex_XBTUSD.dtf")).unwrap(), b"bitmex".to_vec());
+
+    // Restoring on top of existing databases is refused, and leaves nothing behind
+    write(db_path.join("gdax_BTC-USD.dtf"), b"newer").unwrap();
+    let error = uploader::restore_archive(&archive, &db_path.to_string_lossy(), None, None).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+    assert_eq!(read(db_path.join("gdax_BTC-USD.dtf")).unwrap(), b"newer".to_vec());
+    assert_eq!(db_path.read_dir().unwrap().count(), 2);
+
+    remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn archive_info_key_and_metadata() {
+    use uploader::ArchiveInfo;
+
+    let mut info = ArchiveInfo::new("gdax", "BTC-USD", 1538352000.5);
+    info.include(1538352000.5);
+    info.include(1538438399.25);
+    info.include(1538352001.0);
+
+    assert_eq!(info.rows, 3);
+    assert_eq!(info.key("archive.tar.xz"), "gdax/BTC-USD/2018/10/01/archive.tar.xz");
+
+    let metadata = info.metadata();
+    assert_eq!(metadata["start"], "2018-10-01T00:00:00.500Z");
+    assert_eq!(metadata["end"], "2018-10-01T23:59:59.250Z");
+    assert_eq!(metadata["rows"], "3");
+    assert_eq!(metadata["exchange"], "gdax");
+}
+
+#[test]
+fn compress_zstd_dictionary_round_trip() {
+    use std::fs::{read, remove_dir_all, write};
+
+    use tests::fixtures;
+    use uploader::{self, Codec};
+
+    let root = fixtures::temp_root("rusty_road_zstd_test");
+    let db_path = fixtures::db_folder(&root);
+    let contents: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8 ^ (i / 1000) as u8).collect();
+    write(db_path.join("gdax_BTC-USD.dtf"), &contents).unwrap();
+
+    let dictionary = root.join("dtf.dict").to_string_lossy().into_owned();
+    uploader::train_dictionary(&db_path.to_string_lossy(), &dictionary, 4096).unwrap();
+
+    let archive = root.join("archive.tar.zst").to_string_lossy().into_owned();
+    let codec = Codec::Zstd { level: 3, dictionary: Some(dictionary.clone()) };
+    uploader::compress_database_and_delete(&archive, Some(db_path.to_string_lossy().into_owned()), &codec).unwrap();
+
+    // The archive can't be read without the dictionary it was compressed with
+    let unpacked = root.join("unpacked").to_string_lossy().into_owned();
+    assert!(uploader::decompress_archive(&archive, &unpacked, None, None).is_err());
+
+    let _ = remove_dir_all(&unpacked);
+    uploader::decompress_archive(&archive, &unpacked, Some(&dictionary), None).unwrap();
+    assert_eq!(read(root.join("unpacked/db/gdax_BTC-USD.dtf")).unwrap(), contents);
+
+    remove_dir_all(&root).unwrap();
+}
+
+#[bench]
+fn codec_xz_9(b: &mut ::test::Bencher) {
+    use uploader::Codec;
+
+    bench_codec(b, &sample_database("codec_xz_9"), &Codec::Xz { level: 9, threads: 1 });
+}
+
+#[bench]
+fn codec_zstd_3(b: &mut ::test::Bencher) {
+    use uploader::Codec;
+
+    bench_codec(b, &sample_database("codec_zstd_3"), &Codec::Zstd { level: 3, dictionary: None });
+}
+
+#[bench]
+fn codec_zstd_19(b: &mut ::test::Bencher) {
+    use uploader::Codec;
+
+    bench_codec(b, &sample_database("codec_zstd_19"), &Codec::Zstd { level: 19, dictionary: None });
+}
+
+#[bench]
+fn codec_zstd_3_dictionary(b: &mut ::test::Bencher) {
+    use std::env;
+
+    use uploader::{self, Codec};
+
+    let files = sample_database("codec_zstd_3_dictionary");
+    let dictionary = env::temp_dir().join("rusty_road_codec_bench.dict").to_string_lossy().into_owned();
+    uploader::train_dictionary(&files[0].parent().unwrap().to_string_lossy(), &dictionary, 112_640).unwrap();
+
+    bench_codec(b, &files, &Codec::Zstd { level: 3, dictionary: Some(dictionary) });
+}
+
+/// Uploads, lists, downloads, and deletes an archive on a local S3-compatible server. Start one with
+/// `docker run -p 9000:9000 -e MINIO_ACCESS_KEY=minio -e MINIO_SECRET_KEY=minio123 minio/minio server /data`,
+/// create the `rusty-road-test` bucket, and run with `AWS_ACCESS_KEY_ID=minio AWS_SECRET_ACCESS_KEY=minio123
+/// cargo test -- --ignored`. Set `S3_ENDPOINT` to use a different server.
+#[test]
+#[ignore]
+fn s3_storage_on_local_server() {
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs::{read, remove_file, write};
+
+    use rusoto_core::Region;
+
+    use storage::Storage;
+    use storage::s3::{self, S3Storage};
+
+    // Large enough to take two parts
+    let archive = env::temp_dir().join("rusty_road_s3_test.tar.xz").to_string_lossy().into_owned();
+    let contents: Vec<u8> = (0..s3::PART_SIZE + 1024).map(|i| (i % 251) as u8).collect();
+    write(&archive, &contents).unwrap();
+
+    let region = Region::Custom {
+        name: "us-east-1".into(),
+        endpoint: env::var("S3_ENDPOINT").unwrap_or("http://localhost:9000".into()),
+    };
+    let storage = S3Storage::new("rusty-road-test", "STANDARD", region);
+
+    let key = "gdax/BTC-USD/2018/10/01/rusty_road_s3_test.tar.xz";
+    let object = storage.put(&archive, key, &HashMap::new()).unwrap();
+    assert_eq!(object.location, format!("s3://rusty-road-test/{}", key));
+    assert!(storage.list().unwrap().iter().any(|(listed, _)| listed == key));
+
+    let downloaded = format!("{}.downloaded", archive);
+    assert_eq!(storage.get(key, &downloaded).unwrap(), object.md5);
+    assert_eq!(read(&downloaded).unwrap(), contents);
+
+    storage.delete(key).unwrap();
+    remove_file(&archive).unwrap();
+    remove_file(&downloaded).unwrap();
+}
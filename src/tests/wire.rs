@@ -0,0 +1,111 @@
+/// Batch of deltas shaped like what the GDAX level2 channel sends us
+fn sample_deltas() -> Vec<::orderbook::Delta> {
+    use orderbook::{self, Delta};
+
+    (0..64)
+        .map(|seq| Delta {
+            symbol: if seq % 2 == 0 { "BTC-USD".into() } else { "ETH-USD".into() },
+            price: 6500.25 + seq as f32 * 0.5,
+            size: if seq % 5 == 0 { 0.0 } else { 1.25 * seq as f32 },
+            seq,
+            event: if seq % 3 == 0 { orderbook::BID } else { orderbook::ASK } ^ orderbook::UPDATE,
+            ts: 1538000000.123 + seq as f64 * 0.001,
+        })
+        .collect()
+}
+
+/// Asserts that every field survived the round trip
+fn assert_deltas_eq(left: &[::orderbook::Delta], right: &[::orderbook::Delta]) {
+    assert_eq!(left.len(), right.len());
+
+    for (l, r) in left.iter().zip(right.iter()) {
+        assert_eq!(l.symbol, r.symbol);
+        assert_eq!(l.price, r.price);
+        assert_eq!(l.size, r.size);
+        assert_eq!(l.seq, r.seq);
+        assert_eq!(l.event, r.event);
+        // JSON doesn't always round trip the last digit of an `f64`
+        assert!((l.ts - r.ts).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn wire_binary_round_trip() {
+    use transport::wire::{self, WireFormat};
+
+    let deltas = sample_deltas();
+    let payload = wire::encode(&deltas, WireFormat::Binary).unwrap();
+
+    assert!(payload.starts_with(&wire::MAGIC));
+    assert_eq!(payload[wire::MAGIC.len()], wire::VERSION);
+    let decoded = wire::decode(&payload).unwrap();
+    assert_deltas_eq(&deltas, &decoded);
+
+    // Unlike JSON, the binary format keeps timestamps exact
+    assert!(deltas.iter().zip(decoded.iter()).all(|(l, r)| l.ts == r.ts));
+
+    // Empty batches are valid too
+    assert!(wire::decode(&wire::encode(&[], WireFormat::Binary).unwrap()).unwrap().is_empty());
+}
+
+#[test]
+fn wire_json_still_decodes() {
+    use serde_json;
+    use transport::wire::{self, WireFormat};
+
+    let deltas = sample_deltas();
+
+    // Payloads published by collectors that still send plain JSON
+    let payload = serde_json::to_string(&deltas).unwrap();
+    assert_deltas_eq(&deltas, &wire::decode(payload.as_bytes()).unwrap());
+    assert_deltas_eq(&deltas, &wire::decode(&wire::encode(&deltas, WireFormat::Json).unwrap()).unwrap());
+}
+
+#[test]
+fn wire_rejects_bad_payloads() {
+    use transport::wire::{self, WireFormat};
+
+    let payload = wire::encode(&sample_deltas(), WireFormat::Binary).unwrap();
+
+    // Truncated payload
+    assert!(wire::decode(&payload[..payload.len() - 1]).is_err());
+
+    // Payloads from any other version of the format, future or not
+    for &version in &[0, wire::VERSION + 1] {
+        let mut other = payload.clone();
+        other[wire::MAGIC.len()] = version;
+        assert!(wire::decode(&other).is_err());
+    }
+}
+
+#[bench]
+fn wire_json_encode(b: &mut ::test::Bencher) {
+    use transport::wire::{self, WireFormat};
+
+    let deltas = sample_deltas();
+    b.iter(|| wire::encode(&deltas, WireFormat::Json).unwrap());
+}
+
+#[bench]
+fn wire_binary_encode(b: &mut ::test::Bencher) {
+    use transport::wire::{self, WireFormat};
+
+    let deltas = sample_deltas();
+    b.iter(|| wire::encode(&deltas, WireFormat::Binary).unwrap());
+}
+
+#[bench]
+fn wire_json_decode(b: &mut ::test::Bencher) {
+    use transport::wire::{self, WireFormat};
+
+    let payload = wire::encode(&sample_deltas(), WireFormat::Json).unwrap();
+    b.iter(|| wire::decode(&payload).unwrap());
+}
+
+#[bench]
+fn wire_binary_decode(b: &mut ::test::Bencher) {
+    use transport::wire::{self, WireFormat};
+
+    let payload = wire::encode(&sample_deltas(), WireFormat::Binary).unwrap();
+    b.iter(|| wire::decode(&payload).unwrap());
+}
@@ -0,0 +1,178 @@
+use std::io::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::time::{Duration, Instant};
+
+use redis;
+
+/// Redis PUBSUB transport
+pub mod redis_pubsub;
+/// Redis Streams transport with consumer groups
+pub mod redis_streams;
+/// Encodings used for the deltas sent over a transport
+pub mod wire;
+/// ZeroMQ PUB/SUB transport
+pub mod zeromq;
+
+/// Default ZeroMQ endpoint. The listener binds to it, and the exchanges connect to it.
+pub const DEFAULT_ZMQ_ENDPOINT: &str = "ipc:///tmp/rusty_road.ipc";
+/// Default (approximate) maximum amount of entries kept in every Redis stream
+pub const DEFAULT_STREAM_MAX_LEN: usize = 1_000_000;
+
+/// How long listeners wait on a message before checking whether they've been asked to stop
+pub const POLL_TIMEOUT: Duration = Duration::from_secs(1);
+/// Longest a listener keeps draining messages once it's been asked to stop
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest a listener waits between two attempts at reconnecting
+pub const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Publishing half of a transport. Exchanges publish their deltas on a channel named after the exchange.
+pub trait Publisher: Send {
+    /// Publishes `payload` on `channel`
+    fn publish(&mut self, channel: &str, payload: &[u8]) -> Result<(), Error>;
+}
+
+/// Message bus used to move deltas from the exchanges to the listener.
+#[derive(Clone)]
+pub enum Transport {
+    /// Redis PUBSUB. Messages go through the Redis server
+    Redis {
+        /// Redis client (before connection)
+        client: redis::Client,
+        /// Redis password: If this is present, we will send an AUTH message to the server on connect
+        password: Option<String>,
+    },
+    /// Redis Streams. Exchanges `XADD` to a stream per exchange, and listeners read through a consumer group.
+    /// Unlike PUBSUB, nothing is lost while the listener is down, as long as the stream doesn't outgrow `max_len`.
+    RedisStreams {
+        /// Redis client (before connection)
+        client: redis::Client,
+        /// Redis password: If this is present, we will send an AUTH message to the server on connect
+        password: Option<String>,
+        /// Approximate maximum amount of entries kept in every stream
+        max_len: usize,
+        /// Consumer group the listeners read through
+        group: String,
+        /// Name of the listener inside the consumer group. Must stay the same across restarts to resume
+        consumer: String,
+    },
+    /// ZeroMQ PUB/SUB. Exchanges talk to the listener directly, without a server in between
+    ZeroMQ {
+        /// Endpoint the listener binds to (e.g. `ipc:///tmp/rusty_road.ipc` or `tcp://127.0.0.1:5556`)
+        endpoint: String,
+    },
+}
+
+impl Transport {
+    /// Creates a new publisher on this transport
+    pub fn publisher(&self) -> Result<Box<Publisher>, Error> {
+        match self {
+            Transport::Redis { client, password } =>
+                Ok(Box::new(redis_pubsub::RedisPublisher::new(client, password.as_ref())?)),
+            Transport::RedisStreams { client, password, max_len, .. } =>
+                Ok(Box::new(redis_streams::StreamPublisher::new(client, password.as_ref(), *max_len)?)),
+            Transport::ZeroMQ { endpoint } =>
+                Ok(Box::new(zeromq::ZmqPublisher::new(endpoint)?)),
+        }
+    }
+
+    /// Subscribes to `channels` and calls `on_message` with the channel name and payload of every
+    /// message received. Blocks until `running` is set to false, so call it from its own thread.
+    ///
+    /// `on_message` returns a [`Written`] that reports once the message has been written. Redis Streams only
+    /// acknowledges a message once it's been written; the other transports can't redeliver anything, and ignore it.
+    ///
+    /// Messages still in flight when `running` is cleared are drained: we only return once no message
+    /// arrived for [`POLL_TIMEOUT`], or after [`DRAIN_TIMEOUT`] if the publishers never go quiet.
+    pub fn listen<F>(&self, channels: &[String], running: &AtomicBool, on_message: F) -> Result<(), Error>
+        where F: FnMut(&str, &[u8]) -> Written {
+
+        let drain = Drain::new(running);
+
+        match self {
+            Transport::Redis { client, password } =>
+                redis_pubsub::listen(client, password.as_ref(), channels, drain, on_message),
+            Transport::RedisStreams { client, password, group, consumer, .. } =>
+                redis_streams::listen(client, password.as_ref(), channels, group, consumer, drain, on_message),
+            Transport::ZeroMQ { endpoint } =>
+                zeromq::listen(endpoint, channels, drain, on_message),
+        }
+    }
+}
+
+/// Decides when a listener that has been asked to stop is done draining
+pub struct Drain<'a> {
+    /// Cleared once the listener should stop
+    running: &'a AtomicBool,
+    /// When we first noticed `running` was cleared
+    stop_requested: Option<Instant>,
+}
+
+impl<'a> Drain<'a> {
+    /// Creates a new drain watching `running`
+    pub fn new(running: &'a AtomicBool) -> Drain<'a> {
+        Drain {
+            running,
+            stop_requested: None,
+        }
+    }
+
+    /// Returns true once the listener should return. `idle` is true if the last read timed out without a message.
+    pub fn is_done(&mut self, idle: bool) -> bool {
+        if self.running.load(Ordering::SeqCst) {
+            return false;
+        }
+
+        let stop_requested = *self.stop_requested.get_or_insert(Instant::now());
+
+        idle || stop_requested.elapsed() >= DRAIN_TIMEOUT
+    }
+}
+
+/// Reports whether a message handed to a listener was written by everyone it was handed to
+pub struct Written {
+    /// Receives one report per writer
+    receiver: Receiver<bool>,
+    /// Reports we're still waiting on
+    pending: usize,
+}
+
+impl Written {
+    /// Creates a report that waits on `writers` reports, sent on the returned sender. A writer that drops its
+    /// sender without reporting counts as a failure.
+    pub fn new(writers: usize) -> (Sender<bool>, Written) {
+        let (sender, receiver) = mpsc::channel();
+
+        (sender, Written { receiver, pending: writers })
+    }
+
+    /// Creates a report for a message there was nothing to write for
+    pub fn done() -> Written {
+        Written::new(0).1
+    }
+
+    /// Returns whether the message was written everywhere, or None if some writers haven't reported yet
+    pub fn poll(&mut self) -> Option<bool> {
+        while self.pending > 0 {
+            match self.receiver.try_recv() {
+                Ok(true) => self.pending -= 1,
+                Ok(false) | Err(TryRecvError::Disconnected) => return Some(false),
+                Err(TryRecvError::Empty) => return None,
+            }
+        }
+
+        Some(true)
+    }
+
+    /// Blocks until every writer has reported, and returns whether the message was written everywhere
+    pub fn wait(&mut self) -> bool {
+        while self.pending > 0 {
+            match self.receiver.recv() {
+                Ok(true) => self.pending -= 1,
+                Ok(false) | Err(_) => return false,
+            }
+        }
+
+        true
+    }
+}
@@ -0,0 +1,107 @@
+use std::cmp::min;
+use std::io::{Error, ErrorKind};
+use std::thread;
+use std::time::Duration;
+
+use redis::{self, Commands};
+
+use transport::{Drain, Publisher, Written, MAX_RECONNECT_DELAY, POLL_TIMEOUT};
+
+/// Publishes messages through Redis PUBSUB
+pub struct RedisPublisher {
+    /// Authenticated redis connection
+    connection: redis::Connection,
+}
+
+impl RedisPublisher {
+    /// Connects to redis, authenticating if a password is present
+    pub fn new(client: &redis::Client, password: Option<&String>) -> Result<RedisPublisher, Error> {
+        Ok(RedisPublisher {
+            connection: connect(client, password)?,
+        })
+    }
+}
+
+impl Publisher for RedisPublisher {
+    fn publish(&mut self, channel: &str, payload: &[u8]) -> Result<(), Error> {
+        self.connection.publish::<&str, &[u8], u8>(channel, payload)
+            .map_err(to_io_error)?;
+
+        Ok(())
+    }
+}
+
+/// Subscribes to `channels` on redis and hands every message to `on_message` until `drain` is done.
+/// Whenever the connection fails, we reconnect and subscribe again, waiting twice as long after every failed attempt
+/// (up to [`MAX_RECONNECT_DELAY`]). Messages published while we're disconnected are lost.
+pub fn listen<F>(client: &redis::Client, password: Option<&String>, channels: &[String],
+                 mut drain: Drain, mut on_message: F) -> Result<(), Error>
+    where F: FnMut(&str, &[u8]) -> Written {
+
+    let mut delay = POLL_TIMEOUT;
+
+    loop {
+        match receive(client, password, channels, &mut drain, &mut on_message, &mut delay) {
+            Ok(_) => return Ok(()),
+            Err(e) => println!("Log Error: Lost connection to redis: {}. Reconnecting in {}s", e, delay.as_secs()),
+        }
+
+        // No point in reconnecting if we've been asked to stop
+        if drain.is_done(true) {
+            return Ok(());
+        }
+
+        thread::sleep(delay);
+        delay = min(delay * 2, MAX_RECONNECT_DELAY);
+    }
+}
+
+/// Connects to redis, authenticating if a password is present
+pub fn connect(client: &redis::Client, password: Option<&String>) -> Result<redis::Connection, Error> {
+    let connection = client.get_connection().map_err(to_io_error)?;
+
+    if let Some(password) = password {
+        redis::cmd("AUTH").arg(password).query::<()>(&connection).map_err(to_io_error)?;
+    }
+
+    Ok(connection)
+}
+
+/// Subscribes to `channels` on a new connection and hands every message to `on_message`. Returns once `drain`
+/// is done, or with the first error that isn't a timeout. `delay` is reset once we're subscribed.
+fn receive<F>(client: &redis::Client, password: Option<&String>, channels: &[String], drain: &mut Drain,
+              on_message: &mut F, delay: &mut Duration) -> Result<(), Error>
+    where F: FnMut(&str, &[u8]) -> Written {
+
+    let mut redis_conn = connect(client, password)?;
+    // Wake up every now and then to check whether we've been asked to stop
+    redis_conn.set_read_timeout(Some(POLL_TIMEOUT)).map_err(to_io_error)?;
+    let mut subscription = redis_conn.as_pubsub();
+
+    for channel in channels {
+        subscription.subscribe(channel).map_err(to_io_error)?;
+    }
+
+    *delay = POLL_TIMEOUT;
+
+    loop {
+        let idle = match subscription.get_message() {
+            Ok(message) => {
+                // Nothing can be delivered again, so there's no point in waiting on the write
+                let _ = on_message(message.get_channel_name(), message.get_payload_bytes());
+                false
+            },
+            Err(ref e) if e.is_timeout() => true,
+            Err(e) => return Err(to_io_error(e)),
+        };
+
+        if drain.is_done(idle) {
+            return Ok(());
+        }
+    }
+}
+
+/// Redis errors are converted to IO errors, as that's what the transport methods return
+fn to_io_error(e: redis::RedisError) -> Error {
+    Error::new(ErrorKind::Other, e.to_string())
+}
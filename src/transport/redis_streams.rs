@@ -0,0 +1,304 @@
+use std::cmp::min;
+use std::io::{Error, ErrorKind};
+use std::mem;
+use std::thread;
+
+use redis::{self, Value};
+
+use transport::{redis_pubsub, Drain, Publisher, Written, MAX_RECONNECT_DELAY, POLL_TIMEOUT};
+
+/// Maximum amount of entries read at once
+const READ_COUNT: usize = 1_000;
+
+/// Publishes messages with `XADD` to a stream named after the channel. Streams are capped
+/// (approximately) at `max_len` entries so that they don't grow without bounds.
+pub struct StreamPublisher {
+    /// Authenticated redis connection
+    connection: redis::Connection,
+    /// Approximate maximum length of every stream
+    max_len: usize,
+}
+
+impl StreamPublisher {
+    /// Connects to redis, authenticating if a password is present
+    pub fn new(client: &redis::Client, password: Option<&String>, max_len: usize) -> Result<StreamPublisher, Error> {
+        Ok(StreamPublisher {
+            connection: redis_pubsub::connect(client, password)?,
+            max_len,
+        })
+    }
+}
+
+impl Publisher for StreamPublisher {
+    fn publish(&mut self, channel: &str, payload: &[u8]) -> Result<(), Error> {
+        let _: String = redis::cmd("XADD")
+            .arg(channel)
+            .arg("MAXLEN").arg("~").arg(self.max_len)
+            .arg("*")
+            .arg("payload").arg(payload)
+            .query(&self.connection)
+            .map_err(to_io_error)?;
+
+        Ok(())
+    }
+}
+
+/// Single stream entry read through a consumer group
+pub struct StreamEntry {
+    /// Stream (i.e. channel) the entry was read from
+    pub stream: String,
+    /// Entry ID. Needed to acknowledge the entry
+    pub id: String,
+    /// Message payload
+    pub payload: Vec<u8>,
+}
+
+/// Reads streams through a consumer group. Entries stay pending in the group until they're
+/// acknowledged, so a consumer that restarts (under the same name) picks up everything it
+/// hadn't acknowledged yet, followed by everything that was added while it was down.
+pub struct StreamConsumer {
+    /// Authenticated redis connection
+    connection: redis::Connection,
+    /// Streams we read from
+    streams: Vec<String>,
+    /// Consumer group name
+    group: String,
+    /// Name of this consumer inside the group
+    consumer: String,
+    /// Set once we've gone through the entries left pending by a previous run
+    pending_done: bool,
+}
+
+impl StreamConsumer {
+    /// Connects to redis and creates the consumer group on every stream, if it doesn't exist yet.
+    /// New groups start reading from the end of the stream.
+    pub fn new(client: &redis::Client, password: Option<&String>, streams: &[String],
+               group: &str, consumer: &str) -> Result<StreamConsumer, Error> {
+
+        let connection = redis_pubsub::connect(client, password)?;
+
+        for stream in streams {
+            let created: redis::RedisResult<String> = redis::cmd("XGROUP")
+                .arg("CREATE").arg(stream).arg(group).arg("$").arg("MKSTREAM")
+                .query(&connection);
+
+            match created {
+                Ok(_) => (),
+                // Group was created by a previous run. This is what lets us resume
+                Err(ref e) if e.to_string().contains("BUSYGROUP") => (),
+                Err(e) => return Err(to_io_error(e)),
+            }
+        }
+
+        Ok(StreamConsumer {
+            connection,
+            streams: streams.to_vec(),
+            group: group.to_owned(),
+            consumer: consumer.to_owned(),
+            pending_done: false,
+        })
+    }
+
+    /// Reads the next batch of entries. Entries delivered to this consumer but never acknowledged are
+    /// read first, then we block for up to [`POLL_TIMEOUT`] waiting on new entries. Returns an empty batch on timeout.
+    pub fn read(&mut self) -> Result<Vec<StreamEntry>, Error> {
+        while !self.pending_done {
+            let (entries, trimmed) = self.read_group("0", false)?;
+
+            if !entries.is_empty() {
+                return Ok(entries)
+            }
+
+            // Keep going while there are trimmed entries to acknowledge, as they hide whatever is pending after them
+            self.pending_done = trimmed == 0;
+        }
+
+        Ok(self.read_group(">", true)?.0)
+    }
+
+    /// Acknowledges an entry, removing it from the group's pending list
+    pub fn ack(&mut self, entry: &StreamEntry) -> Result<(), Error> {
+        self.ack_id(&entry.stream, &entry.id)
+    }
+
+    /// Acknowledges the entry `id` of `stream`
+    fn ack_id(&mut self, stream: &str, id: &str) -> Result<(), Error> {
+        let _: i64 = redis::cmd("XACK")
+            .arg(stream)
+            .arg(&self.group)
+            .arg(id)
+            .query(&self.connection)
+            .map_err(to_io_error)?;
+
+        Ok(())
+    }
+
+    /// Runs `XREADGROUP` with the same starting `id` for every stream. Entries without a payload can't be handed
+    /// to anyone, so they're acknowledged right away. Returns the other entries, along with how many were acknowledged.
+    fn read_group(&mut self, id: &str, block: bool) -> Result<(Vec<StreamEntry>, usize), Error> {
+        let mut cmd = redis::cmd("XREADGROUP");
+        cmd.arg("GROUP").arg(&self.group).arg(&self.consumer)
+            .arg("COUNT").arg(READ_COUNT);
+
+        if block {
+            cmd.arg("BLOCK").arg(POLL_TIMEOUT.as_secs() * 1000);
+        }
+
+        cmd.arg("STREAMS").arg(&self.streams);
+        for _ in &self.streams {
+            cmd.arg(id);
+        }
+
+        let reply: Value = cmd.query(&self.connection).map_err(to_io_error)?;
+        let (entries, trimmed) = parse_entries(&reply);
+
+        for (stream, id) in &trimmed {
+            self.ack_id(stream, id)?;
+        }
+
+        Ok((entries, trimmed.len()))
+    }
+}
+
+/// Reads `streams` through the consumer group `group` and hands every entry to `on_message`.
+/// Entries are acknowledged once the [`Written`] returned by `on_message` reports them written. Returns once `drain`
+/// is done, after waiting on every entry handed out so far.
+///
+/// Whenever reading fails, we reconnect, waiting twice as long after every failed attempt (up to [`MAX_RECONNECT_DELAY`]).
+/// Nothing is lost in the meantime: entries are picked up where we left off, starting with those that were
+/// delivered but never acknowledged. Entries can be delivered more than once this way, but never skipped.
+///
+/// Note that "written" means the sinks took the entry; sinks that buffer their writes can still lose whatever they
+/// hadn't flushed if the process dies.
+pub fn listen<F>(client: &redis::Client, password: Option<&String>, streams: &[String],
+                 group: &str, consumer: &str, mut drain: Drain, mut on_message: F) -> Result<(), Error>
+    where F: FnMut(&str, &[u8]) -> Written {
+
+    let mut delay = POLL_TIMEOUT;
+    // Entries handed out but not acknowledged yet. Kept across reconnects, so that those written in the meantime
+    // are acknowledged before the new consumer reads its pending entries again
+    let mut unacked = vec![];
+
+    loop {
+        let received = StreamConsumer::new(client, password, streams, group, consumer)
+            .and_then(|mut stream_consumer| {
+                delay = POLL_TIMEOUT;
+                receive(&mut stream_consumer, &mut unacked, &mut drain, &mut on_message)
+            });
+
+        match received {
+            Ok(_) => return Ok(()),
+            Err(e) => println!("Log Error: Lost connection to redis: {}. Reconnecting in {}s", e, delay.as_secs()),
+        }
+
+        // Anything left unread or unacknowledged stays in the stream, and is picked up on the next start
+        if drain.is_done(true) {
+            return Ok(());
+        }
+
+        thread::sleep(delay);
+        delay = min(delay * 2, MAX_RECONNECT_DELAY);
+    }
+}
+
+/// Hands every entry read by `stream_consumer` to `on_message`, queueing it in `unacked` until it's written.
+/// Returns once `drain` is done, or with the first read error.
+fn receive<F>(stream_consumer: &mut StreamConsumer, unacked: &mut Vec<(StreamEntry, Written)>,
+              drain: &mut Drain, on_message: &mut F) -> Result<(), Error>
+    where F: FnMut(&str, &[u8]) -> Written {
+
+    loop {
+        acknowledge(stream_consumer, unacked, false);
+
+        let entries = stream_consumer.read()?;
+        let idle = entries.is_empty();
+
+        for entry in entries {
+            let written = on_message(&entry.stream, &entry.payload);
+            unacked.push((entry, written));
+        }
+
+        // Anything left unread stays in the stream, and is picked up on the next start
+        if drain.is_done(idle) {
+            acknowledge(stream_consumer, unacked, true);
+            return Ok(());
+        }
+    }
+}
+
+/// Acknowledges the entries of `unacked` that have been written, and keeps the others queued. If `wait` is set,
+/// blocks until every entry has been handled.
+///
+/// Entries that failed to be written, or to be acknowledged, stay pending, and are delivered again once we reconnect.
+fn acknowledge(stream_consumer: &mut StreamConsumer, unacked: &mut Vec<(StreamEntry, Written)>, wait: bool) {
+    for (entry, mut written) in mem::replace(unacked, vec![]) {
+        let done = if wait { Some(written.wait()) } else { written.poll() };
+
+        match done {
+            Some(true) => if let Err(e) = stream_consumer.ack(&entry) {
+                println!("Log Error: Failed to acknowledge {} on {}: {}", entry.id, entry.stream, e);
+            },
+            Some(false) => println!("Log Error: {} on {} wasn't written to every sink. Leaving it pending", entry.id, entry.stream),
+            None => unacked.push((entry, written)),
+        }
+    }
+}
+
+/// Parses an `XREADGROUP` reply. The reply looks like
+/// `[[stream, [[id, [field, value, ...]], ...]], ...]`, or nil if the read timed out.
+///
+/// Pending entries that were trimmed off their stream since they were delivered come back as `[id, nil]`. They're
+/// returned apart, as (stream, id), along with any other entry missing a payload.
+fn parse_entries(reply: &Value) -> (Vec<StreamEntry>, Vec<(String, String)>) {
+    let mut entries = vec![];
+    let mut trimmed = vec![];
+
+    let streams = match reply {
+        Value::Bulk(streams) => streams,
+        _ => return (entries, trimmed),
+    };
+
+    for stream in streams {
+        let (name, messages) = match stream {
+            Value::Bulk(stream) if stream.len() == 2 => match (&stream[0], &stream[1]) {
+                (Value::Data(name), Value::Bulk(messages)) => (String::from_utf8_lossy(name).into_owned(), messages),
+                _ => continue,
+            },
+            _ => continue,
+        };
+
+        for message in messages {
+            let (id, fields) = match message {
+                Value::Bulk(message) if message.len() == 2 => match (&message[0], &message[1]) {
+                    (Value::Data(id), Value::Bulk(fields)) => (String::from_utf8_lossy(id).into_owned(), Some(fields)),
+                    (Value::Data(id), Value::Nil) => (String::from_utf8_lossy(id).into_owned(), None),
+                    _ => continue,
+                },
+                _ => continue,
+            };
+
+            let payload = fields.and_then(|fields| fields.chunks(2)
+                .filter_map(|pair| match pair {
+                    [Value::Data(field), Value::Data(payload)] if field.as_slice() == b"payload" => Some(payload),
+                    _ => None,
+                })
+                .next());
+
+            match payload {
+                Some(payload) => entries.push(StreamEntry {
+                    stream: name.clone(),
+                    id,
+                    payload: payload.clone(),
+                }),
+                None => trimmed.push((name.clone(), id)),
+            }
+        }
+    }
+
+    (entries, trimmed)
+}
+
+/// Redis errors are converted to IO errors, as that's what the transport methods return
+fn to_io_error(e: redis::RedisError) -> Error {
+    Error::new(ErrorKind::Other, e.to_string())
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::io::{Cursor, Error, ErrorKind, Read};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use serde_json;
+
+use orderbook::Delta;
+
+/// First bytes of every binary payload. JSON payloads always start with `[`, so this
+/// is enough for the listener to tell the two formats apart.
+pub const MAGIC: [u8; 2] = [0xD7, 0x1A];
+/// Version of the binary format written by [`encode_binary`]
+pub const VERSION: u8 = 1;
+
+/// Size of the header: magic, version, symbol count (u16), and delta count (u32)
+const HEADER_SIZE: usize = 2 + 1 + 2 + 4;
+/// Size of a single encoded delta: symbol index (u16), price (f32), size (f32), seq (u32), event (u8), ts (f64)
+const DELTA_SIZE: usize = 2 + 4 + 4 + 4 + 1 + 8;
+
+/// Encoding used for the deltas published on the message bus
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WireFormat {
+    /// JSON array of deltas. Readable by anyone, but verbose
+    Json,
+    /// Compact, versioned binary encoding. See [`encode_binary`]
+    Binary,
+}
+
+/// Encodes a batch of deltas using `format`
+pub fn encode(deltas: &[Delta], format: WireFormat) -> Result<Vec<u8>, Error> {
+    match format {
+        WireFormat::Json => serde_json::to_vec(deltas)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e)),
+        WireFormat::Binary => encode_binary(deltas),
+    }
+}
+
+/// Decodes a batch of deltas. There's no negotiation between publishers and listeners: the format of every
+/// payload is detected as it's received, from its first bytes, so publishers using either format can share a
+/// single listener. Consumers that only understand JSON need every publisher to stick to [`WireFormat::Json`].
+pub fn decode(payload: &[u8]) -> Result<Vec<Delta>, Error> {
+    if payload.starts_with(&MAGIC) {
+        decode_binary(payload)
+    } else {
+        serde_json::from_slice(payload)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+}
+
+/// Encodes deltas in the binary format. All values are little endian.
+///
+/// ```text
+/// header:       magic [u8; 2] | version u8 | symbol count u16 | delta count u32
+/// symbol table: (length u8 | utf-8 bytes) for every symbol
+/// deltas:       symbol index u16 | price f32 | size f32 | seq u32 | event u8 | ts f64
+/// ```
+///
+/// Symbols are interned, so every delta has the same fixed width no matter how long its symbol is.
+pub fn encode_binary(deltas: &[Delta]) -> Result<Vec<u8>, Error> {
+    let mut symbols: Vec<&str> = vec![];
+    let mut symbol_indexes: HashMap<&str, u16> = HashMap::new();
+
+    for delta in deltas {
+        if !symbol_indexes.contains_key(delta.symbol.as_str()) {
+            if symbols.len() == u16::max_value() as usize {
+                return Err(Error::new(ErrorKind::InvalidInput, "Too many symbols in a single batch"))
+            }
+            if delta.symbol.len() > u8::max_value() as usize {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("Symbol {} is too long", delta.symbol)))
+            }
+
+            symbol_indexes.insert(delta.symbol.as_str(), symbols.len() as u16);
+            symbols.push(delta.symbol.as_str());
+        }
+    }
+
+    let symbols_size: usize = symbols.iter().map(|symbol| 1 + symbol.len()).sum();
+    let mut buf = Vec::with_capacity(HEADER_SIZE + symbols_size + deltas.len() * DELTA_SIZE);
+
+    buf.extend_from_slice(&MAGIC);
+    buf.write_u8(VERSION)?;
+    buf.write_u16::<LittleEndian>(symbols.len() as u16)?;
+    buf.write_u32::<LittleEndian>(deltas.len() as u32)?;
+
+    for symbol in &symbols {
+        buf.write_u8(symbol.len() as u8)?;
+        buf.extend_from_slice(symbol.as_bytes());
+    }
+
+    for delta in deltas {
+        buf.write_u16::<LittleEndian>(symbol_indexes[delta.symbol.as_str()])?;
+        buf.write_f32::<LittleEndian>(delta.price)?;
+        buf.write_f32::<LittleEndian>(delta.size)?;
+        buf.write_u32::<LittleEndian>(delta.seq)?;
+        buf.write_u8(delta.event)?;
+        buf.write_f64::<LittleEndian>(delta.ts)?;
+    }
+
+    Ok(buf)
+}
+
+/// Decodes deltas encoded with [`encode_binary`]. Fails on payloads written by any other version of the format.
+pub fn decode_binary(payload: &[u8]) -> Result<Vec<Delta>, Error> {
+    if !payload.starts_with(&MAGIC) {
+        return Err(Error::new(ErrorKind::InvalidData, "Payload is not in the binary wire format"))
+    }
+
+    let mut rdr = Cursor::new(&payload[MAGIC.len()..]);
+
+    let version = rdr.read_u8()?;
+    if version != VERSION {
+        return Err(Error::new(ErrorKind::InvalidData, format!("Unsupported wire format version {}", version)))
+    }
+
+    let symbol_count = rdr.read_u16::<LittleEndian>()?;
+    let delta_count = rdr.read_u32::<LittleEndian>()?;
+
+    let mut symbols = Vec::with_capacity(symbol_count as usize);
+    for _ in 0..symbol_count {
+        let mut symbol = vec![0; rdr.read_u8()? as usize];
+        rdr.read_exact(&mut symbol)?;
+
+        symbols.push(String::from_utf8(symbol)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?);
+    }
+
+    // Make sure the payload is as long as it claims to be before allocating anything based on its header
+    let remaining = payload.len() - MAGIC.len() - rdr.position() as usize;
+    if remaining != delta_count as usize * DELTA_SIZE {
+        return Err(Error::new(ErrorKind::InvalidData, "Binary payload length doesn't match its header"))
+    }
+
+    let mut deltas = Vec::with_capacity(delta_count as usize);
+    for _ in 0..delta_count {
+        let symbol = symbols.get(rdr.read_u16::<LittleEndian>()? as usize)
+            .ok_or(Error::new(ErrorKind::InvalidData, "Symbol index out of range"))?
+            .clone();
+
+        deltas.push(Delta {
+            symbol,
+            price: rdr.read_f32::<LittleEndian>()?,
+            size: rdr.read_f32::<LittleEndian>()?,
+            seq: rdr.read_u32::<LittleEndian>()?,
+            event: rdr.read_u8()?,
+            ts: rdr.read_f64::<LittleEndian>()?,
+        });
+    }
+
+    Ok(deltas)
+}
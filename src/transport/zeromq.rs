@@ -0,0 +1,99 @@
+use std::io::{Error, ErrorKind};
+
+use zmq;
+
+use transport::{Drain, Publisher, Written, POLL_TIMEOUT};
+
+/// Publishes messages on a ZeroMQ PUB socket. Every message is sent as two frames: the channel
+/// name, followed by the payload. Publishers connect to the endpoint the listener is bound to,
+/// which lets any number of exchanges publish to a single listener. Publishers that others subscribe to
+/// instead, such as the arbitrage monitor, bind to an endpoint of their own.
+pub struct ZmqPublisher {
+    /// Kept alive for as long as the socket is
+    _context: zmq::Context,
+    /// PUB socket
+    socket: zmq::Socket,
+}
+
+impl ZmqPublisher {
+    /// Creates a PUB socket and connects it to `endpoint`
+    pub fn new(endpoint: &str) -> Result<ZmqPublisher, Error> {
+        ZmqPublisher::open(endpoint, false)
+    }
+
+    /// Creates a PUB socket bound to `endpoint`, for any number of SUB sockets to connect to
+    pub fn bind(endpoint: &str) -> Result<ZmqPublisher, Error> {
+        ZmqPublisher::open(endpoint, true)
+    }
+
+    /// Creates a PUB socket, and either binds or connects it to `endpoint`
+    fn open(endpoint: &str, bind: bool) -> Result<ZmqPublisher, Error> {
+        let context = zmq::Context::new();
+        let socket = context.socket(zmq::PUB).map_err(to_io_error)?;
+
+        if bind {
+            socket.bind(endpoint).map_err(to_io_error)?;
+        } else {
+            socket.connect(endpoint).map_err(to_io_error)?;
+        }
+
+        Ok(ZmqPublisher {
+            _context: context,
+            socket,
+        })
+    }
+}
+
+impl Publisher for ZmqPublisher {
+    fn publish(&mut self, channel: &str, payload: &[u8]) -> Result<(), Error> {
+        self.socket.send_multipart(&[channel.as_bytes(), payload], 0)
+            .map_err(to_io_error)
+    }
+}
+
+/// Binds a SUB socket to `endpoint`, subscribes to `channels`, and hands every message published on one of them to
+/// `on_message` until `drain` is done
+pub fn listen<F>(endpoint: &str, channels: &[String], mut drain: Drain, mut on_message: F) -> Result<(), Error>
+    where F: FnMut(&str, &[u8]) -> Written {
+
+    let context = zmq::Context::new();
+    let socket = context.socket(zmq::SUB).map_err(to_io_error)?;
+
+    socket.bind(endpoint).map_err(to_io_error)?;
+    // Wake up every now and then to check whether we've been asked to stop
+    socket.set_rcvtimeo((POLL_TIMEOUT.as_secs() * 1000) as i32).map_err(to_io_error)?;
+
+    for channel in channels {
+        socket.set_subscribe(channel.as_bytes()).map_err(to_io_error)?;
+    }
+
+    loop {
+        let idle = match socket.recv_multipart(0) {
+            Ok(frames) => {
+                if frames.len() == 2 {
+                    let channel = String::from_utf8_lossy(&frames[0]);
+
+                    // Subscriptions match by prefix, so "gdax" also lets "gdax_l2" through
+                    if channels.iter().any(|c| *c == channel) {
+                        // Nothing can be delivered again, so there's no point in waiting on the write
+                        let _ = on_message(&channel, &frames[1]);
+                    }
+                } else {
+                    println!("Log Error: Expected 2 frames from ZeroMQ, got {}", frames.len());
+                }
+                false
+            },
+            Err(zmq::Error::EAGAIN) => true,
+            Err(e) => return Err(to_io_error(e)),
+        };
+
+        if drain.is_done(idle) {
+            return Ok(());
+        }
+    }
+}
+
+/// ZeroMQ errors are converted to IO errors, as that's what the transport methods return
+fn to_io_error(e: zmq::Error) -> Error {
+    Error::new(ErrorKind::Other, e.to_string())
+}
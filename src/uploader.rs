@@ -1,126 +1,681 @@
 use std::collections::HashMap;
 use std::env;
-use std::fs::{read_dir, remove_file, File};
-use std::io::{Error, ErrorKind, Read, Write};
+use std::fs::{create_dir_all, read_dir, remove_dir_all, remove_file, rename, File};
+use std::io::{self, BufReader, Cursor, Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
 
-use rusoto_core;
-use rusoto_s3;
-use rusoto_s3::{S3, S3Client};
+use chrono::prelude::*;
+use serde_json;
 use tar;
-use xz2::read::XzEncoder;
+use xz2::read::XzDecoder;
+use xz2::stream::{Check, MtStreamBuilder, Stream};
+use xz2::write::XzEncoder;
+use zstd;
+
+use catalog::{Catalog, CatalogEntry};
+use config::UploadConfig;
+use encryption::{DecryptingReader, EncryptingWriter, EncryptionKey, ENCRYPTED_MAGIC};
+use orderbook::{dtf, tectonic};
+use storage::{self, s3, Storage};
+use storage::s3::S3Storage;
+
+/// First bytes of every xz stream
+const XZ_MAGIC: [u8; 6] = [0xFD, 0x37, 0x7A, 0x58, 0x5A, 0x00];
+/// First bytes of every zstd frame
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+/// Size of the chunks DTF files are split into when training a zstd dictionary
+const DICTIONARY_SAMPLE_SIZE: usize = 16 * 1024;
+/// Version of the collector, recorded in the metadata of every object we upload
+const COLLECTOR_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Extension of the file the metadata of an archive is kept in until it's uploaded (see [`Archive::metadata_path`])
+const METADATA_EXTENSION: &str = ".json";
+
+/// Compression used for the database archives. The codec is detected from the archive itself when
+/// decompressing, and recorded in the object metadata when uploading (see [`Codec::metadata`]).
+#[derive(Clone, Debug, PartialEq)]
+pub enum Codec {
+    /// xz (LZMA2). Best ratio, but slow at high levels
+    Xz {
+        /// Preset, from 0 to 9
+        level: u32,
+        /// Amount of threads to compress with. Anything above 1 uses the multithreaded encoder
+        threads: u32,
+    },
+    /// Zstandard. Much faster than xz for a similar ratio
+    Zstd {
+        /// Level, from 1 to 22
+        level: i32,
+        /// Dictionary trained with [`train_dictionary`]. Archives compressed with a dictionary
+        /// can only be decompressed with that same dictionary
+        dictionary: Option<String>,
+    },
+}
+
+impl Default for Codec {
+    fn default() -> Codec {
+        Codec::Xz {
+            level: 9,
+            threads: 1,
+        }
+    }
+}
+
+impl Codec {
+    /// File extension of archives compressed with this codec
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Codec::Xz { .. } => ".tar.xz",
+            Codec::Zstd { .. } => ".tar.zst",
+        }
+    }
+
+    /// Describes the codec, so that downstream tooling knows how to decode the archive
+    pub fn metadata(&self) -> HashMap<String, String> {
+        let mut metadata = HashMap::new();
+
+        match self {
+            Codec::Xz { level, .. } => {
+                metadata.insert("codec".into(), "xz".into());
+                metadata.insert("level".into(), level.to_string());
+            },
+            Codec::Zstd { level, dictionary } => {
+                metadata.insert("codec".into(), "zstd".into());
+                metadata.insert("level".into(), level.to_string());
+
+                if let Some(dictionary) = dictionary {
+                    let name = Path::new(dictionary).file_name().unwrap_or_default().to_string_lossy();
+                    metadata.insert("zstd-dictionary".into(), name.into_owned());
+                }
+            },
+        }
+
+        metadata
+    }
+}
+
+/// Describes the data in an archive (or any other file we upload). Used to build the object's key
+/// and recorded in its metadata, so that data can be found in the bucket without downloading it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArchiveInfo {
+    /// Exchange the data was collected from
+    pub exchange: String,
+    /// Pair symbol, as named by the exchange
+    pub symbol: String,
+    /// Timestamp of the earliest delta, in seconds
+    pub start: f64,
+    /// Timestamp of the latest delta, in seconds
+    pub end: f64,
+    /// Amount of deltas
+    pub rows: u64,
+}
+
+impl ArchiveInfo {
+    /// Starts describing an empty file of `exchange`/`symbol` data. Add deltas to it with [`ArchiveInfo::include`]
+    pub fn new(exchange: &str, symbol: &str, ts: f64) -> ArchiveInfo {
+        ArchiveInfo {
+            exchange: exchange.into(),
+            symbol: symbol.into(),
+            start: ts,
+            end: ts,
+            rows: 0,
+        }
+    }
+
+    /// Reads the header of the DTF file at `path`. The updates themselves aren't decoded,
+    /// as TectonicDB keeps their count and time range in the header.
+    pub fn from_dtf(path: &str) -> Result<ArchiveInfo, Error> {
+        let meta = dtf::read_meta(path)?;
+        let (exchange, symbol) = tectonic::split_db_name(&meta.symbol);
+
+        Ok(ArchiveInfo {
+            exchange,
+            symbol,
+            // DTF timestamps are in milliseconds
+            start: meta.min_ts as f64 * 0.001,
+            end: meta.max_ts as f64 * 0.001,
+            rows: meta.nums,
+        })
+    }
+
+    /// Accounts for one more delta, which happened at `ts`
+    pub fn include(&mut self, ts: f64) {
+        self.start = self.start.min(ts);
+        self.end = self.end.max(ts);
+        self.rows += 1;
+    }
+
+    /// Key the file is uploaded under: `exchange/symbol/yyyy/mm/dd/file_name`, dated by the earliest delta
+    pub fn key(&self, file_name: &str) -> String {
+        format!("{}/{}/{}/{}", self.exchange, self.symbol, to_utc(self.start).format("%Y/%m/%d"), file_name)
+    }
+
+    /// Object metadata: exchange, symbol, time range (RFC 3339), row count, and the collector's version
+    pub fn metadata(&self) -> HashMap<String, String> {
+        let mut metadata = HashMap::new();
+
+        metadata.insert("exchange".into(), self.exchange.clone());
+        metadata.insert("symbol".into(), self.symbol.clone());
+        metadata.insert("start".into(), to_utc(self.start).to_rfc3339_opts(SecondsFormat::Millis, true));
+        metadata.insert("end".into(), to_utc(self.end).to_rfc3339_opts(SecondsFormat::Millis, true));
+        metadata.insert("rows".into(), self.rows.to_string());
+        metadata.insert("collector-version".into(), COLLECTOR_VERSION.into());
+
+        metadata
+    }
+}
+
+/// Archive written by [`archive_databases`]
+#[derive(Clone, Debug)]
+pub struct Archive {
+    /// Location of the archive on disk
+    pub path: String,
+    /// Key to upload the archive under
+    pub key: String,
+    /// Object metadata, describing both the data and the codec
+    pub metadata: HashMap<String, String>,
+}
+
+impl Archive {
+    /// Loads an archive `archive_dir` holds, along with its metadata. The key is the archive's path relative
+    /// to `archive_dir`. This is how uploads that failed are resumed.
+    pub fn load(path: &str, archive_dir: &str) -> Result<Archive, Error> {
+        let key = Path::new(path)
+            .strip_prefix(archive_dir)
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, format!("{} isn't in the archive directory {}", path, archive_dir)))?
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect::<Vec<String>>()
+            .join("/");
+
+        let metadata_path = Archive::metadata_path(path);
+        let metadata = File::open(&metadata_path)
+            .and_then(|file| serde_json::from_reader(BufReader::new(file)).map_err(|e| Error::new(ErrorKind::InvalidData, e)))
+            .map_err(|e| Error::new(e.kind(), format!("Failed to read the metadata of {} from {}: {}", path, metadata_path, e)))?;
+
+        Ok(Archive {
+            path: path.into(),
+            key,
+            metadata,
+        })
+    }
+
+    /// File the metadata of the archive at `path` is kept in, next to the archive, until it's uploaded
+    pub fn metadata_path(path: &str) -> String {
+        format!("{}{}", path, METADATA_EXTENSION)
+    }
+
+    /// Writes the metadata next to the archive, so that the upload can be resumed by a later run
+    fn save_metadata(&self) -> Result<(), Error> {
+        let file = File::create(Archive::metadata_path(&self.path))?;
+
+        serde_json::to_writer(file, &self.metadata)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+}
+
+/// Converts a delta timestamp (in seconds) to a date
+fn to_utc(ts: f64) -> DateTime<Utc> {
+    Utc.timestamp(ts.floor() as i64, (ts.fract() * 1e9) as u32)
+}
+
+/// Write half of a codec. Has to be finished for the archive to be complete
+enum ArchiveEncoder<W: Write> {
+    /// xz encoder
+    Xz(XzEncoder<W>),
+    /// zstd encoder
+    Zstd(zstd::Encoder<W>),
+}
+
+impl<W: Write> ArchiveEncoder<W> {
+    /// Creates an encoder writing to `writer`
+    fn new(writer: W, codec: &Codec) -> Result<ArchiveEncoder<W>, Error> {
+        match codec {
+            Codec::Xz { level, threads } => {
+                let stream = if *threads > 1 {
+                    MtStreamBuilder::new()
+                        .preset(*level)
+                        .threads(*threads)
+                        .encoder()
+                } else {
+                    Stream::new_easy_encoder(*level, Check::Crc64)
+                }.map_err(|e| Error::new(ErrorKind::Other, e))?;
+
+                Ok(ArchiveEncoder::Xz(XzEncoder::new_stream(writer, stream)))
+            },
+            Codec::Zstd { level, dictionary } => {
+                let dictionary = match dictionary {
+                    Some(path) => read_file(path)?,
+                    None => vec![],
+                };
+
+                Ok(ArchiveEncoder::Zstd(zstd::Encoder::with_dictionary(writer, *level, &dictionary)?))
+            },
+        }
+    }
+
+    /// Finishes the stream, returning the inner writer
+    fn finish(self) -> Result<W, Error> {
+        match self {
+            ArchiveEncoder::Xz(encoder) => encoder.finish(),
+            ArchiveEncoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for ArchiveEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        match self {
+            ArchiveEncoder::Xz(encoder) => encoder.write(buf),
+            ArchiveEncoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match self {
+            ArchiveEncoder::Xz(encoder) => encoder.flush(),
+            ArchiveEncoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Reads a whole file into memory
+fn read_file(path: &str) -> Result<Vec<u8>, Error> {
+    let mut buf = vec![];
+    File::open(path)?.read_to_end(&mut buf)?;
+
+    Ok(buf)
+}
+
+/// Opens an archive for reading, detecting its codec (and whether it's encrypted) from the first bytes of the file.
+/// `dictionary` is required for zstd archives compressed with one, and `key` for encrypted archives.
+pub fn open_archive(archive: &str, dictionary: Option<&String>, key: Option<&EncryptionKey>) -> Result<Box<Read>, Error> {
+    let (magic, reader) = peek_magic(Box::new(File::open(archive)?))?;
+
+    let (magic, reader) = if magic.starts_with(&ENCRYPTED_MAGIC) {
+        let key = key.ok_or(Error::new(ErrorKind::InvalidInput,
+            format!("{} is encrypted. Set upload.encryption_key or ARCHIVE_ENCRYPTION_KEY to decrypt it", archive)))?;
+
+        peek_magic(Box::new(DecryptingReader::new(reader, key)?))?
+    } else {
+        (magic, reader)
+    };
+
+    if magic.starts_with(&XZ_MAGIC) {
+        Ok(Box::new(XzDecoder::new(reader)))
+    } else if magic.starts_with(&ZSTD_MAGIC) {
+        let dictionary = match dictionary {
+            Some(path) => read_file(path)?,
+            None => vec![],
+        };
+
+        Ok(Box::new(zstd::Decoder::with_dictionary(BufReader::new(reader), &dictionary)?))
+    } else {
+        Err(Error::new(ErrorKind::InvalidData, format!("{} is neither an xz nor a zstd archive", archive)))
+    }
+}
+
+/// Reads the first bytes of `reader`, long enough to hold any of the magic numbers we look for.
+/// Returns them along with a reader that still starts at the beginning.
+fn peek_magic(mut reader: Box<Read>) -> Result<(Vec<u8>, Box<Read>), Error> {
+    let mut magic = vec![];
+    (&mut reader).take(ENCRYPTED_MAGIC.len() as u64).read_to_end(&mut magic)?;
+
+    Ok((magic.clone(), Box::new(Cursor::new(magic).chain(reader))))
+}
 
 /// Compresses the DTF database, with the path loaded from environment variable `DTF_DB_PATH`
 /// Optionally, a path can be supplied to the function as an Optional parameter.
 ///
-/// Database is first stored as a tar file, and is then xz compressed at the highest level possible.
-/// The `tar.xz` file will then be ready to be moved into Amazon S3 for long term storage
+/// Every file in the database ends up in the same archive. Use [`archive_databases`] to get one archive per database.
+/// See [`compress_files_and_delete`] for how the archive is written.
 ///
 /// We require the `db_name` parameter because it's easier to maintain and encode information about
 /// the file externally than it is to figure it out inside the function.
 ///
 /// # Parameters
-/// `db_name`: Filename the final tar.xz archive will have. Must be a unique name to avoid
-///     conflicts in the cloud. A good idea is to use the date and time, or a uuid.
+/// `db_name`: Filename the final archive will have. Must be a unique name to avoid
+///     conflicts in the cloud. A good idea is to use the date and time, or a uuid,
+///     followed by [`Codec::extension`].
 /// `db_path`: Path to location of the DTF database (on disk)
-pub fn compress_database_and_delete(db_name: &String, db_path: Option<String>) -> Result<(), Error> {
+/// `codec`: Compression used for the archive
+pub fn compress_database_and_delete(db_name: &String, db_path: Option<String>, codec: &Codec) -> Result<(), Error> {
     // Define the database path location. We will try and match against environment variables before
     // falling back into a hardcoded default path. TODO: avoid using hardcoded path
     let db_path = db_path.unwrap_or(
         env::var("DTF_DB_PATH").unwrap_or(env::var("HOME").unwrap() + "/tectonicdb/target/release/db"));
 
-    let db_tar = File::create(db_name)?;
-    let mut db_tar_builder = tar::Builder::new(&db_tar);
+    compress_files_and_delete(db_name, &list_files(&db_path)?, codec, None)
+}
 
-    // Add all files inside the dtf database folder and name the folder "db"
-    db_tar_builder.append_dir_all("db", &db_path)?;
-    // Create and write the tar archive
-    db_tar_builder.into_inner()?;
-    // Drop tar file for later writing as an xz archive
-    drop(db_tar);
+/// Compresses every DTF file in `db_path` into an archive of its own, stored under `archive_dir` with the same
+/// layout as the keys the archives are uploaded under (see [`ArchiveInfo::key`]). Each DTF file is deleted once
+/// its archive is in place. Returns the archives written, along with their keys and metadata.
+/// The metadata is also written next to every archive (see [`Archive::metadata_path`]) until it's uploaded.
+///
+/// Archives are named `exchange_symbol_<start>` followed by [`Codec::extension`], where `<start>` is the time
+/// of the earliest delta in the archive. With an `encryption_key`, archives are encrypted (see [`EncryptingWriter`]) and
+/// get an extra `.enc` extension.
+pub fn archive_databases(db_path: &str, archive_dir: &str, codec: &Codec, encryption_key: Option<&EncryptionKey>) -> Result<Vec<Archive>, Error> {
+    let mut archives = vec![];
 
-    // Create XzEncoder instance with new file open to avoid 'Bad file descriptor' error.
-    let mut xz_enc = XzEncoder::new(File::open(db_name)?, 9);
-    let mut xz_buf = vec![];
+    for dtf_file in list_files(db_path)? {
+        let info = ArchiveInfo::from_dtf(&dtf_file.to_string_lossy())?;
 
-    // Read compressed xz bytes to a buffer
-    xz_enc.read_to_end(&mut xz_buf)?;
+        // Nothing worth keeping. TectonicDB will write to the same file again
+        if info.rows == 0 {
+            continue;
+        }
 
-    // Reopen file. It doesn't matter if the data gets truncated, given we've
-    // already read the contents of the tar file into a buffer.
-    let mut db_tar = File::create(db_name)?;
-    // Finally, write compressed xz bytes to a file
-    db_tar.write_all(&mut xz_buf)?;
+        let file_name = format!("{}_{}_{}{}{}",
+            info.exchange,
+            info.symbol,
+            to_utc(info.start).format("%Y%m%dT%H%M%SZ"),
+            codec.extension(),
+            if encryption_key.is_some() { ".enc" } else { "" });
+        let key = info.key(&file_name);
 
-    // Delete all files in the tectonic database
-    for dtf_file in read_dir(&db_path)? {
-        let _ = remove_file(dtf_file?.path()).expect("Failed to delete DTF file");
+        let path = Path::new(archive_dir).join(&key);
+        create_dir_all(path.parent().unwrap())?;
+        let path = path.to_string_lossy().into_owned();
+
+        let mut metadata = info.metadata();
+        metadata.extend(codec.metadata());
+        if encryption_key.is_some() {
+            metadata.insert("encryption".into(), "aes-256-gcm".into());
+        }
+
+        let archive = Archive {
+            path,
+            key,
+            metadata,
+        };
+
+        // The metadata goes first, as the DTF file is gone once the archive is in place
+        archive.save_metadata()?;
+
+        if let Err(e) = compress_files_and_delete(&archive.path, &[dtf_file], codec, encryption_key) {
+            let _ = remove_file(Archive::metadata_path(&archive.path));
+            return Err(e);
+        }
+
+        archives.push(archive);
     }
 
-    Ok(())
+    Ok(archives)
 }
 
-/// Upload database (xz compressed) to the Amazon S3 bucket [`bucket`]. If [`bucket`] is `None`, then we will read the bucket
-/// from the environment variable `S3_BUCKET`. We will default to `CuteQ` if we receive a `None`
-/// value, and the environment variable is missing.
+/// Compresses `files` into the archive `db_name`, then deletes them.
 ///
-/// # Parameters
-/// `db_name`: filename of the database tar file
-/// `bucket`: S3 Bucket name we will upload to. Defaults to `cuteq`
-/// `region`: Amazon AWS Region to use. Defaults to `us-east-1`
+/// Files are streamed through tar and `codec` straight to disk, so memory usage doesn't grow with
+/// their size. The archive is written to `db_name.partial`, read back to verify it, and only
+/// then renamed to `db_name`. The files are deleted once the archive is in place; if anything fails
+/// along the way, the partial archive is removed and the files are left untouched.
+/// The archive will then be ready to be moved into Amazon S3 for long term storage
 ///
-/// Issue: Does not upload to S3.
-pub fn s3_upload(db_name: &String,
-                 metadata: Option<HashMap<String, String>>,
-                 bucket: Option<String>,
-                 region: Option<rusoto_core::Region>) -> Result<(), Error> {
-
-    let credentials = rusoto_core::credential::ChainProvider::new();
-
-    // Default to region us-east-1
-    let region = region.unwrap_or(rusoto_core::Region::UsEast1);
-
-    let s3 = S3Client::new_with(
-        rusoto_core::request::HttpClient::new().unwrap(),
-        credentials,
-        region);
-
-    // TODO: Remove hardcoded `cuteq` variable and load from Cargo.toml
-    let bucket = bucket.unwrap_or(env::var("S3_BUCKET").unwrap_or("cuteq".into()));
-
-    let mut xz_archive = File::open(db_name)?;
-    let mut dtf_buf = vec![];
-
-    xz_archive.read_to_end(&mut dtf_buf)?;
-
-    let s3_req = rusoto_s3::PutObjectRequest {
-        bucket,
-        body: Some(dtf_buf.into()),
-        key: db_name.clone(),
-        metadata,
-        // Set the storage class. We will default to infrequent access
-        // if no environment variable is set. This is to save money on long term
-        // storage, while still being able to retrieve the data at a reasonable price
-        // compared to AWS Glacier.
-        storage_class: Some(env::var("S3_STORAGE_CLASS").unwrap_or("STANDARD_IA".into())),
-
-        ..Default::default()
+/// With a `key`, the archive is encrypted as it's written, so that the data never hits the disk in the clear.
+pub fn compress_files_and_delete(db_name: &String, files: &[PathBuf], codec: &Codec, key: Option<&EncryptionKey>) -> Result<(), Error> {
+    // Written next to the final archive so that renaming it never crosses filesystems
+    let partial_name = format!("{}.partial", db_name);
+
+    let archived = match write_archive(&partial_name, files, codec, key)
+        .and_then(|archived| verify_archive(&partial_name, &archived, codec, key).map(|_| archived)) {
+
+        Ok(archived) => archived,
+        Err(e) => {
+            let _ = remove_file(&partial_name);
+            return Err(e);
+        }
     };
 
-    match s3.put_object(s3_req).sync() {
-        Ok(msg) => {
-            // TODO: implement logging
-            println!("{:?}", msg);
+    // Renaming is atomic, so `db_name` is either missing or a complete archive
+    rename(&partial_name, db_name)?;
 
-            drop(xz_archive);
-            // Delete the archive, given we have no need for it anymore
-            remove_file(db_name)?;
+    // Delete the files we've archived. Anything created while we were compressing is kept for the next archive
+    for (dtf_file, _) in archived {
+        remove_file(dtf_file)?;
+    }
 
-            return Ok(())
-        },
-        Err(e) => {
-            // TODO: implement logging
-            println!("{:?}", e);
-            return Err(Error::new(ErrorKind::Other, e))
+    Ok(())
+}
+
+/// Streams `files` through tar and `codec` into `writer`, naming them `db/<file name>`.
+/// Returns the writer once the archive is complete.
+pub fn compress_files<W: Write>(writer: W, files: &[PathBuf], codec: &Codec) -> Result<W, Error> {
+    let mut tar = tar::Builder::new(ArchiveEncoder::new(writer, codec)?);
+
+    for path in files {
+        tar.append_path_with_name(path, Path::new("db").join(path.file_name().unwrap()))?;
+    }
+
+    // Finish the tar, then the compressed stream
+    tar.into_inner()?.finish()
+}
+
+/// Archives left in `archive_dir` by uploads that failed, along with their metadata. Archives are found through
+/// their metadata files, and those still being written are left out.
+pub fn leftover_archives(archive_dir: &str) -> Result<Vec<Archive>, Error> {
+    let mut metadata_files = vec![];
+
+    if Path::new(archive_dir).is_dir() {
+        find_metadata_files(Path::new(archive_dir), &mut metadata_files)?;
+    }
+
+    metadata_files.iter()
+        .map(|metadata_path| metadata_path.to_string_lossy())
+        .map(|metadata_path| metadata_path[..metadata_path.len() - METADATA_EXTENSION.len()].to_owned())
+        .filter(|path| Path::new(path).is_file())
+        .map(|path| Archive::load(&path, archive_dir))
+        .collect()
+}
+
+/// Recursively collects every metadata file found under `dir`
+fn find_metadata_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            find_metadata_files(&path, files)?;
+        } else if path.to_string_lossy().ends_with(METADATA_EXTENSION) {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Lists the files in `db_path`, skipping folders
+fn list_files(db_path: &str) -> Result<Vec<PathBuf>, Error> {
+    let mut files = vec![];
+
+    for dtf_file in read_dir(db_path)? {
+        let path = dtf_file?.path();
+
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+
+    Ok(files)
+}
+
+/// Archives `files` into `archive`, encrypting it with `key` if given. Returns the files archived along with their sizes.
+fn write_archive(archive: &str, files: &[PathBuf], codec: &Codec, key: Option<&EncryptionKey>) -> Result<Vec<(PathBuf, u64)>, Error> {
+    let mut archived = vec![];
+
+    for path in files {
+        archived.push((path.clone(), path.metadata()?.len()));
+    }
+
+    let file = match key {
+        Some(key) => compress_files(EncryptingWriter::new(File::create(archive)?, key)?, files, codec)?.finish()?,
+        None => compress_files(File::create(archive)?, files, codec)?,
+    };
+
+    // Make sure everything made it to disk before we go on to delete the originals
+    file.sync_all()?;
+
+    Ok(archived)
+}
+
+/// Reads `archive` back in full, which checks the integrity of the compressed stream, and makes sure every
+/// file in `archived` is in it at its original size
+fn verify_archive(archive: &str, archived: &[(PathBuf, u64)], codec: &Codec, key: Option<&EncryptionKey>) -> Result<(), Error> {
+    let dictionary = match codec {
+        Codec::Zstd { dictionary, .. } => dictionary.as_ref(),
+        Codec::Xz { .. } => None,
+    };
+
+    let mut tar = tar::Archive::new(open_archive(archive, dictionary, key)?);
+    let mut sizes = HashMap::new();
+
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let size = io::copy(&mut entry, &mut io::sink())?;
+
+        sizes.insert(path, size);
+    }
+
+    for (dtf_file, size) in archived {
+        let name = Path::new("db").join(dtf_file.file_name().unwrap());
+
+        if sizes.get(&name) != Some(size) {
+            return Err(Error::new(ErrorKind::InvalidData,
+                format!("{} is missing or truncated in {}", dtf_file.display(), archive)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Unpacks an archive created by [`compress_database_and_delete`] into the folder `dest`.
+/// The DTF files will be found under `dest/db`. `dictionary` is required for zstd archives compressed with one,
+/// and `key` for encrypted archives.
+pub fn decompress_archive(archive: &String, dest: &String, dictionary: Option<&String>, key: Option<&EncryptionKey>) -> Result<(), Error> {
+    create_dir_all(dest)?;
+
+    let mut tar = tar::Archive::new(open_archive(archive, dictionary, key)?);
+    tar.unpack(dest)
+}
+
+/// Trains a zstd dictionary on the DTF files in `db_path`, and writes it to `output`.
+/// Files are split into chunks, which are used as the training samples. `max_size` is the size of the dictionary in bytes.
+pub fn train_dictionary(db_path: &str, output: &str, max_size: usize) -> Result<(), Error> {
+    let mut samples = vec![];
+
+    for dtf_file in read_dir(db_path)? {
+        let path = dtf_file?.path();
+
+        if path.is_file() {
+            let contents = read_file(&path.to_string_lossy())?;
+            samples.extend(contents.chunks(DICTIONARY_SAMPLE_SIZE).map(|chunk| chunk.to_vec()));
+        }
+    }
+
+    if samples.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("No files to train on in {}", db_path)));
+    }
+
+    let dictionary = zstd::dict::from_samples(&samples, max_size)?;
+
+    File::create(output)?.write_all(&dictionary)
+}
+
+/// Stores the file at `path` under `key` in every configured backend (see [`config::UploadConfig::backends`]),
+/// recording each copy in the catalog. A failed backend doesn't stop the others from being attempted. The file is
+/// only deleted once every backend has it, so that the upload can be retried; the first error is returned otherwise.
+///
+/// Once everything is stored, every backend's retention policy is applied.
+pub fn upload_archive(path: &str, key: &str, metadata: &HashMap<String, String>, upload: &UploadConfig) -> Result<(), Error> {
+    let backends = upload.backends()?;
+    let catalog = Catalog::open(&upload.catalog)?;
+    let mut result = Ok(());
+
+    for backend in &backends {
+        let stored = backend.storage.put(path, key, metadata)
+            .and_then(|object| catalog.record(&CatalogEntry::from_object(&object)?));
+
+        if let Err(e) = stored {
+            println!("Log Error: Failed to store {} at {}: {}", path, backend.storage.location(key), e);
+            result = result.and(Err(e));
         }
     }
+
+    result?;
+
+    // Delete the archive, given we have no need for it anymore. Files that aren't archives have no metadata file
+    remove_file(path)?;
+    let _ = remove_file(Archive::metadata_path(path));
+
+    for backend in &backends {
+        if let Err(e) = storage::apply_retention(backend, &catalog) {
+            println!("Log Error: Failed to apply the retention policy: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolves the location of an archive to a file on disk. `s3://bucket/key` locations (as stored in the [`catalog`])
+/// are downloaded into `download_dir` from the region configured in `upload`. `file://` locations and anything else
+/// are taken to be local paths and returned as is. Returns the path of the archive, along with its MD5 if it was downloaded.
+pub fn fetch_archive(location: &str, download_dir: &str, upload: &UploadConfig) -> Result<(String, Option<String>), Error> {
+    if !location.starts_with("s3://") {
+        let path = if location.starts_with("file://") { &location["file://".len()..] } else { location };
+
+        if !Path::new(path).is_file() {
+            return Err(Error::new(ErrorKind::NotFound, format!("{} doesn't exist", location)));
+        }
+
+        return Ok((path.into(), None));
+    }
+
+    let (bucket, key) = s3::parse_location(location)?;
+
+    create_dir_all(download_dir)?;
+    let dest = Path::new(download_dir)
+        .join(Path::new(key).file_name().unwrap_or_default())
+        .to_string_lossy()
+        .into_owned();
+
+    let md5 = S3Storage::new(bucket, &upload.storage_class, upload.region()?).get(key, &dest)?;
+
+    Ok((dest, Some(md5)))
+}
+
+/// Unpacks `archive` and moves the DTF files inside it into the TectonicDB data folder `db_path`, so that the
+/// server can load them again. Existing databases are never overwritten: if one of the files is already in
+/// `db_path`, nothing is restored. Returns the paths of the restored files.
+/// `dictionary` is required for zstd archives compressed with one, and `key` for encrypted archives.
+pub fn restore_archive(archive: &str, db_path: &str, dictionary: Option<&String>, key: Option<&EncryptionKey>) -> Result<Vec<PathBuf>, Error> {
+    create_dir_all(db_path)?;
+
+    // Unpacked inside `db_path`, so that the files can be moved into place without crossing filesystems
+    let unpack_dir = Path::new(db_path)
+        .join(format!(".restore_{}", Utc::now().timestamp_nanos()))
+        .to_string_lossy()
+        .into_owned();
+
+    let restored = decompress_archive(&archive.to_owned(), &unpack_dir, dictionary, key)
+        .and_then(|_| list_files(&Path::new(&unpack_dir).join("db").to_string_lossy()))
+        .and_then(|dtf_files| {
+            let targets: Vec<PathBuf> = dtf_files.iter()
+                .map(|dtf_file| Path::new(db_path).join(dtf_file.file_name().unwrap()))
+                .collect();
+
+            if let Some(existing) = targets.iter().find(|target| target.exists()) {
+                return Err(Error::new(ErrorKind::AlreadyExists,
+                    format!("{} already exists. Move it out of the way to restore {}", existing.display(), archive)));
+            }
+
+            for (dtf_file, target) in dtf_files.iter().zip(targets.iter()) {
+                rename(dtf_file, target)?;
+            }
+
+            Ok(targets)
+        });
+
+    let _ = remove_dir_all(&unpack_dir);
+
+    restored
 }